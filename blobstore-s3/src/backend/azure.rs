@@ -0,0 +1,389 @@
+//! Azure Blob Storage backend
+//!
+
+use std::{num::NonZeroU32, sync::Arc};
+
+use async_trait::async_trait;
+use azure_core::{
+    error::ErrorKind, prelude::IfMatchCondition, request_options::Range, ClientOptions, Context,
+    Policy, PolicyResult, Request, StatusCode,
+};
+use azure_storage::StorageCredentials;
+use azure_storage_blobs::{
+    container::operations::ListBlobsResponse,
+    prelude::{
+        BlobClient, BlobContentEncoding, BlobContentType, BlobServiceClient, ClientBuilder,
+        ContainerClient,
+    },
+};
+use futures::{StreamExt, TryStreamExt};
+use tracing::{debug, error};
+use wasmbus_rpc::error::{RpcError, RpcResult};
+
+use super::{from_offset_datetime, Backend, ByteStream, DEFAULT_MAX_ITEMS};
+use crate::{
+    config::AzureConfig,
+    wasmcloud_interface_blobstore::{
+        self as blobstore, ContainerMetadata, ContainersInfo, ListObjectsRequest,
+        ListObjectsResponse, MultiResult, ObjectMetadata, PutObjectRequest, PutObjectResponse,
+    },
+};
+
+pub(crate) struct AzureBackend {
+    service: BlobServiceClient,
+}
+
+impl AzureBackend {
+    pub(crate) fn new(config: AzureConfig) -> RpcResult<Self> {
+        let builder = if config.use_emulator {
+            // azurite, with its well-known development account
+            ClientBuilder::emulator()
+        } else {
+            let (account, access_key) = match (config.account, config.access_key) {
+                (Some(account), Some(access_key)) => (account, access_key),
+                _ => {
+                    return Err(RpcError::ProviderInit(
+                        "azure backend requires account and access_key".to_string(),
+                    ))
+                }
+            };
+            ClientBuilder::new(
+                account.clone(),
+                StorageCredentials::Key(account, access_key),
+            )
+        };
+        let service = builder
+            .client_options(
+                ClientOptions::default()
+                    .per_call_policies(vec![Arc::new(ListMarkerPolicy) as Arc<dyn Policy>]),
+            )
+            .blob_service_client();
+        Ok(AzureBackend { service })
+    }
+
+    fn container(&self, bucket_id: &str) -> ContainerClient {
+        self.service.container_client(bucket_id)
+    }
+
+    fn blob(&self, bucket_id: &str, object_id: &str) -> BlobClient {
+        self.container(bucket_id).blob_client(object_id)
+    }
+
+    /// Lists one page of blobs, starting at the marker returned with a previous page
+    async fn list_page(
+        &self,
+        bucket_id: &str,
+        max_items: NonZeroU32,
+        marker: Option<&str>,
+    ) -> RpcResult<ListBlobsResponse> {
+        let mut ctx = Context::new();
+        if let Some(marker) = marker {
+            ctx.insert(ListMarker(marker.to_string()));
+        }
+        let mut pages = self
+            .container(bucket_id)
+            .list_blobs()
+            .max_results(max_items)
+            .context(ctx)
+            .into_stream();
+        match pages.next().await {
+            Some(Ok(page)) => Ok(page),
+            Some(Err(e)) => {
+                error!(error = %e, "unable to list objects");
+                Err(RpcError::Other(e.to_string()))
+            }
+            None => Err(RpcError::Other(format!(
+                "no response listing objects in container '{}'",
+                bucket_id
+            ))),
+        }
+    }
+}
+
+/// The marker of the page to list, added to the request by [ListMarkerPolicy].
+/// The sdk only sends a marker for the pages of a listing after the first, so a
+/// listing continued in a later request would otherwise start from the beginning
+#[derive(Debug)]
+struct ListMarker(String);
+
+/// Adds the [ListMarker] in the request context to the request url
+#[derive(Debug)]
+struct ListMarkerPolicy;
+
+#[async_trait]
+impl Policy for ListMarkerPolicy {
+    async fn send(
+        &self,
+        ctx: &Context,
+        request: &mut Request,
+        next: &[Arc<dyn Policy>],
+    ) -> PolicyResult {
+        if let Some(ListMarker(marker)) = ctx.get::<ListMarker>() {
+            request
+                .url_mut()
+                .query_pairs_mut()
+                .append_pair("marker", marker);
+        }
+        next[0].send(ctx, request, &next[1..]).await
+    }
+}
+
+#[async_trait]
+impl Backend for AzureBackend {
+    async fn container_exists(&self, bucket_id: &str) -> RpcResult<bool> {
+        self.container(bucket_id).exists().await.map_err(|e| {
+            error!(error = %e, "Unable to get container");
+            RpcError::Other(e.to_string())
+        })
+    }
+
+    async fn create_container(&self, bucket_id: &str) -> RpcResult<()> {
+        self.container(bucket_id).create().await.map_err(|e| {
+            error!(error = %e, "Unable to create container");
+            RpcError::Other(e.to_string())
+        })
+    }
+
+    async fn get_container_info(&self, bucket_id: &str) -> RpcResult<ContainerMetadata> {
+        match self.container(bucket_id).get_properties().await {
+            // azure doesn't report when a container was created
+            Ok(_) => Ok(ContainerMetadata {
+                container_id: bucket_id.to_string(),
+                created_at: None,
+            }),
+            Err(e) if status(&e) == Some(StatusCode::NotFound) => {
+                Err(RpcError::Other(format!("Bucket({})not found", bucket_id)))
+            }
+            Err(e) => Err(RpcError::Other(e.to_string())),
+        }
+    }
+
+    async fn list_containers(&self) -> RpcResult<ContainersInfo> {
+        let mut pages = self.service.list_containers().into_stream();
+        let mut containers = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| {
+                error!(error = %e, "Unable to list containers");
+                RpcError::Other(e.to_string())
+            })?;
+            containers.extend(page.containers.into_iter().map(|c| ContainerMetadata {
+                container_id: c.name,
+                created_at: None,
+            }));
+        }
+        Ok(containers)
+    }
+
+    async fn remove_containers(&self, bucket_ids: &[&str]) -> RpcResult<MultiResult> {
+        let mut results = Vec::with_capacity(bucket_ids.len());
+        for bucket in bucket_ids.iter() {
+            match self.container(bucket).delete().await {
+                Ok(_) => {}
+                Err(e) if status(&e).is_some() => {
+                    results.push(blobstore::ItemResult {
+                        key: bucket.to_string(),
+                        error: Some(e.to_string()),
+                        success: false,
+                    });
+                }
+                Err(e) => {
+                    error!(error = %e, "unexpected error");
+                    return Err(RpcError::Other(format!("unexpected error: {}", e)));
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    async fn object_exists(&self, bucket_id: &str, object_id: &str) -> RpcResult<bool> {
+        self.blob(bucket_id, object_id).exists().await.map_err(|e| {
+            error!(
+                error = %e,
+                "unexpected error for object_exists"
+            );
+            RpcError::Other(e.to_string())
+        })
+    }
+
+    async fn get_object_info(&self, bucket_id: &str, object_id: &str) -> RpcResult<ObjectMetadata> {
+        match self.blob(bucket_id, object_id).get_properties().await {
+            Ok(props) => {
+                let blob = props.blob;
+                Ok(ObjectMetadata {
+                    container_id: bucket_id.to_string(),
+                    object_id: object_id.to_string(),
+                    content_length: blob.properties.content_length,
+                    last_modified: from_offset_datetime(blob.properties.last_modified),
+                    content_type: Some(blob.properties.content_type),
+                    content_encoding: blob.properties.content_encoding,
+                    etag: Some(blob.properties.etag.to_string()),
+                })
+            }
+            Err(e) if status(&e) == Some(StatusCode::NotFound) => {
+                Err(super::object_not_found(bucket_id, object_id))
+            }
+            Err(e) => Err(RpcError::Other(format!(
+                "get_object_metadata for Bucket({}) Object({}): {}",
+                bucket_id, object_id, e
+            ))),
+        }
+    }
+
+    async fn list_objects(
+        &self,
+        bucket_id: &str,
+        arg: &ListObjectsRequest,
+    ) -> RpcResult<ListObjectsResponse> {
+        let max_items = NonZeroU32::new(arg.max_items.unwrap_or(DEFAULT_MAX_ITEMS))
+            .ok_or_else(|| RpcError::InvalidParameter("max_items must be > 0".to_string()))?;
+        let mut page = self
+            .list_page(bucket_id, max_items, arg.continuation.as_deref())
+            .await?;
+        // azure can't start a listing after a name, so with start_with, the pages without
+        // names after it are skipped, and the names up to it are left out of the first page
+        let start_after = arg
+            .start_with
+            .as_deref()
+            .filter(|_| arg.continuation.is_none());
+        if let Some(start_after) = start_after {
+            while !page
+                .blobs
+                .blobs()
+                .any(|blob| blob.name.as_str() > start_after)
+            {
+                match page.next_marker.take() {
+                    Some(marker) => {
+                        page = self
+                            .list_page(bucket_id, max_items, Some(marker.as_str()))
+                            .await?
+                    }
+                    None => break,
+                }
+            }
+        }
+        let objects: Vec<ObjectMetadata> = page
+            .blobs
+            .blobs()
+            .filter(|blob| !matches!(start_after, Some(start) if blob.name.as_str() <= start))
+            .map(|blob| ObjectMetadata {
+                container_id: bucket_id.to_string(),
+                object_id: blob.name.clone(),
+                content_length: blob.properties.content_length,
+                last_modified: from_offset_datetime(blob.properties.last_modified),
+                content_type: Some(blob.properties.content_type.clone()),
+                content_encoding: blob.properties.content_encoding.clone(),
+                etag: Some(blob.properties.etag.to_string()),
+            })
+            .collect();
+        debug!(
+            "list_objects (bucket:{}) returned {} items",
+            bucket_id,
+            objects.len()
+        );
+        let continuation = page.next_marker.map(|m| m.as_str().to_string());
+        Ok(ListObjectsResponse {
+            is_last: continuation.is_none(),
+            continuation,
+            objects,
+        })
+    }
+
+    async fn remove_objects(
+        &self,
+        bucket_id: &str,
+        object_ids: &[String],
+    ) -> RpcResult<MultiResult> {
+        let mut results = Vec::new();
+        for object_id in object_ids.iter() {
+            match self.blob(bucket_id, object_id).delete().await {
+                // as with s3, removing an object that doesn't exist succeeds
+                Ok(_) => {}
+                Err(e) if status(&e) == Some(StatusCode::NotFound) => {}
+                Err(e) => results.push(blobstore::ItemResult {
+                    key: object_id.clone(),
+                    error: Some(e.to_string()),
+                    success: false,
+                }),
+            }
+        }
+        Ok(results)
+    }
+
+    async fn put_object(
+        &self,
+        bucket_id: &str,
+        arg: &PutObjectRequest,
+    ) -> RpcResult<PutObjectResponse> {
+        super::reject_object_lock(arg)?;
+        let object_id = &arg.chunk.object_id;
+        let mut req = self
+            .blob(bucket_id, object_id)
+            .put_block_blob(arg.chunk.bytes.to_owned());
+        if let Some(content_type) = &arg.content_type {
+            req = req.content_type(BlobContentType::from(content_type.clone()));
+        }
+        if let Some(content_encoding) = &arg.content_encoding {
+            req = req.content_encoding(BlobContentEncoding::from(content_encoding.clone()));
+        }
+        if super::if_none_match(arg)? {
+            req = req.if_match(IfMatchCondition::NotMatch("*".to_string()));
+        } else if let Some(etag) = &arg.if_match {
+            req = req.if_match(IfMatchCondition::Match(etag.clone()));
+        }
+        match req.await {
+            Ok(resp) => Ok(PutObjectResponse {
+                etag: Some(resp.etag.to_string()),
+                ..Default::default()
+            }),
+            // 409 is returned when the blob is created concurrently with an if-none-match put
+            Err(e)
+                if matches!(
+                    status(&e),
+                    Some(StatusCode::PreconditionFailed) | Some(StatusCode::Conflict)
+                ) =>
+            {
+                debug!(error = %e, "put_object precondition failed");
+                Err(super::precondition_failed(bucket_id, object_id))
+            }
+            Err(e) => {
+                error!(
+                    error = %e,
+                    "Error putting object",
+                );
+                Err(RpcError::Other(e.to_string()))
+            }
+        }
+    }
+
+    async fn get_object(
+        &self,
+        bucket_id: &str,
+        object_id: &str,
+        range: Option<(u64, u64)>,
+    ) -> RpcResult<ByteStream> {
+        let mut req = self.blob(bucket_id, object_id).get();
+        if let Some((start, end)) = range {
+            // azure ranges exclude the end offset
+            req = req.range(Range::new(start, end + 1));
+        }
+        // the sdk requests the blob in pages, and each page body is returned as one item
+        let body = req
+            .into_stream()
+            .map_err(|e| RpcError::Other(e.to_string()))
+            .and_then(|resp| async move {
+                resp.data
+                    .collect()
+                    .await
+                    .map_err(|e| RpcError::Other(e.to_string()))
+            });
+        Ok(Box::pin(body))
+    }
+}
+
+/// returns the http status code of an error response from the service
+fn status(e: &azure_core::Error) -> Option<StatusCode> {
+    match e.kind() {
+        ErrorKind::HttpResponse { status, .. } => Some(*status),
+        _ => None,
+    }
+}
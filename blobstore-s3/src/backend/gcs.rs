@@ -0,0 +1,450 @@
+//! Google Cloud Storage backend, using the GCS json api
+//!
+
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use google_cloud_storage::{
+    client::{Client, ClientConfig},
+    http::{
+        buckets::{
+            delete::DeleteBucketRequest,
+            get::GetBucketRequest,
+            insert::{InsertBucketParam, InsertBucketRequest},
+            list::ListBucketsRequest,
+        },
+        objects::{
+            delete::DeleteObjectRequest,
+            download::Range,
+            get::GetObjectRequest,
+            list::ListObjectsRequest as GcsListObjectsRequest,
+            upload::{Media, UploadObjectRequest, UploadType},
+            Object,
+        },
+        Error as GcsError,
+    },
+};
+use tracing::{debug, error};
+use wasmbus_rpc::error::{RpcError, RpcResult};
+
+use super::{from_offset_datetime, Backend, ByteStream, DEFAULT_MAX_ITEMS};
+use crate::{
+    config::GcsConfig,
+    wasmcloud_interface_blobstore::{
+        self as blobstore, ContainerMetadata, ContainersInfo, ListObjectsRequest,
+        ListObjectsResponse, MultiResult, ObjectMetadata, PutObjectRequest, PutObjectResponse,
+    },
+};
+
+pub(crate) struct GcsBackend {
+    client: Client,
+    /// project used for listing and creating buckets
+    project_id: Option<String>,
+}
+
+impl GcsBackend {
+    pub(crate) async fn new(config: GcsConfig) -> RpcResult<Self> {
+        let client_config = match (&config.endpoint, &config.credentials_path) {
+            // emulators such as fake-gcs-server don't check credentials,
+            // and the default config sends requests without them
+            (Some(endpoint), _) => {
+                let endpoint = endpoint.trim_end_matches('/');
+                ClientConfig {
+                    storage_endpoint: if endpoint.starts_with("http") {
+                        endpoint.to_string()
+                    } else {
+                        format!("http://{}", endpoint)
+                    },
+                    ..Default::default()
+                }
+            }
+            (None, Some(path)) => {
+                let credentials =
+                    google_cloud_storage::client::google_cloud_auth::credentials::CredentialsFile::new_from_file(path.clone())
+                        .await
+                        .map_err(|e| {
+                            RpcError::ProviderInit(format!(
+                                "reading gcs credentials '{}': {}",
+                                path, e
+                            ))
+                        })?;
+                ClientConfig::default()
+                    .with_credentials(credentials)
+                    .await
+                    .map_err(|e| RpcError::ProviderInit(format!("gcs credentials: {}", e)))?
+            }
+            // application default credentials
+            (None, None) => ClientConfig::default()
+                .with_auth()
+                .await
+                .map_err(|e| RpcError::ProviderInit(format!("gcs credentials: {}", e)))?,
+        };
+        let project_id = config
+            .project_id
+            .or_else(|| client_config.project_id.clone());
+        Ok(GcsBackend {
+            client: Client::new(client_config),
+            project_id,
+        })
+    }
+
+    fn project_id(&self) -> RpcResult<String> {
+        self.project_id.clone().ok_or_else(|| {
+            RpcError::InvalidParameter(
+                "gcs project_id is required to list or create buckets".to_string(),
+            )
+        })
+    }
+
+    async fn get_object_raw(&self, bucket_id: &str, object_id: &str) -> Result<Object, GcsError> {
+        self.client
+            .get_object(&GetObjectRequest {
+                bucket: bucket_id.to_string(),
+                object: object_id.to_string(),
+                ..Default::default()
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl Backend for GcsBackend {
+    async fn container_exists(&self, bucket_id: &str) -> RpcResult<bool> {
+        match self
+            .client
+            .get_bucket(&GetBucketRequest {
+                bucket: bucket_id.to_string(),
+                ..Default::default()
+            })
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) if status(&e) == Some(404) => Ok(false),
+            Err(e) => {
+                error!(error = %e, "Unable to get bucket");
+                Err(RpcError::Other(e.to_string()))
+            }
+        }
+    }
+
+    async fn create_container(&self, bucket_id: &str) -> RpcResult<()> {
+        match self
+            .client
+            .insert_bucket(&InsertBucketRequest {
+                name: bucket_id.to_string(),
+                param: InsertBucketParam {
+                    project: self.project_id()?,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .await
+        {
+            Ok(bucket) => {
+                debug!(location = %bucket.location, "bucket created");
+                Ok(())
+            }
+            Err(e) => {
+                error!(error = %e, "Unable to create bucket");
+                Err(RpcError::Other(e.to_string()))
+            }
+        }
+    }
+
+    async fn get_container_info(&self, bucket_id: &str) -> RpcResult<ContainerMetadata> {
+        match self
+            .client
+            .get_bucket(&GetBucketRequest {
+                bucket: bucket_id.to_string(),
+                ..Default::default()
+            })
+            .await
+        {
+            Ok(bucket) => Ok(ContainerMetadata {
+                container_id: bucket_id.to_string(),
+                created_at: bucket.time_created.and_then(from_offset_datetime),
+            }),
+            Err(e) if status(&e) == Some(404) => {
+                Err(RpcError::Other(format!("Bucket({})not found", bucket_id)))
+            }
+            Err(e) => Err(RpcError::Other(e.to_string())),
+        }
+    }
+
+    async fn list_containers(&self) -> RpcResult<ContainersInfo> {
+        let project = self.project_id()?;
+        let mut containers = Vec::new();
+        let mut page_token = None;
+        loop {
+            let resp = self
+                .client
+                .list_buckets(&ListBucketsRequest {
+                    project: project.clone(),
+                    page_token,
+                    ..Default::default()
+                })
+                .await
+                .map_err(|e| {
+                    error!(error = %e, "Unable to list buckets");
+                    RpcError::Other(e.to_string())
+                })?;
+            containers.extend(resp.items.into_iter().map(|bucket| ContainerMetadata {
+                container_id: bucket.name,
+                created_at: bucket.time_created.and_then(from_offset_datetime),
+            }));
+            page_token = resp.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+        Ok(containers)
+    }
+
+    async fn remove_containers(&self, bucket_ids: &[&str]) -> RpcResult<MultiResult> {
+        let mut results = Vec::with_capacity(bucket_ids.len());
+        for bucket in bucket_ids.iter() {
+            match self
+                .client
+                .delete_bucket(&DeleteBucketRequest {
+                    bucket: bucket.to_string(),
+                    ..Default::default()
+                })
+                .await
+            {
+                Ok(_) => {}
+                Err(GcsError::Response(e)) => {
+                    results.push(blobstore::ItemResult {
+                        key: bucket.to_string(),
+                        error: Some(e.to_string()),
+                        success: false,
+                    });
+                }
+                Err(e) => {
+                    error!(error = %e, "unexpected error");
+                    return Err(RpcError::Other(format!("unexpected error: {}", e)));
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    async fn object_exists(&self, bucket_id: &str, object_id: &str) -> RpcResult<bool> {
+        match self.get_object_raw(bucket_id, object_id).await {
+            Ok(_) => Ok(true),
+            Err(e) if status(&e) == Some(404) => Ok(false),
+            Err(e) => {
+                error!(
+                    error = %e,
+                    "unexpected error for object_exists"
+                );
+                Err(RpcError::Other(e.to_string()))
+            }
+        }
+    }
+
+    async fn get_object_info(&self, bucket_id: &str, object_id: &str) -> RpcResult<ObjectMetadata> {
+        match self.get_object_raw(bucket_id, object_id).await {
+            Ok(object) => Ok(to_metadata(bucket_id, object)),
+            Err(e) if status(&e) == Some(404) => Err(super::object_not_found(bucket_id, object_id)),
+            Err(e) => Err(RpcError::Other(format!(
+                "get_object_metadata for Bucket({}) Object({}): {}",
+                bucket_id, object_id, e
+            ))),
+        }
+    }
+
+    async fn list_objects(
+        &self,
+        bucket_id: &str,
+        arg: &ListObjectsRequest,
+    ) -> RpcResult<ListObjectsResponse> {
+        let max_items = arg.max_items.unwrap_or(DEFAULT_MAX_ITEMS);
+        if max_items > i32::MAX as u32 {
+            return Err(RpcError::InvalidParameter(
+                "max_items too large".to_string(),
+            ));
+        }
+        let mut req = GcsListObjectsRequest {
+            bucket: bucket_id.to_string(),
+            max_results: Some(max_items as i32),
+            ..Default::default()
+        };
+        // gcs starts listing at start_offset, rather than after it as s3 does with
+        // start_with, so an object named start_with is left out
+        let start_after = arg
+            .start_with
+            .as_ref()
+            .filter(|_| arg.continuation.is_none());
+        if let Some(continuation) = &arg.continuation {
+            req.page_token = Some(continuation.clone());
+        } else if let Some(start_with) = start_after {
+            req.start_offset = Some(start_with.clone());
+        }
+        match self.client.list_objects(&req).await {
+            Ok(list) => {
+                let objects: Vec<ObjectMetadata> = list
+                    .items
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|o| Some(&o.name) != start_after)
+                    .map(|o| to_metadata(bucket_id, o))
+                    .collect();
+                debug!(
+                    "list_objects (bucket:{}) returned {} items",
+                    bucket_id,
+                    objects.len()
+                );
+                Ok(ListObjectsResponse {
+                    is_last: list.next_page_token.is_none(),
+                    continuation: list.next_page_token,
+                    objects,
+                })
+            }
+            Err(e) => {
+                error!(error = %e, "unable to list objects");
+                Err(RpcError::Other(e.to_string()))
+            }
+        }
+    }
+
+    async fn remove_objects(
+        &self,
+        bucket_id: &str,
+        object_ids: &[String],
+    ) -> RpcResult<MultiResult> {
+        // gcs has no batch delete in the json api, so objects are removed one at a time
+        let mut results = Vec::new();
+        for object_id in object_ids.iter() {
+            match self
+                .client
+                .delete_object(&DeleteObjectRequest {
+                    bucket: bucket_id.to_string(),
+                    object: object_id.clone(),
+                    ..Default::default()
+                })
+                .await
+            {
+                // as with s3, removing an object that doesn't exist succeeds
+                Ok(_) => {}
+                Err(e) if status(&e) == Some(404) => {}
+                Err(e) => results.push(blobstore::ItemResult {
+                    key: object_id.clone(),
+                    error: Some(e.to_string()),
+                    success: false,
+                }),
+            }
+        }
+        Ok(results)
+    }
+
+    async fn put_object(
+        &self,
+        bucket_id: &str,
+        arg: &PutObjectRequest,
+    ) -> RpcResult<PutObjectResponse> {
+        super::reject_object_lock(arg)?;
+        let object_id = &arg.chunk.object_id;
+        // gcs preconditions use generation numbers, so a required etag is
+        // translated to the generation of the object that has that etag
+        let if_generation_match = if super::if_none_match(arg)? {
+            Some(0)
+        } else if let Some(etag) = &arg.if_match {
+            match self.get_object_raw(bucket_id, object_id).await {
+                Ok(object) if &object.etag == etag => Some(object.generation),
+                Ok(_) => return Err(super::precondition_failed(bucket_id, object_id)),
+                Err(e) if status(&e) == Some(404) => {
+                    return Err(super::precondition_failed(bucket_id, object_id))
+                }
+                Err(e) => return Err(RpcError::Other(e.to_string())),
+            }
+        } else {
+            None
+        };
+        let mut media = Media::new(object_id.clone());
+        if let Some(content_type) = &arg.content_type {
+            media.content_type = content_type.clone().into();
+        }
+        media.content_length = Some(arg.chunk.bytes.len() as u64);
+        match self
+            .client
+            .upload_object(
+                &UploadObjectRequest {
+                    bucket: bucket_id.to_string(),
+                    if_generation_match,
+                    ..Default::default()
+                },
+                arg.chunk.bytes.to_owned(),
+                &UploadType::Simple(media),
+            )
+            .await
+        {
+            Ok(object) => Ok(PutObjectResponse {
+                etag: Some(object.etag),
+                ..Default::default()
+            }),
+            Err(e) if status(&e) == Some(412) => {
+                debug!(error = %e, "put_object precondition failed");
+                Err(super::precondition_failed(bucket_id, object_id))
+            }
+            Err(e) => {
+                error!(
+                    error = %e,
+                    "Error putting object",
+                );
+                Err(RpcError::Other(e.to_string()))
+            }
+        }
+    }
+
+    async fn get_object(
+        &self,
+        bucket_id: &str,
+        object_id: &str,
+        range: Option<(u64, u64)>,
+    ) -> RpcResult<ByteStream> {
+        let range = match range {
+            Some((start, end)) => Range(Some(start), Some(end)),
+            None => Range(None, None),
+        };
+        match self
+            .client
+            .download_streamed_object(
+                &GetObjectRequest {
+                    bucket: bucket_id.to_string(),
+                    object: object_id.to_string(),
+                    ..Default::default()
+                },
+                &range,
+            )
+            .await
+        {
+            Ok(stream) => Ok(Box::pin(stream.map_err(|e| RpcError::Other(e.to_string())))),
+            Err(e) => {
+                error!(
+                    error = %e,
+                    "Error when getting object"
+                );
+                Err(RpcError::Other(e.to_string()))
+            }
+        }
+    }
+}
+
+/// returns the http status code of an error response from the service
+fn status(e: &GcsError) -> Option<u16> {
+    match e {
+        GcsError::Response(resp) => Some(resp.code),
+        _ => None,
+    }
+}
+
+fn to_metadata(bucket_id: &str, o: Object) -> ObjectMetadata {
+    ObjectMetadata {
+        container_id: bucket_id.to_string(),
+        object_id: o.name,
+        content_length: o.size as u64,
+        last_modified: o.updated.and_then(from_offset_datetime),
+        content_type: o.content_type,
+        content_encoding: o.content_encoding,
+        etag: Some(o.etag),
+    }
+}
@@ -0,0 +1,182 @@
+//! Storage services that can be used behind a blobstore link
+//!
+//! `StorageClient` handles the parts of the blobstore contract that are common to all
+//! services (bucket aliases, request validation, and streaming chunks to actors), and uses
+//! a [Backend] for the calls to the storage service itself.
+
+use std::pin::Pin;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::Stream;
+use wasmbus_rpc::error::{RpcError, RpcResult};
+
+use crate::wasmcloud_interface_blobstore::{
+    ContainerMetadata, ContainersInfo, LegalHoldRequest, ListObjectsRequest, ListObjectsResponse,
+    MultiResult, ObjectMetadata, PutObjectRequest, PutObjectResponse,
+};
+
+mod azure;
+mod gcs;
+mod s3;
+
+pub(crate) use azure::AzureBackend;
+pub(crate) use gcs::GcsBackend;
+pub(crate) use s3::S3Backend;
+
+/// number of items to return in list_objects if max_items not specified
+pub(crate) const DEFAULT_MAX_ITEMS: u32 = 1000;
+
+/// Stream of object data returned from a backend
+pub(crate) type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, RpcError>> + Send>>;
+
+/// Operations on a storage service.
+///
+/// Bucket names passed to a backend have already been unaliased, and requests have been
+/// validated by `StorageClient`.
+#[async_trait]
+pub(crate) trait Backend: Send + Sync {
+    /// Returns true if the bucket exists
+    async fn container_exists(&self, bucket_id: &str) -> RpcResult<bool>;
+
+    /// Creates the bucket
+    async fn create_container(&self, bucket_id: &str) -> RpcResult<()>;
+
+    /// Returns metadata for the bucket, or an error if it does not exist
+    async fn get_container_info(&self, bucket_id: &str) -> RpcResult<ContainerMetadata>;
+
+    /// Returns all buckets accessible with the link's credentials
+    async fn list_containers(&self) -> RpcResult<ContainersInfo>;
+
+    /// Removes buckets. Buckets that the service could not remove are returned in the result
+    async fn remove_containers(&self, bucket_ids: &[&str]) -> RpcResult<MultiResult>;
+
+    /// Returns true if the object exists
+    async fn object_exists(&self, bucket_id: &str, object_id: &str) -> RpcResult<bool>;
+
+    /// Returns metadata for the object, or an error if it does not exist
+    async fn get_object_info(&self, bucket_id: &str, object_id: &str) -> RpcResult<ObjectMetadata>;
+
+    /// Lists objects in the bucket
+    async fn list_objects(
+        &self,
+        bucket_id: &str,
+        arg: &ListObjectsRequest,
+    ) -> RpcResult<ListObjectsResponse>;
+
+    /// Removes objects. Objects that the service could not remove are returned in the result
+    async fn remove_objects(
+        &self,
+        bucket_id: &str,
+        object_ids: &[String],
+    ) -> RpcResult<MultiResult>;
+
+    /// Writes a complete object, honoring any preconditions in the request
+    async fn put_object(
+        &self,
+        bucket_id: &str,
+        arg: &PutObjectRequest,
+    ) -> RpcResult<PutObjectResponse>;
+
+    /// Reads an object's contents. `range` contains the first and last (inclusive) byte
+    /// offsets, and has been checked against the object's size. If `range` is None,
+    /// the whole object is returned.
+    async fn get_object(
+        &self,
+        bucket_id: &str,
+        object_id: &str,
+        range: Option<(u64, u64)>,
+    ) -> RpcResult<ByteStream>;
+
+    /// Places or removes a legal hold on an object
+    async fn set_legal_hold(&self, _bucket_id: &str, _arg: &LegalHoldRequest) -> RpcResult<()> {
+        Err(RpcError::NotImplemented)
+    }
+
+    /// Begins a multipart upload for the object in the request, and returns the upload id.
+    /// The request's chunk is not uploaded
+    async fn start_upload(&self, _bucket_id: &str, _arg: &PutObjectRequest) -> RpcResult<String> {
+        Err(RpcError::NotImplemented)
+    }
+
+    /// Uploads one part of a multipart upload, and returns the part's entity tag.
+    /// Part numbers start at 1
+    async fn upload_part(
+        &self,
+        _bucket_id: &str,
+        _object_id: &str,
+        _upload_id: &str,
+        _part_number: i32,
+        _bytes: Bytes,
+    ) -> RpcResult<String> {
+        Err(RpcError::NotImplemented)
+    }
+
+    /// Completes a multipart upload from its parts, and returns the object's entity tag
+    async fn complete_upload(
+        &self,
+        _bucket_id: &str,
+        _object_id: &str,
+        _upload_id: &str,
+        _parts: &[(i32, String)],
+    ) -> RpcResult<Option<String>> {
+        Err(RpcError::NotImplemented)
+    }
+
+    /// Aborts a multipart upload, discarding any parts uploaded
+    async fn abort_upload(
+        &self,
+        _bucket_id: &str,
+        _object_id: &str,
+        _upload_id: &str,
+    ) -> RpcResult<()> {
+        Err(RpcError::NotImplemented)
+    }
+}
+
+/// Returns an error if the request uses object lock settings,
+/// for backends that don't support object lock
+pub(crate) fn reject_object_lock(arg: &PutObjectRequest) -> RpcResult<()> {
+    if arg.retention_mode.is_some() || arg.retain_until.is_some() || arg.legal_hold == Some(true) {
+        return Err(RpcError::InvalidParameter(
+            "object lock is only supported by the s3 backend".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the value of the `if_none_match` precondition, which may only be "*"
+pub(crate) fn if_none_match(arg: &PutObjectRequest) -> RpcResult<bool> {
+    match arg.if_none_match.as_deref() {
+        None => Ok(false),
+        Some("*") => Ok(true),
+        Some(other) => Err(RpcError::InvalidParameter(format!(
+            "unsupported if_none_match value '{}': only '*' is supported",
+            other
+        ))),
+    }
+}
+
+/// Error returned when a conditional put was rejected by the service
+pub(crate) fn precondition_failed(bucket_id: &str, object_id: &str) -> RpcError {
+    RpcError::Other(format!(
+        "{}: Bucket({}) Object({})",
+        crate::PRECONDITION_FAILED,
+        bucket_id,
+        object_id
+    ))
+}
+
+/// Error returned when an object does not exist
+pub(crate) fn object_not_found(bucket_id: &str, object_id: &str) -> RpcError {
+    RpcError::Other(format!(
+        "Not found: Bucket({}) Object({})",
+        bucket_id, object_id,
+    ))
+}
+
+/// translate a date-time returned by the gcs or azure sdk to a Timestamp.
+/// Invalid times return None.
+pub(crate) fn from_offset_datetime(dt: time::OffsetDateTime) -> Option<wasmbus_rpc::Timestamp> {
+    wasmbus_rpc::Timestamp::new(dt.unix_timestamp(), dt.nanosecond()).ok()
+}
@@ -0,0 +1,891 @@
+//! AWS S3 backend
+//!
+//! assume role http request https://docs.aws.amazon.com/cli/latest/reference/sts/assume-role.html
+//! get session token https://docs.aws.amazon.com/cli/latest/reference/sts/get-session-token.html
+
+use async_trait::async_trait;
+use aws_sdk_s3::{
+    error::{HeadBucketError, HeadBucketErrorKind, HeadObjectError, HeadObjectErrorKind},
+    model::{
+        CompletedMultipartUpload, CompletedPart, ObjectIdentifier, ObjectLockLegalHold,
+        ObjectLockLegalHoldStatus, ObjectLockMode,
+    },
+    output::{CreateBucketOutput, HeadObjectOutput, ListBucketsOutput},
+    types::{ByteStream as S3ByteStream, SdkError},
+};
+use bytes::Bytes;
+use futures::TryStreamExt;
+use tracing::{debug, error};
+use wasmbus_rpc::error::{RpcError, RpcResult};
+
+use super::{Backend, ByteStream, DEFAULT_MAX_ITEMS};
+
+/// Number of objects whose object lock settings are checked at once by remove_objects
+const LOCK_CHECK_CONCURRENCY: usize = 16;
+use crate::{
+    config::{ObjectLockConfig, StorageConfig},
+    wasmcloud_interface_blobstore::{
+        self as blobstore, ContainerMetadata, ContainersInfo, LegalHoldRequest, ListObjectsRequest,
+        ListObjectsResponse, MultiResult, ObjectMetadata, PutObjectRequest, PutObjectResponse,
+    },
+};
+
+pub(crate) struct S3Backend {
+    s3_client: aws_sdk_s3::Client,
+    object_lock: ObjectLockConfig,
+}
+
+impl S3Backend {
+    pub(crate) async fn new(config: StorageConfig) -> Self {
+        let object_lock = config.object_lock.clone();
+        let s3_config = aws_sdk_s3::Config::from(&config.configure_aws().await);
+        S3Backend {
+            s3_client: aws_sdk_s3::Client::from_conf(s3_config),
+            object_lock,
+        }
+    }
+
+    /// Determine the object lock retention for a new object.
+    /// Values in the request take precedence over the link's default settings.
+    /// Returns None if no retention should be applied.
+    fn object_retention(
+        &self,
+        arg: &PutObjectRequest,
+    ) -> Result<Option<(ObjectLockMode, aws_sdk_s3::types::DateTime)>, RpcError> {
+        let mode = arg
+            .retention_mode
+            .as_ref()
+            .or(self.object_lock.mode.as_ref());
+        let retain_until = match (&arg.retain_until, self.object_lock.retain_days) {
+            (Some(ts), _) => Some(aws_sdk_s3::types::DateTime::from_secs_and_nanos(
+                ts.sec, ts.nsec,
+            )),
+            (None, Some(days)) => {
+                let now = wasmbus_rpc::Timestamp::now();
+                Some(aws_sdk_s3::types::DateTime::from_secs(
+                    now.sec + days as i64 * 24 * 60 * 60,
+                ))
+            }
+            (None, None) => None,
+        };
+        match (mode, retain_until) {
+            (None, None) => Ok(None),
+            (Some(mode), Some(retain_until)) => Ok(Some((to_lock_mode(mode)?, retain_until))),
+            (Some(_), None) => Err(RpcError::InvalidParameter(
+                "object lock retention mode requires a retain_until date".to_string(),
+            )),
+            (None, Some(_)) => Err(RpcError::InvalidParameter(
+                "object lock retain_until date requires a retention mode".to_string(),
+            )),
+        }
+    }
+
+    /// Returns the error for removing the object if its current version has unexpired retention
+    /// or a legal hold, or if they can't be checked. Deleting by key only adds a delete marker,
+    /// which object lock allows, so remove_objects checks this first to refuse protected objects
+    async fn lock_protection(&self, bucket_id: &str, object_id: &str) -> Option<String> {
+        let (retention, legal_hold) = futures::join!(
+            self.s3_client
+                .get_object_retention()
+                .bucket(bucket_id)
+                .key(object_id)
+                .send(),
+            self.s3_client
+                .get_object_legal_hold()
+                .bucket(bucket_id)
+                .key(object_id)
+                .send(),
+        );
+        let retain_until = match retention {
+            Ok(output) => output.retention.and_then(|r| r.retain_until_date),
+            Err(e) => return lock_lookup_error(e, object_id),
+        };
+        let legal_hold = match legal_hold {
+            Ok(output) => output
+                .legal_hold
+                .and_then(|hold| hold.status)
+                .map(|status| status == ObjectLockLegalHoldStatus::On)
+                .unwrap_or_default(),
+            Err(e) => return lock_lookup_error(e, object_id),
+        };
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        lock_protection_message(retain_until.as_ref(), legal_hold, now)
+    }
+}
+
+#[async_trait]
+impl Backend for S3Backend {
+    async fn container_exists(&self, bucket_id: &str) -> RpcResult<bool> {
+        match self.s3_client.head_bucket().bucket(bucket_id).send().await {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError {
+                err:
+                    HeadBucketError {
+                        kind: HeadBucketErrorKind::NotFound(_),
+                        ..
+                    },
+                ..
+            }) => Ok(false),
+            Err(e) => {
+                error!(error = %e, "Unable to head bucket");
+                Err(RpcError::Other(e.to_string()))
+            }
+        }
+    }
+
+    async fn create_container(&self, bucket_id: &str) -> RpcResult<()> {
+        match self
+            .s3_client
+            .create_bucket()
+            .bucket(bucket_id)
+            .set_object_lock_enabled_for_bucket(self.object_lock.enabled.then_some(true))
+            .send()
+            .await
+        {
+            Ok(CreateBucketOutput { location, .. }) => {
+                debug!(?location, "bucket created");
+                Ok(())
+            }
+            Err(SdkError::ServiceError { err, .. }) => {
+                error!(
+                    error = %err,
+                    "Got service error",
+                );
+                Err(RpcError::Other(err.to_string()))
+            }
+            Err(e) => {
+                error!(
+                    error = %e,
+                    "unexpected_error",
+                );
+                Err(RpcError::Other(e.to_string()))
+            }
+        }
+    }
+
+    async fn get_container_info(&self, bucket_id: &str) -> RpcResult<ContainerMetadata> {
+        match self.s3_client.head_bucket().bucket(bucket_id).send().await {
+            Ok(_) => Ok(ContainerMetadata {
+                container_id: bucket_id.to_string(),
+                // unfortunately, HeadBucketOut doesn't include any information
+                // so we can't fill in creation date
+                created_at: None,
+            }),
+            Err(SdkError::ServiceError {
+                err:
+                    HeadBucketError {
+                        kind: HeadBucketErrorKind::NotFound(_),
+                        ..
+                    },
+                ..
+            }) => Err(RpcError::Other(format!("Bucket({})not found", bucket_id))),
+            Err(e) => Err(RpcError::Other(e.to_string())),
+        }
+    }
+
+    async fn list_containers(&self) -> RpcResult<ContainersInfo> {
+        match self.s3_client.list_buckets().send().await {
+            Ok(ListBucketsOutput {
+                buckets: Some(list),
+                ..
+            }) => Ok(list
+                .iter()
+                .map(|bucket| ContainerMetadata {
+                    container_id: bucket.name.clone().unwrap_or_default(),
+                    created_at: to_timestamp(bucket.creation_date),
+                })
+                .collect()),
+            Ok(ListBucketsOutput { buckets: None, .. }) => Ok(Vec::new()),
+            Err(SdkError::ServiceError { err, .. }) => {
+                error!(error = %err, "Service error");
+                Err(RpcError::Other(err.to_string()))
+            }
+            Err(e) => {
+                error!(error = %e, "unexpected error");
+                Err(RpcError::Other(e.to_string()))
+            }
+        }
+    }
+
+    async fn remove_containers(&self, bucket_ids: &[&str]) -> RpcResult<MultiResult> {
+        let mut results = Vec::with_capacity(bucket_ids.len());
+        for bucket in bucket_ids.iter() {
+            match self.s3_client.delete_bucket().bucket(*bucket).send().await {
+                Ok(_) => {}
+                Err(SdkError::ServiceError { err, .. }) => {
+                    results.push(blobstore::ItemResult {
+                        key: bucket.to_string(),
+                        error: Some(err.to_string()),
+                        success: false,
+                    });
+                }
+                Err(e) => {
+                    error!(error = %e, "unexpected error");
+                    return Err(RpcError::Other(format!("unexpected error: {}", e)));
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    async fn object_exists(&self, bucket_id: &str, object_id: &str) -> RpcResult<bool> {
+        match self
+            .s3_client
+            .head_object()
+            .bucket(bucket_id)
+            .key(object_id)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError {
+                err:
+                    HeadObjectError {
+                        kind: HeadObjectErrorKind::NotFound(_),
+                        ..
+                    },
+                ..
+            }) => Ok(false),
+            Err(e) => {
+                error!(
+                    error = %e,
+                    "unexpected error for object_exists"
+                );
+                Err(RpcError::Other(e.to_string()))
+            }
+        }
+    }
+
+    async fn get_object_info(&self, bucket_id: &str, object_id: &str) -> RpcResult<ObjectMetadata> {
+        match self
+            .s3_client
+            .head_object()
+            .bucket(bucket_id)
+            .key(object_id)
+            .send()
+            .await
+        {
+            Ok(HeadObjectOutput {
+                last_modified,
+                content_length,
+                content_type,
+                content_encoding,
+                e_tag,
+                ..
+            }) => Ok(ObjectMetadata {
+                container_id: bucket_id.to_string(),
+                object_id: object_id.to_string(),
+                last_modified: to_timestamp(last_modified),
+                content_type,
+                content_encoding,
+                content_length: content_length as u64,
+                etag: e_tag,
+            }),
+            Err(SdkError::ServiceError {
+                err:
+                    HeadObjectError {
+                        kind: HeadObjectErrorKind::NotFound(_),
+                        ..
+                    },
+                ..
+            }) => Err(super::object_not_found(bucket_id, object_id)),
+            Err(e) => Err(RpcError::Other(format!(
+                "get_object_metadata for Bucket({}) Object({}): {}",
+                bucket_id, object_id, e
+            ))),
+        }
+    }
+
+    async fn list_objects(
+        &self,
+        bucket_id: &str,
+        arg: &ListObjectsRequest,
+    ) -> RpcResult<ListObjectsResponse> {
+        let mut req = self.s3_client.list_objects_v2().bucket(bucket_id);
+        if let Some(max_items) = arg.max_items {
+            if max_items > i32::MAX as u32 {
+                // edge case to avoid panic
+                return Err(RpcError::InvalidParameter(
+                    "max_items too large".to_string(),
+                ));
+            }
+            req = req.max_keys(max_items as i32);
+        } else {
+            req = req.max_keys(DEFAULT_MAX_ITEMS as i32);
+        }
+        if let Some(continuation) = &arg.continuation {
+            req = req.set_continuation_token(Some(continuation.clone()));
+        } else if let Some(start_with) = &arg.start_with {
+            req = req.set_start_after(Some(start_with.clone()));
+        }
+        match req.send().await {
+            Ok(list) => {
+                debug!(
+                    "list_objects (bucket:{}) returned {} items",
+                    bucket_id,
+                    list.contents.as_ref().map(|l| l.len()).unwrap_or(0)
+                );
+                let is_last = !list.is_truncated;
+                let objects = match list.contents {
+                    Some(items) => items
+                        .iter()
+                        .map(|o| ObjectMetadata {
+                            container_id: bucket_id.to_string(),
+                            last_modified: to_timestamp(o.last_modified),
+                            object_id: o.key.clone().unwrap_or_default(),
+                            content_length: o.size as u64,
+                            content_encoding: None,
+                            content_type: None,
+                            etag: o.e_tag.clone(),
+                        })
+                        .collect(),
+                    None => Vec::<ObjectMetadata>::new(),
+                };
+                Ok(ListObjectsResponse {
+                    continuation: list.next_continuation_token,
+                    objects,
+                    is_last,
+                })
+            }
+            Err(e) => {
+                error!(error = %e, "unable to list objects");
+                Err(RpcError::Other(e.to_string()))
+            }
+        }
+    }
+
+    async fn remove_objects(
+        &self,
+        bucket_id: &str,
+        object_ids: &[String],
+    ) -> RpcResult<MultiResult> {
+        let mut protections = Vec::with_capacity(object_ids.len());
+        if self.object_lock.enabled {
+            for ids in object_ids.chunks(LOCK_CHECK_CONCURRENCY) {
+                let checks = ids.iter().map(|id| self.lock_protection(bucket_id, id));
+                protections.extend(futures::future::join_all(checks).await);
+            }
+        } else {
+            protections.resize(object_ids.len(), None);
+        }
+        let mut results = Vec::new();
+        let mut objects = Vec::with_capacity(object_ids.len());
+        for (id, protection) in object_ids.iter().zip(protections) {
+            match protection {
+                Some(error) => results.push(blobstore::ItemResult {
+                    key: id.clone(),
+                    error: Some(error),
+                    success: false,
+                }),
+                None => objects.push(ObjectIdentifier::builder().key(id).build()),
+            }
+        }
+        if objects.is_empty() {
+            return Ok(results);
+        }
+        match self
+            .s3_client
+            .delete_objects()
+            .bucket(bucket_id)
+            .delete(
+                aws_sdk_s3::model::Delete::builder()
+                    .set_objects(Some(objects))
+                    .quiet(true)
+                    .build(),
+            )
+            .send()
+            .await
+        {
+            Ok(output) => {
+                for e in output.errors.unwrap_or_default().iter() {
+                    results.push(blobstore::ItemResult {
+                        key: e.key.clone().unwrap_or_default(),
+                        error: Some(delete_error_message(
+                            e.code.as_deref(),
+                            e.message.as_deref(),
+                        )),
+                        success: false,
+                    });
+                }
+                Ok(results)
+            }
+            Err(e) => {
+                error!(error = %e, "Unable to delete objects");
+                Err(RpcError::Other(e.to_string()))
+            }
+        }
+    }
+
+    async fn put_object(
+        &self,
+        bucket_id: &str,
+        arg: &PutObjectRequest,
+    ) -> RpcResult<PutObjectResponse> {
+        let preconditions = put_preconditions(arg)?;
+        let retention = self.object_retention(arg)?;
+        // TODO: make sure put_object takes an owned `PutObjectRequest` to avoid cloning the whole chunk
+        let bytes = arg.chunk.bytes.to_owned();
+        // S3 requires an integrity check for writes with object lock, which the sdk doesn't add
+        let content_md5 =
+            (retention.is_some() || arg.legal_hold == Some(true) || self.object_lock.enabled)
+                .then(|| content_md5(&bytes));
+        let mut req = self
+            .s3_client
+            .put_object()
+            .bucket(bucket_id)
+            .key(&arg.chunk.object_id)
+            .set_content_md5(content_md5)
+            .body(S3ByteStream::from(bytes));
+        if let Some((mode, retain_until)) = retention {
+            req = req
+                .object_lock_mode(mode)
+                .object_lock_retain_until_date(retain_until);
+        }
+        if arg.legal_hold == Some(true) {
+            req = req.object_lock_legal_hold_status(ObjectLockLegalHoldStatus::On);
+        }
+        let result = if preconditions.is_empty() {
+            req.send().await
+        } else {
+            // the sdk doesn't model conditional writes, so the headers are added to the raw request
+            req.customize()
+                .await
+                .map_err(|e| RpcError::Other(e.to_string()))?
+                .map_request(|mut http_req| {
+                    http_req.headers_mut().extend(preconditions);
+                    Ok::<_, RpcError>(http_req)
+                })?
+                .send()
+                .await
+        };
+        match result {
+            Ok(output) => Ok(PutObjectResponse {
+                etag: output.e_tag,
+                ..Default::default()
+            }),
+            Err(SdkError::ServiceError { err, raw })
+                if is_precondition_failure(raw.http().status(), err.code()) =>
+            {
+                debug!(error = %err, "put_object precondition failed");
+                Err(super::precondition_failed(bucket_id, &arg.chunk.object_id))
+            }
+            Err(e) => {
+                error!(
+                    error = %e,
+                    "Error putting object",
+                );
+                Err(RpcError::Other(e.to_string()))
+            }
+        }
+    }
+
+    async fn get_object(
+        &self,
+        bucket_id: &str,
+        object_id: &str,
+        range: Option<(u64, u64)>,
+    ) -> RpcResult<ByteStream> {
+        match self
+            .s3_client
+            .get_object()
+            .bucket(bucket_id)
+            .key(object_id)
+            .set_range(range.and_then(|(start, end)| to_range_header(Some(start), Some(end))))
+            .send()
+            .await
+        {
+            Ok(object_output) => Ok(Box::pin(
+                object_output
+                    .body
+                    .map_err(|e| RpcError::Other(e.to_string())),
+            )),
+            Err(e) => {
+                error!(
+                    error = %e,
+                    "Error when getting object"
+                );
+                Err(RpcError::Other(e.to_string()))
+            }
+        }
+    }
+
+    async fn set_legal_hold(&self, bucket_id: &str, arg: &LegalHoldRequest) -> RpcResult<()> {
+        let status = if arg.enabled {
+            ObjectLockLegalHoldStatus::On
+        } else {
+            ObjectLockLegalHoldStatus::Off
+        };
+        match self
+            .s3_client
+            .put_object_legal_hold()
+            .bucket(bucket_id)
+            .key(&arg.object_id)
+            .legal_hold(ObjectLockLegalHold::builder().status(status).build())
+            .send()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!(error = %e, "Unable to set legal hold");
+                Err(RpcError::Other(format!(
+                    "set_legal_hold for Bucket({}) Object({}): {}",
+                    bucket_id, &arg.object_id, e
+                )))
+            }
+        }
+    }
+
+    async fn start_upload(&self, bucket_id: &str, arg: &PutObjectRequest) -> RpcResult<String> {
+        if arg.if_match.is_some() || arg.if_none_match.is_some() {
+            return Err(RpcError::InvalidParameter(
+                "preconditions are not supported for chunked uploads".to_string(),
+            ));
+        }
+        let retention = self.object_retention(arg)?;
+        let mut req = self
+            .s3_client
+            .create_multipart_upload()
+            .bucket(bucket_id)
+            .key(&arg.chunk.object_id)
+            .set_content_type(arg.content_type.clone())
+            .set_content_encoding(arg.content_encoding.clone());
+        if let Some((mode, retain_until)) = retention {
+            req = req
+                .object_lock_mode(mode)
+                .object_lock_retain_until_date(retain_until);
+        }
+        if arg.legal_hold == Some(true) {
+            req = req.object_lock_legal_hold_status(ObjectLockLegalHoldStatus::On);
+        }
+        match req.send().await {
+            Ok(output) => output.upload_id.ok_or_else(|| {
+                RpcError::Other("create_multipart_upload returned no upload id".to_string())
+            }),
+            Err(e) => {
+                error!(error = %e, "Unable to start multipart upload");
+                Err(RpcError::Other(e.to_string()))
+            }
+        }
+    }
+
+    async fn upload_part(
+        &self,
+        bucket_id: &str,
+        object_id: &str,
+        upload_id: &str,
+        part_number: i32,
+        bytes: Bytes,
+    ) -> RpcResult<String> {
+        // parts of an upload with object lock retention must have Content-MD5. The retention
+        // of the upload isn't known here, so it's sent for every part
+        match self
+            .s3_client
+            .upload_part()
+            .bucket(bucket_id)
+            .key(object_id)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .content_md5(content_md5(&bytes))
+            .body(S3ByteStream::from(bytes))
+            .send()
+            .await
+        {
+            Ok(output) => Ok(output.e_tag.unwrap_or_default()),
+            Err(e) => {
+                error!(error = %e, part_number, "Unable to upload part");
+                Err(RpcError::Other(e.to_string()))
+            }
+        }
+    }
+
+    async fn complete_upload(
+        &self,
+        bucket_id: &str,
+        object_id: &str,
+        upload_id: &str,
+        parts: &[(i32, String)],
+    ) -> RpcResult<Option<String>> {
+        let parts = parts
+            .iter()
+            .map(|(part_number, etag)| {
+                CompletedPart::builder()
+                    .part_number(*part_number)
+                    .e_tag(etag)
+                    .build()
+            })
+            .collect();
+        match self
+            .s3_client
+            .complete_multipart_upload()
+            .bucket(bucket_id)
+            .key(object_id)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+        {
+            Ok(output) => Ok(output.e_tag),
+            Err(e) => {
+                error!(error = %e, "Unable to complete multipart upload");
+                Err(RpcError::Other(e.to_string()))
+            }
+        }
+    }
+
+    async fn abort_upload(
+        &self,
+        bucket_id: &str,
+        object_id: &str,
+        upload_id: &str,
+    ) -> RpcResult<()> {
+        self.s3_client
+            .abort_multipart_upload()
+            .bucket(bucket_id)
+            .key(object_id)
+            .upload_id(upload_id)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| RpcError::Other(e.to_string()))
+    }
+}
+
+/// translate optional s3 DateTime to optional Timestamp.
+/// Invalid times return None.
+fn to_timestamp(dt: Option<aws_sdk_s3::types::DateTime>) -> Option<wasmbus_rpc::Timestamp> {
+    match dt {
+        Some(dt) => match wasmbus_rpc::Timestamp::new(dt.secs(), dt.subsec_nanos()) {
+            Ok(t) => Some(t),
+            Err(_) => None,
+        },
+        None => None,
+    }
+}
+
+/// base64-encoded MD5 digest of the body, for the Content-MD5 header
+fn content_md5(body: &[u8]) -> String {
+    use md5::{Digest, Md5};
+    base64::encode(Md5::digest(body))
+}
+
+/// parse object lock retention mode
+fn to_lock_mode(mode: &str) -> Result<ObjectLockMode, RpcError> {
+    match mode {
+        "GOVERNANCE" => Ok(ObjectLockMode::Governance),
+        "COMPLIANCE" => Ok(ObjectLockMode::Compliance),
+        _ => Err(RpcError::InvalidParameter(format!(
+            "invalid object lock retention mode '{}': expected GOVERNANCE or COMPLIANCE",
+            mode
+        ))),
+    }
+}
+
+/// Returns the error for removing an object whose current version is retained until
+/// `retain_until`, or has a legal hold, at `now` seconds since the epoch.
+/// Returns None if the object isn't protected
+fn lock_protection_message(
+    retain_until: Option<&aws_sdk_s3::types::DateTime>,
+    legal_hold: bool,
+    now: i64,
+) -> Option<String> {
+    match retain_until {
+        Some(until) if until.secs() > now => Some(format!(
+            "ObjectLocked: the object is retained until {}",
+            until
+                .fmt(aws_smithy_types::date_time::Format::DateTime)
+                .unwrap_or_else(|_| until.secs().to_string())
+        )),
+        _ if legal_hold => Some("ObjectLocked: the object has a legal hold".to_string()),
+        _ => None,
+    }
+}
+
+/// Returns the error for removing an object whose retention or legal hold couldn't be read,
+/// or None if the object has neither, or doesn't exist
+fn lock_lookup_error<E>(e: SdkError<E>, object_id: &str) -> Option<String>
+where
+    E: aws_smithy_types::retry::ProvideErrorKind + std::error::Error + 'static,
+{
+    if let SdkError::ServiceError { err, .. } = &e {
+        if matches!(
+            err.code(),
+            Some("NoSuchObjectLockConfiguration" | "NoSuchKey")
+        ) {
+            return None;
+        }
+    }
+    error!(error = %e, %object_id, "unable to check object lock before removing object");
+    Some(delete_error_message(
+        Some("ObjectLockUnknown"),
+        Some(&format!("unable to check object lock: {}", e)),
+    ))
+}
+
+/// Format the error for an object that could not be deleted.
+/// S3 reports objects protected by retention or a legal hold as AccessDenied,
+/// so that case gets an explicit hint.
+fn delete_error_message(code: Option<&str>, message: Option<&str>) -> String {
+    let code = code.unwrap_or("Unknown");
+    let message = message.unwrap_or("object could not be removed");
+    if code == "AccessDenied" {
+        format!(
+            "{}: {} (the object may be protected by object lock retention or a legal hold)",
+            code, message
+        )
+    } else {
+        format!("{}: {}", code, message)
+    }
+}
+
+/// Convert the optional preconditions of a put request to http headers.
+/// `if_none_match` only supports the value "*" (write only if the object doesn't exist).
+fn put_preconditions(
+    arg: &PutObjectRequest,
+) -> Result<Vec<(http::header::HeaderName, http::HeaderValue)>, RpcError> {
+    let mut headers = Vec::new();
+    if let Some(etag) = &arg.if_match {
+        let value = http::HeaderValue::from_str(etag)
+            .map_err(|_| RpcError::InvalidParameter(format!("invalid if_match etag: {}", etag)))?;
+        headers.push((http::header::IF_MATCH, value));
+    }
+    if super::if_none_match(arg)? {
+        headers.push((
+            http::header::IF_NONE_MATCH,
+            http::HeaderValue::from_static("*"),
+        ));
+    }
+    Ok(headers)
+}
+
+/// returns true if S3 rejected a conditional request. S3 returns 412 when the
+/// precondition is not met, and 409 if a concurrent conditional write won the race.
+fn is_precondition_failure(status: http::StatusCode, code: Option<&str>) -> bool {
+    status == http::StatusCode::PRECONDITION_FAILED
+        || matches!(
+            code,
+            Some("PreconditionFailed" | "ConditionalRequestConflict")
+        )
+}
+
+/// convert optional start/end to an http range request header value
+/// If end is before start, the range is invalid, and per spec (https://www.w3.org/Protocols/rfc2616/rfc2616-sec14.html#sec14.35),
+/// the range will be ignored.
+/// If end is specified and start is None, start value of 0 is used. (Otherwise "bytes=-x" is interpreted as the last x bytes)
+fn to_range_header(start: Option<u64>, end: Option<u64>) -> Option<String> {
+    match (start, end) {
+        (Some(start), Some(end)) if start <= end => Some(format!("bytes={}-{}", start, end)),
+        (Some(start), None) => Some(format!("bytes={}-", start)),
+        (None, Some(end)) => Some(format!("bytes=0-{}", end)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn md5_header() {
+        assert_eq!(content_md5(b""), "1B2M2Y8AsgTpgAmY7PhCfg==");
+        assert_eq!(content_md5(b"hello"), "XUFAKrxLKna5cZ2REBfFkg==");
+    }
+
+    #[test]
+    fn range_header() {
+        assert_eq!(
+            to_range_header(Some(1), Some(99)),
+            Some("bytes=1-99".to_string())
+        );
+        assert_eq!(to_range_header(Some(10), Some(5)), None);
+        assert_eq!(
+            to_range_header(None, Some(99)),
+            Some("bytes=0-99".to_string())
+        );
+        assert_eq!(
+            to_range_header(Some(99), None),
+            Some("bytes=99-".to_string())
+        );
+        assert_eq!(to_range_header(None, None), None);
+    }
+
+    #[test]
+    fn preconditions() {
+        let mut req = PutObjectRequest::default();
+        assert!(put_preconditions(&req).unwrap().is_empty());
+
+        req.if_none_match = Some("*".to_string());
+        req.if_match = Some("\"abc123\"".to_string());
+        let headers = put_preconditions(&req).unwrap();
+        assert_eq!(headers.len(), 2);
+        assert!(headers.contains(&(
+            http::header::IF_MATCH,
+            http::HeaderValue::from_static("\"abc123\"")
+        )));
+        assert!(headers.contains(&(
+            http::header::IF_NONE_MATCH,
+            http::HeaderValue::from_static("*")
+        )));
+
+        req.if_match = None;
+        req.if_none_match = Some("\"abc123\"".to_string());
+        assert!(put_preconditions(&req).is_err(), "only '*' supported");
+
+        assert!(is_precondition_failure(
+            http::StatusCode::PRECONDITION_FAILED,
+            None
+        ));
+        assert!(is_precondition_failure(
+            http::StatusCode::CONFLICT,
+            Some("ConditionalRequestConflict")
+        ));
+        assert!(!is_precondition_failure(
+            http::StatusCode::FORBIDDEN,
+            Some("AccessDenied")
+        ));
+    }
+
+    #[test]
+    fn lock_mode() {
+        assert_eq!(
+            to_lock_mode("GOVERNANCE").unwrap(),
+            ObjectLockMode::Governance
+        );
+        assert_eq!(
+            to_lock_mode("COMPLIANCE").unwrap(),
+            ObjectLockMode::Compliance
+        );
+        assert!(to_lock_mode("compliance").is_err());
+        assert!(to_lock_mode("").is_err());
+
+        assert!(
+            delete_error_message(Some("AccessDenied"), Some("Access Denied"))
+                .contains("object lock")
+        );
+        assert_eq!(
+            delete_error_message(Some("NoSuchKey"), None),
+            "NoSuchKey: object could not be removed"
+        );
+    }
+
+    #[test]
+    fn lock_protection() {
+        let until = aws_sdk_s3::types::DateTime::from_secs(1_000);
+        let retained = lock_protection_message(Some(&until), false, 999).unwrap();
+        assert!(retained.starts_with("ObjectLocked:"), "{}", retained);
+        assert!(retained.contains("1970-01-01T00:16:40Z"), "{}", retained);
+        // expired retention doesn't protect the object
+        assert_eq!(lock_protection_message(Some(&until), false, 1_000), None);
+        assert_eq!(
+            lock_protection_message(Some(&until), true, 1_000).as_deref(),
+            Some("ObjectLocked: the object has a legal hold")
+        );
+        assert_eq!(lock_protection_message(None, false, 0), None);
+    }
+}
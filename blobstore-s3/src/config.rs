@@ -9,12 +9,26 @@ use serde::Deserialize;
 use std::{collections::HashMap, env};
 use wasmbus_rpc::error::{RpcError, RpcResult};
 
+use crate::upload::MIN_PART_SIZE;
+
 const DEFAULT_STS_SESSION: &str = "blobstore_s3_provider";
 
-/// Configuration for connecting to S3.
+/// default part size for chunked uploads (8MB)
+const DEFAULT_PART_SIZE: u64 = 8 * 1024 * 1024;
+/// largest part size accepted by s3 (5GB)
+const MAX_PART_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// valid object lock retention modes
+pub(crate) const OBJECT_LOCK_MODES: &[&str] = &["GOVERNANCE", "COMPLIANCE"];
+
+/// Configuration for connecting to a storage service.
 ///
 #[derive(Clone, Default, Deserialize)]
 pub struct StorageConfig {
+    /// storage service used by the link. If the link doesn't set it, BLOBSTORE_BACKEND
+    /// is used, and otherwise s3
+    #[serde(default)]
+    pub backend: BackendKind,
     /// AWS_ACCESS_KEY_ID, can be specified from environment
     pub access_key_id: Option<String>,
     /// AWS_SECRET_ACCESS_KEY, can be in environment
@@ -32,6 +46,91 @@ pub struct StorageConfig {
     /// optional map of bucket aliases to names
     #[serde(default)]
     pub aliases: HashMap<String, String>,
+    /// optional list of buckets the link may use, as "bucket" or "bucket/prefix".
+    /// If empty, all buckets are allowed
+    #[serde(default)]
+    pub allowed_buckets: Vec<String>,
+    /// reject requests that create, modify, or remove buckets or objects
+    #[serde(default)]
+    pub read_only: bool,
+    /// size of the parts sent to storage for chunked uploads, in bytes. Defaults to 8MB
+    pub upload_part_size: Option<u64>,
+    /// optional object lock (WORM) settings
+    #[serde(default)]
+    pub object_lock: ObjectLockConfig,
+    /// settings for the gcs backend
+    #[serde(default)]
+    pub gcs: GcsConfig,
+    /// settings for the azure backend
+    #[serde(default)]
+    pub azure: AzureConfig,
+}
+
+/// Storage service behind a link
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// AWS S3, or an s3-compatible service such as minio
+    #[default]
+    S3,
+    /// Google Cloud Storage
+    Gcs,
+    /// Azure Blob Storage
+    Azure,
+}
+
+impl std::str::FromStr for BackendKind {
+    type Err = RpcError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "s3" => Ok(BackendKind::S3),
+            "gcs" => Ok(BackendKind::Gcs),
+            "azure" => Ok(BackendKind::Azure),
+            _ => Err(RpcError::InvalidParameter(format!(
+                "invalid backend '{}': must be one of s3, gcs, azure",
+                s
+            ))),
+        }
+    }
+}
+
+/// Settings for Google Cloud Storage
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct GcsConfig {
+    /// project for listing and creating buckets (GOOGLE_CLOUD_PROJECT).
+    /// If not set, the project of the service account is used.
+    pub project_id: Option<String>,
+    /// path to a service account key file (GOOGLE_APPLICATION_CREDENTIALS).
+    /// If not set, application default credentials are used.
+    pub credentials_path: Option<String>,
+    /// optional override for the storage endpoint, for use with emulators (STORAGE_EMULATOR_HOST).
+    /// Requests to an overridden endpoint are not authenticated.
+    pub endpoint: Option<String>,
+}
+
+/// Settings for Azure Blob Storage
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AzureConfig {
+    /// storage account name (AZURE_STORAGE_ACCOUNT)
+    pub account: Option<String>,
+    /// storage account access key (AZURE_STORAGE_KEY)
+    pub access_key: Option<String>,
+    /// connect to a local azurite emulator with its development account
+    #[serde(default)]
+    pub use_emulator: bool,
+}
+
+/// Object lock settings for buckets created and objects written through a link
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ObjectLockConfig {
+    /// Create new buckets with object lock enabled
+    #[serde(default)]
+    pub enabled: bool,
+    /// Default retention mode for new objects, "GOVERNANCE" or "COMPLIANCE"
+    pub mode: Option<String>,
+    /// Default retention period for new objects, in days
+    pub retain_days: Option<u32>,
 }
 
 #[derive(Clone, Default, Deserialize)]
@@ -50,17 +149,32 @@ pub struct StsAssumeRoleConfig {
 impl StorageConfig {
     /// initialize from linkdef values
     pub fn from_values(values: &HashMap<String, String>) -> RpcResult<StorageConfig> {
-        let mut config = if let Some(config_b64) = values.get("config_b64") {
+        let json = if let Some(config_b64) = values.get("config_b64") {
             let bytes = base64::decode(config_b64.as_bytes()).map_err(|e| {
                 RpcError::InvalidParameter(format!("invalid base64 encoding: {}", e))
             })?;
-            serde_json::from_slice::<StorageConfig>(&bytes)
-                .map_err(|e| RpcError::InvalidParameter(format!("corrupt config_b64: {}", e)))?
-        } else if let Some(config) = values.get("config_json") {
-            serde_json::from_str::<StorageConfig>(config)
-                .map_err(|e| RpcError::InvalidParameter(format!("corrupt config_json: {}", e)))?
+            Some((
+                "config_b64",
+                serde_json::from_slice::<serde_json::Value>(&bytes),
+            ))
         } else {
-            StorageConfig::default()
+            values.get("config_json").map(|config| {
+                (
+                    "config_json",
+                    serde_json::from_str::<serde_json::Value>(config),
+                )
+            })
+        };
+        // the backend is chosen by the link, and BLOBSTORE_BACKEND only if the link doesn't set it
+        let mut link_backend = false;
+        let mut config = match json {
+            Some((name, json)) => {
+                let corrupt = |e| RpcError::InvalidParameter(format!("corrupt {}: {}", name, e));
+                let json = json.map_err(corrupt)?;
+                link_backend = json.get("backend").is_some();
+                serde_json::from_value::<StorageConfig>(json).map_err(corrupt)?
+            }
+            None => StorageConfig::default(),
         };
         // load environment variables from file
         if let Some(env_file) = values.get("env") {
@@ -88,10 +202,75 @@ impl StorageConfig {
         if let Ok(endpoint) = env::var("AWS_ENDPOINT") {
             config.endpoint = Some(endpoint)
         }
+
+        if !link_backend {
+            if let Ok(backend) = env::var("BLOBSTORE_BACKEND") {
+                config.backend = backend.parse()?;
+            }
+        }
+        if let Ok(project_id) = env::var("GOOGLE_CLOUD_PROJECT") {
+            config.gcs.project_id = Some(project_id);
+        }
+        if let Ok(path) = env::var("GOOGLE_APPLICATION_CREDENTIALS") {
+            config.gcs.credentials_path = Some(path);
+        }
+        if let Ok(host) = env::var("STORAGE_EMULATOR_HOST") {
+            config.gcs.endpoint = Some(host);
+        }
+        if let Ok(account) = env::var("AZURE_STORAGE_ACCOUNT") {
+            config.azure.account = Some(account);
+        }
+        if let Ok(key) = env::var("AZURE_STORAGE_KEY") {
+            config.azure.access_key = Some(key);
+        }
+
+        if config.backend != BackendKind::S3
+            && (config.object_lock.enabled
+                || config.object_lock.mode.is_some()
+                || config.object_lock.retain_days.is_some())
+        {
+            return Err(RpcError::InvalidParameter(
+                "object_lock is only supported by the s3 backend".to_string(),
+            ));
+        }
+        if let Some(size) = config.upload_part_size {
+            if !(MIN_PART_SIZE..=MAX_PART_SIZE).contains(&size) {
+                return Err(RpcError::InvalidParameter(format!(
+                    "invalid upload_part_size {}: must be between {} and {}",
+                    size, MIN_PART_SIZE, MAX_PART_SIZE
+                )));
+            }
+        }
+        match (&config.object_lock.mode, config.object_lock.retain_days) {
+            (Some(_), None) => {
+                return Err(RpcError::InvalidParameter(
+                    "object_lock mode requires retain_days".to_string(),
+                ))
+            }
+            (None, Some(_)) => {
+                return Err(RpcError::InvalidParameter(
+                    "object_lock retain_days requires a mode".to_string(),
+                ))
+            }
+            _ => {}
+        }
+        if let Some(mode) = &config.object_lock.mode {
+            if !OBJECT_LOCK_MODES.contains(&mode.as_str()) {
+                return Err(RpcError::InvalidParameter(format!(
+                    "invalid object_lock mode '{}': must be one of {:?}",
+                    mode, OBJECT_LOCK_MODES
+                )));
+            }
+        }
         // aliases are added from linkdefs in StorageClient::new()
         Ok(config)
     }
 
+    /// size of the parts sent to storage for chunked uploads
+    pub(crate) fn upload_part_size(&self) -> u64 {
+        self.upload_part_size.unwrap_or(DEFAULT_PART_SIZE)
+    }
+
     pub async fn configure_aws(self) -> AwsConfig {
         use aws_config::{
             default_provider::{credentials::DefaultCredentialsChain, region::DefaultRegionChain},
@@ -155,3 +334,27 @@ impl StorageConfig {
         loader.load().await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backend_precedence() {
+        std::env::set_var("BLOBSTORE_BACKEND", "gcs");
+        let config = |json: &str| {
+            StorageConfig::from_values(&HashMap::from([(
+                "config_json".to_string(),
+                json.to_string(),
+            )]))
+            .map(|config| config.backend)
+        };
+        // the link's backend is used, and the environment only if the link doesn't set one
+        let link = config(r#"{"backend":"azure"}"#);
+        let default = config("{}");
+        std::env::remove_var("BLOBSTORE_BACKEND");
+        assert_eq!(link.unwrap(), BackendKind::Azure);
+        assert_eq!(default.unwrap(), BackendKind::Gcs);
+        assert!(matches!(config("{"), Err(RpcError::InvalidParameter(_))));
+    }
+}
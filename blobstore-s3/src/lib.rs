@@ -1,18 +1,12 @@
-//! AWS S3 implementation for wasmcloud:blobstore
+//! Blobstore capability provider for S3, Google Cloud Storage, and Azure Blob Storage
 //!
-//! assume role http request https://docs.aws.amazon.com/cli/latest/reference/sts/assume-role.html
-//! get session token https://docs.aws.amazon.com/cli/latest/reference/sts/get-session-token.html
+//! `StorageClient` implements the wasmcloud:blobstore contract for one link, and forwards
+//! storage calls to the backend selected in the link's configuration.
 
 use std::num::NonZeroU64;
 use std::sync::Arc;
 use std::{collections::HashMap, num::NonZeroUsize};
 
-use aws_sdk_s3::{
-    error::{HeadBucketError, HeadBucketErrorKind, HeadObjectError, HeadObjectErrorKind},
-    model::ObjectIdentifier,
-    output::{CreateBucketOutput, HeadObjectOutput, ListBucketsOutput},
-    types::{ByteStream, SdkError},
-};
 use bytes::Bytes;
 use tokio_stream::StreamExt;
 use tracing::{debug, error, instrument, warn};
@@ -22,11 +16,22 @@ use wasmbus_rpc::{core::LinkDefinition, provider::prelude::*};
 use crate::wasmcloud_interface_blobstore::{
     self as blobstore, Blobstore, Chunk, ChunkReceiver, ChunkReceiverSender, ContainerId,
     ContainerIds, ContainerMetadata, ContainerObject, ContainersInfo, GetObjectResponse,
-    MultiResult, ObjectMetadata, PutChunkRequest, PutObjectResponse, RemoveObjectsRequest,
+    LegalHoldRequest, MultiResult, ObjectMetadata, PutChunkRequest, PutObjectResponse,
+    RemoveObjectsRequest,
 };
 
+mod backend;
+use backend::{AzureBackend, Backend, ByteStream, GcsBackend, S3Backend};
+
+mod policy;
+use policy::LinkPolicy;
+
+mod upload;
+pub use upload::UploadLimiter;
+use upload::Uploads;
+
 mod config;
-pub use config::StorageConfig;
+pub use config::{AzureConfig, BackendKind, GcsConfig, ObjectLockConfig, StorageConfig};
 
 // this is not an external library - built locally via build.rs & codegen.toml
 #[allow(dead_code)]
@@ -36,24 +41,29 @@ pub mod wasmcloud_interface_blobstore {
 
 const ALIAS_PREFIX: &str = "alias_";
 
-/// number of items to return in get_objects if max_items not specified
-const DEFAULT_MAX_ITEMS: i32 = 1000;
+/// Prefix of the error message returned when a conditional request
+/// (`if_match` or `if_none_match`) is rejected because its precondition failed
+pub const PRECONDITION_FAILED: &str = "PreconditionFailed";
 
-/// maximum size of message that we'll return from s3 (500MB)
+/// Prefix of the error message returned when a request is rejected by the
+/// link's bucket allowlist or read-only setting
+pub const ACCESS_DENIED: &str = "AccessDenied";
+
+/// maximum size of message that we'll return from storage (500MB)
 const MAX_CHUNK_SIZE: usize = 500 * 1024 * 1024;
 
 #[derive(Clone)]
 pub struct StorageClient {
-    s3_client: aws_sdk_s3::Client,
+    backend: Arc<dyn Backend>,
     ld: Arc<LinkDefinition>,
     aliases: Arc<HashMap<String, String>>,
+    policy: Arc<LinkPolicy>,
+    uploads: Uploads,
 }
 
 impl StorageClient {
-    pub async fn new(config: StorageConfig, ld: LinkDefinition) -> Self {
+    pub async fn new(config: StorageConfig, ld: LinkDefinition) -> RpcResult<Self> {
         let mut aliases = config.aliases.clone();
-        let s3_config = aws_sdk_s3::Config::from(&config.configure_aws().await);
-        let s3_client = aws_sdk_s3::Client::from_conf(s3_config);
         for (k, v) in ld.values.iter() {
             if let Some(alias) = k.strip_prefix(ALIAS_PREFIX) {
                 if alias.is_empty() || v.is_empty() {
@@ -63,11 +73,26 @@ impl StorageClient {
                 }
             }
         }
-        StorageClient {
-            s3_client,
+        let policy = LinkPolicy::new(&config, &ld.values)?;
+        let part_size = config.upload_part_size();
+        let backend: Arc<dyn Backend> = match config.backend {
+            BackendKind::S3 => Arc::new(S3Backend::new(config).await),
+            BackendKind::Gcs => Arc::new(GcsBackend::new(config.gcs).await?),
+            BackendKind::Azure => Arc::new(AzureBackend::new(config.azure)?),
+        };
+        Ok(StorageClient {
+            backend: backend.clone(),
             ld: Arc::new(ld),
             aliases: Arc::new(aliases),
-        }
+            policy: Arc::new(policy),
+            uploads: Uploads::new(backend, UploadLimiter::default(), part_size),
+        })
+    }
+
+    /// Use a limiter shared with other links for the memory used by chunked uploads
+    pub fn with_upload_limiter(mut self, limiter: UploadLimiter) -> Self {
+        self.uploads.set_limiter(limiter);
+        self
     }
 
     /// perform alias lookup on bucket name
@@ -97,60 +122,11 @@ impl StorageClient {
         MAX_CHUNK_SIZE
     }
 
-    /// Perform any cleanup necessary for a link + s3 connection
+    /// Perform any cleanup necessary for a link + storage connection
     pub async fn close(&self) {
         debug!(actor_id = %self.ld.actor_id, "blobstore-s3 dropping linkdef");
-        // If there were any https clients, caches, or other link-specific data,
-        // we would delete those here
-    }
-
-    /// Retrieves metadata about the object
-    #[instrument(level = "debug", skip(self, _ctx), fields(actor_id = ?_ctx.actor))]
-    async fn get_object_metadata(
-        &self,
-        _ctx: &Context,
-        bucket_id: &str,
-        object_id: &str,
-    ) -> Result<ObjectMetadata, RpcError> {
-        let bucket_id = self.unalias(bucket_id);
-        match self
-            .s3_client
-            .head_object()
-            .bucket(bucket_id)
-            .key(object_id)
-            .send()
-            .await
-        {
-            Ok(HeadObjectOutput {
-                last_modified,
-                content_length,
-                content_type,
-                content_encoding,
-                ..
-            }) => Ok(ObjectMetadata {
-                container_id: bucket_id.to_string(),
-                object_id: object_id.to_string(),
-                last_modified: to_timestamp(last_modified),
-                content_type,
-                content_encoding,
-                content_length: content_length as u64,
-            }),
-            Err(SdkError::ServiceError {
-                err:
-                    HeadObjectError {
-                        kind: HeadObjectErrorKind::NotFound(_),
-                        ..
-                    },
-                ..
-            }) => Err(RpcError::Other(format!(
-                "Not found: Bucket({}) Object({})",
-                bucket_id, object_id,
-            ))),
-            Err(e) => Err(RpcError::Other(format!(
-                "get_object_metadata for Bucket({}) Object({}): {}",
-                bucket_id, object_id, e
-            ))),
-        }
+        // uploads that were not completed are aborted, so their parts are not kept in storage
+        self.uploads.cancel_all().await;
     }
 
     /// Sends bytes to actor in a single rpc message.
@@ -212,15 +188,15 @@ impl StorageClient {
         Ok(bytes_sent)
     }
 
-    /// Async tokio task to accept chunks from S3 and send to actor.
+    /// Async tokio task to accept chunks from the backend and send to actor.
     /// `container_object` has the names of the container (bucket) and object to be streamed,
-    /// `excess` contains optional bytes from the first s3 stream chunk that didn't fit
+    /// `excess` contains optional bytes from the first stream chunk that didn't fit
     ///    in the initial message
     /// `offset` is the current offset within object that we are returning to the actor
     ///    (on entry, this should be the initial range offset requested plus the number
     ///    of bytes already sent to the actor in the GetObjectResponse)
     /// `end_range` the byte offset (inclusive) of the last byte to be returned to the client
-    async fn stream_from_s3(
+    async fn stream_to_actor(
         &self,
         ctx: &Context,
         mut container_object: ContainerObject,
@@ -261,7 +237,7 @@ impl StorageClient {
                 Ok(())
             }
             .instrument(tracing::debug_span!(
-                "stream_from_s3",
+                "stream_to_actor",
                 ?actor_id,
                 ?excess_len,
                 offset,
@@ -277,27 +253,15 @@ impl Blobstore for StorageClient {
     #[instrument(level = "debug", skip(self, _ctx, arg), fields(actor_id = ?_ctx.actor, bucket_id = %self.unalias(arg)))]
     async fn container_exists(&self, _ctx: &Context, arg: &ContainerId) -> RpcResult<bool> {
         let bucket_id = self.unalias(arg);
-        match self.s3_client.head_bucket().bucket(bucket_id).send().await {
-            Ok(_) => Ok(true),
-            Err(SdkError::ServiceError {
-                err:
-                    HeadBucketError {
-                        kind: HeadBucketErrorKind::NotFound(_),
-                        ..
-                    },
-                ..
-            }) => Ok(false),
-            Err(e) => {
-                error!(error = %e, "Unable to head bucket");
-                Err(RpcError::Other(e.to_string()))
-            }
-        }
+        self.policy.check_bucket(bucket_id)?;
+        self.backend.container_exists(bucket_id).await
     }
 
     /// Creates container if it does not exist
     #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, bucket_id = %self.unalias(arg)))]
     async fn create_container(&self, ctx: &Context, arg: &ContainerId) -> RpcResult<()> {
         let bucket_id = self.unalias(arg);
+        self.policy.check_bucket_write(bucket_id)?;
         match self.container_exists(ctx, &bucket_id.to_string()).await {
             Ok(true) => Ok(()),
             _ => {
@@ -308,32 +272,7 @@ impl Blobstore for StorageClient {
                         bucket_id, msg
                     )));
                 }
-                match self
-                    .s3_client
-                    .create_bucket()
-                    .bucket(bucket_id)
-                    .send()
-                    .await
-                {
-                    Ok(CreateBucketOutput { location, .. }) => {
-                        debug!(?location, "bucket created");
-                        Ok(())
-                    }
-                    Err(SdkError::ServiceError { err, .. }) => {
-                        error!(
-                            error = %err,
-                            "Got service error",
-                        );
-                        Err(RpcError::Other(err.to_string()))
-                    }
-                    Err(e) => {
-                        error!(
-                            error = %e,
-                            "unexpected_error",
-                        );
-                        Err(RpcError::Other(e.to_string()))
-                    }
-                }
+                self.backend.create_container(bucket_id).await
             }
         }
     }
@@ -345,48 +284,15 @@ impl Blobstore for StorageClient {
         arg: &ContainerId,
     ) -> RpcResult<ContainerMetadata> {
         let bucket_id = self.unalias(arg);
-        match self.s3_client.head_bucket().bucket(bucket_id).send().await {
-            Ok(_) => Ok(ContainerMetadata {
-                container_id: bucket_id.to_string(),
-                // unfortunately, HeadBucketOut doesn't include any information
-                // so we can't fill in creation date
-                created_at: None,
-            }),
-            Err(SdkError::ServiceError {
-                err:
-                    HeadBucketError {
-                        kind: HeadBucketErrorKind::NotFound(_),
-                        ..
-                    },
-                ..
-            }) => Err(RpcError::Other(format!("Bucket({})not found", bucket_id))),
-            Err(e) => Err(RpcError::Other(e.to_string())),
-        }
+        self.policy.check_bucket(bucket_id)?;
+        self.backend.get_container_info(bucket_id).await
     }
 
     #[instrument(level = "debug", skip(self, _ctx), fields(actor_id = ?_ctx.actor))]
     async fn list_containers(&self, _ctx: &Context) -> RpcResult<ContainersInfo> {
-        match self.s3_client.list_buckets().send().await {
-            Ok(ListBucketsOutput {
-                buckets: Some(list),
-                ..
-            }) => Ok(list
-                .iter()
-                .map(|bucket| ContainerMetadata {
-                    container_id: bucket.name.clone().unwrap_or_default(),
-                    created_at: to_timestamp(bucket.creation_date),
-                })
-                .collect()),
-            Ok(ListBucketsOutput { buckets: None, .. }) => Ok(Vec::new()),
-            Err(SdkError::ServiceError { err, .. }) => {
-                error!(error = %err, "Service error");
-                Err(RpcError::Other(err.to_string()))
-            }
-            Err(e) => {
-                error!(error = %e, "unexpected error");
-                Err(RpcError::Other(e.to_string()))
-            }
-        }
+        let mut containers = self.backend.list_containers().await?;
+        containers.retain(|c| self.policy.allows_bucket(&c.container_id));
+        Ok(containers)
     }
 
     #[instrument(level = "debug", skip(self, _ctx), fields(actor_id = ?_ctx.actor))]
@@ -395,24 +301,11 @@ impl Blobstore for StorageClient {
         _ctx: &Context,
         arg: &ContainerIds,
     ) -> RpcResult<MultiResult> {
-        let mut results = Vec::with_capacity(arg.len());
-        for bucket in arg.iter() {
-            let bucket = self.unalias(bucket);
-            match self.s3_client.delete_bucket().bucket(bucket).send().await {
-                Ok(_) => {}
-                Err(SdkError::ServiceError { err, .. }) => {
-                    results.push(blobstore::ItemResult {
-                        key: bucket.to_string(),
-                        error: Some(err.to_string()),
-                        success: false,
-                    });
-                }
-                Err(e) => {
-                    error!(error = %e, "unexpected error");
-                    return Err(RpcError::Other(format!("unexpected error: {}", e)));
-                }
-            }
+        let buckets: Vec<&str> = arg.iter().map(|bucket| self.unalias(bucket)).collect();
+        for bucket in buckets.iter() {
+            self.policy.check_bucket_write(bucket)?;
         }
+        let results = self.backend.remove_containers(&buckets).await?;
         if !results.is_empty() {
             error!(
                 "remove_containers returned {}/{} errors",
@@ -427,31 +320,8 @@ impl Blobstore for StorageClient {
     #[instrument(level = "debug", skip(self, _ctx, arg), fields(actor_id = ?_ctx.actor, bucket_id = %self.unalias(&arg.container_id), object_id = %arg.object_id))]
     async fn object_exists(&self, _ctx: &Context, arg: &ContainerObject) -> RpcResult<bool> {
         let bucket_id = self.unalias(&arg.container_id);
-        match self
-            .s3_client
-            .head_object()
-            .bucket(bucket_id)
-            .key(&arg.object_id)
-            .send()
-            .await
-        {
-            Ok(_) => Ok(true),
-            Err(SdkError::ServiceError {
-                err:
-                    HeadObjectError {
-                        kind: HeadObjectErrorKind::NotFound(_),
-                        ..
-                    },
-                ..
-            }) => Ok(false),
-            Err(e) => {
-                error!(
-                    error = %e,
-                    "unexpected error for object_exists"
-                );
-                Err(RpcError::Other(e.to_string()))
-            }
-        }
+        self.policy.check_object(bucket_id, &arg.object_id)?;
+        self.backend.object_exists(bucket_id, &arg.object_id).await
     }
 
     /// Retrieves metadata about the object
@@ -462,44 +332,10 @@ impl Blobstore for StorageClient {
         arg: &ContainerObject,
     ) -> Result<ObjectMetadata, RpcError> {
         let bucket_id = self.unalias(&arg.container_id);
-        match self
-            .s3_client
-            .head_object()
-            .bucket(bucket_id)
-            .key(arg.object_id.clone())
-            .send()
+        self.policy.check_object(bucket_id, &arg.object_id)?;
+        self.backend
+            .get_object_info(bucket_id, &arg.object_id)
             .await
-        {
-            Ok(HeadObjectOutput {
-                last_modified,
-                content_length,
-                content_type,
-                content_encoding,
-                ..
-            }) => Ok(ObjectMetadata {
-                container_id: bucket_id.to_string(),
-                object_id: arg.object_id.clone(),
-                last_modified: to_timestamp(last_modified),
-                content_type,
-                content_encoding,
-                content_length: content_length as u64,
-            }),
-            Err(SdkError::ServiceError {
-                err:
-                    HeadObjectError {
-                        kind: HeadObjectErrorKind::NotFound(_),
-                        ..
-                    },
-                ..
-            }) => Err(RpcError::Other(format!(
-                "Not found: Bucket({}) Object({})",
-                bucket_id, &arg.object_id,
-            ))),
-            Err(e) => Err(RpcError::Other(format!(
-                "get_object_metadata for Bucket({}) Object({}): {}",
-                bucket_id, &arg.object_id, e
-            ))),
-        }
     }
 
     #[instrument(level = "debug", skip(self, _ctx, arg), fields(actor_id = ?_ctx.actor, bucket_id = %self.unalias(&arg.container_id), max_items = arg.max_items))]
@@ -510,56 +346,12 @@ impl Blobstore for StorageClient {
     ) -> RpcResult<blobstore::ListObjectsResponse> {
         let bucket_id = self.unalias(&arg.container_id);
         debug!("asking for list_objects bucket: {}", bucket_id);
-        let mut req = self.s3_client.list_objects_v2().bucket(bucket_id);
-        if let Some(max_items) = arg.max_items {
-            if max_items > i32::MAX as u32 {
-                // edge case to avoid panic
-                return Err(RpcError::InvalidParameter(
-                    "max_items too large".to_string(),
-                ));
-            }
-            req = req.max_keys(max_items as i32);
-        } else {
-            req = req.max_keys(DEFAULT_MAX_ITEMS);
-        }
-        if let Some(continuation) = &arg.continuation {
-            req = req.set_continuation_token(Some(continuation.clone()));
-        } else if let Some(start_with) = &arg.start_with {
-            req = req.set_start_after(Some(start_with.clone()));
-        }
-        match req.send().await {
-            Ok(list) => {
-                debug!(
-                    "list_objects (bucket:{}) returned {} items",
-                    bucket_id,
-                    list.contents.as_ref().map(|l| l.len()).unwrap_or(0)
-                );
-                let is_last = !list.is_truncated;
-                let objects = match list.contents {
-                    Some(items) => items
-                        .iter()
-                        .map(|o| ObjectMetadata {
-                            container_id: bucket_id.to_string(),
-                            last_modified: to_timestamp(o.last_modified),
-                            object_id: o.key.clone().unwrap_or_default(),
-                            content_length: o.size as u64,
-                            content_encoding: None,
-                            content_type: None,
-                        })
-                        .collect(),
-                    None => Vec::<ObjectMetadata>::new(),
-                };
-                Ok(blobstore::ListObjectsResponse {
-                    continuation: list.next_continuation_token,
-                    objects,
-                    is_last,
-                })
-            }
-            Err(e) => {
-                error!(error = %e, "unable to list objects");
-                Err(RpcError::Other(e.to_string()))
-            }
-        }
+        self.policy.check_bucket(bucket_id)?;
+        let mut list = self.backend.list_objects(bucket_id, arg).await?;
+        // objects outside the link's allowed prefixes are omitted
+        list.objects
+            .retain(|o| self.policy.allows_object(bucket_id, &o.object_id));
+        Ok(list)
     }
 
     #[instrument(level = "debug", skip(self, _ctx, arg), fields(actor_id = ?_ctx.actor, bucket_id = %self.unalias(&arg.container_id)))]
@@ -569,51 +361,18 @@ impl Blobstore for StorageClient {
         arg: &RemoveObjectsRequest,
     ) -> RpcResult<MultiResult> {
         let bucket_id = self.unalias(&arg.container_id);
-        match self
-            .s3_client
-            .delete_objects()
-            .bucket(bucket_id)
-            .delete(
-                aws_sdk_s3::model::Delete::builder()
-                    .set_objects(Some(
-                        arg.objects
-                            .iter()
-                            .map(|id| ObjectIdentifier::builder().key(id).build())
-                            .collect(),
-                    ))
-                    .quiet(true)
-                    .build(),
-            )
-            .send()
-            .await
-        {
-            Ok(output) => {
-                if let Some(errors) = output.errors {
-                    let mut results = Vec::with_capacity(errors.len());
-                    for e in errors.iter() {
-                        results.push(blobstore::ItemResult {
-                            key: e.key.clone().unwrap_or_default(),
-                            error: e.message.clone(),
-                            success: false,
-                        });
-                    }
-                    if !results.is_empty() {
-                        error!(
-                            "delete_objects returned {}/{} errors",
-                            results.len(),
-                            arg.objects.len()
-                        );
-                    }
-                    Ok(results)
-                } else {
-                    Ok(Vec::new())
-                }
-            }
-            Err(e) => {
-                error!(error = %e, "Unable to delete objects");
-                Err(RpcError::Other(e.to_string()))
-            }
+        for object_id in arg.objects.iter() {
+            self.policy.check_object_write(bucket_id, object_id)?;
         }
+        let results = self.backend.remove_objects(bucket_id, &arg.objects).await?;
+        if !results.is_empty() {
+            error!(
+                "delete_objects returned {}/{} errors",
+                results.len(),
+                arg.objects.len()
+            );
+        }
+        Ok(results)
     }
 
     #[instrument(
@@ -627,12 +386,6 @@ impl Blobstore for StorageClient {
         arg: &blobstore::PutObjectRequest,
     ) -> RpcResult<PutObjectResponse> {
         let bucket_id = self.unalias(&arg.chunk.container_id);
-        if !arg.chunk.is_last {
-            error!("put_object for multi-part upload: not implemented!");
-            return Err(RpcError::InvalidParameter(
-                "multipart upload not implemented".to_string(),
-            ));
-        }
         if arg.chunk.offset != 0 {
             error!("put_object with initial offset non-zero: not implemented!");
             return Err(RpcError::InvalidParameter(
@@ -645,29 +398,20 @@ impl Blobstore for StorageClient {
                 "cannot put zero-length objects".to_string(),
             ));
         }
-        // TODO: make sure put_object takes an owned `PutObjectRequest` to avoid cloning the whole chunk
-        let bytes = arg.chunk.bytes.to_owned();
-        match self
-            .s3_client
-            .put_object()
-            .bucket(bucket_id)
-            .key(&arg.chunk.object_id)
-            .body(ByteStream::from(bytes))
-            .send()
-            .await
-        {
-            Ok(_) => Ok(PutObjectResponse::default()),
-            Err(e) => {
-                error!(
-                    error = %e,
-                    "Error putting object",
-                );
-                Err(RpcError::Other(e.to_string()))
-            }
+        self.policy
+            .check_object_write(bucket_id, &arg.chunk.object_id)?;
+        if !arg.chunk.is_last {
+            // the rest of the object will be sent with put_chunk
+            let stream_id = self.uploads.start(bucket_id, arg).await?;
+            return Ok(PutObjectResponse {
+                stream_id: Some(stream_id),
+                ..Default::default()
+            });
         }
+        self.backend.put_object(bucket_id, arg).await
     }
 
-    /// Retrieve object from s3 storage.
+    /// Retrieve object from storage.
     #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, bucket_id = %self.unalias(&arg.container_id), object_id = %arg.object_id, bytes_requested = tracing::field::Empty))]
     async fn get_object(
         &self,
@@ -675,10 +419,12 @@ impl Blobstore for StorageClient {
         arg: &blobstore::GetObjectRequest,
     ) -> RpcResult<GetObjectResponse> {
         let bucket_id = self.unalias(&arg.container_id);
+        self.policy.check_object(bucket_id, &arg.object_id)?;
         let max_chunk_size = self.max_chunk_size();
-        // If the object is not found, or not readable, get_object_metadata will return error.
+        // If the object is not found, or not readable, get_object_info will return error.
         let meta = self
-            .get_object_metadata(ctx, bucket_id, &arg.object_id)
+            .backend
+            .get_object_info(bucket_id, &arg.object_id)
             .await?;
         // calculate content_length requested, with error checking for range bounds
         let bytes_requested = match (arg.range_start, arg.range_end) {
@@ -699,6 +445,7 @@ impl Blobstore for StorageClient {
                 content_length: 0,
                 content_encoding: meta.content_encoding.clone(),
                 content_type: meta.content_type.clone(),
+                etag: meta.etag.clone(),
                 initial_chunk: Some(Chunk {
                     bytes: vec![],
                     container_id: bucket_id.to_string(),
@@ -711,114 +458,107 @@ impl Blobstore for StorageClient {
             });
         }
 
-        let get_object_req = self
-            .s3_client
-            .get_object()
-            .bucket(bucket_id)
-            .key(&arg.object_id)
-            .set_range(to_range_header(arg.range_start, arg.range_end));
-        match get_object_req.send().await {
-            Ok(mut object_output) => {
-                let len = object_output.content_length as u64;
-                if len > bytes_requested {
-                    // either the math is wrong above, or we misunderstood the api.
-                    // docs say content_length is "Size of the body in bytes"
-                    error!(
-                        %len,
-                        "requested {} bytes but more bytes were returned!",
-                        bytes_requested
-                    );
-                }
-                let mut bytes = match object_output.body.next().await {
-                    Some(Ok(bytes)) => {
-                        debug!(chunk_len = %bytes.len(), "initial chunk received");
-                        bytes
-                    }
-                    None => {
-                        error!("stream ended before getting first chunk from s3");
-                        return Err(RpcError::Other("no data received from s3".to_string()));
-                    }
-                    Some(Err(e)) => {
-                        error!(error = %e, "chunk.try_next returned error");
-                        return Err(RpcError::Other(e.to_string()));
-                    }
-                };
-                // determine if we need to stream additional chunks
-                let bytes = if (bytes.len() as u64) < bytes_requested
-                    || bytes.len() > max_chunk_size as usize
-                {
-                    debug!(
-                        chunk_len = %bytes.len(),
-                        "Beginning streaming response. Initial S3 chunk contains {} bytes out of {}",
-                        bytes.len(),
-                        bytes_requested,
-                    );
-                    let (bytes, excess) = if bytes.len() > max_chunk_size {
-                        let excess = bytes.split_off(max_chunk_size);
-                        (bytes, excess)
-                    } else {
-                        (bytes, Bytes::new())
-                    };
-                    // create task to deliver remaining chunks
-                    let offset = arg.range_start.unwrap_or(0) + bytes.len() as u64;
-                    self.stream_from_s3(
-                        ctx,
-                        ContainerObject {
-                            container_id: bucket_id.to_string(),
-                            object_id: arg.object_id.clone(),
-                        },
-                        excess.into(),
-                        offset,
-                        offset + bytes_requested,
-                        object_output.body,
-                    )
-                    .await;
-                    Vec::from(bytes)
-                } else {
-                    // no streaming required - everything in first chunk
-                    Vec::from(bytes)
-                };
-                // return first chunk
-                Ok(blobstore::GetObjectResponse {
-                    success: true,
-                    initial_chunk: Some(Chunk {
-                        is_last: (bytes.len() as u64) >= bytes_requested,
-                        bytes,
-                        container_id: bucket_id.to_string(),
-                        object_id: arg.object_id.clone(),
-                        offset: arg.range_start.unwrap_or(0),
-                    }),
-                    content_length: bytes_requested,
-                    content_type: object_output.content_type.clone(),
-                    content_encoding: object_output.content_encoding.clone(),
-                    error: None,
-                })
+        let range_start = arg.range_start.unwrap_or(0);
+        let range = (bytes_requested < meta.content_length)
+            .then(|| (range_start, range_start + bytes_requested - 1));
+        let mut body = self
+            .backend
+            .get_object(bucket_id, &arg.object_id, range)
+            .await?;
+        let mut bytes = match body.next().await {
+            Some(Ok(bytes)) => {
+                debug!(chunk_len = %bytes.len(), "initial chunk received");
+                bytes
             }
-            Err(e) => {
-                error!(
-                    error = %e,
-                    "Error when getting object"
-                );
-                Err(RpcError::Other(e.to_string()))
+            None => {
+                error!("stream ended before getting first chunk from storage");
+                return Err(RpcError::Other("no data received from storage".to_string()));
             }
-        }
+            Some(Err(e)) => {
+                error!(error = %e, "chunk.try_next returned error");
+                return Err(e);
+            }
+        };
+        // determine if we need to stream additional chunks
+        let bytes = if (bytes.len() as u64) < bytes_requested || bytes.len() > max_chunk_size {
+            debug!(
+                chunk_len = %bytes.len(),
+                "Beginning streaming response. Initial chunk contains {} bytes out of {}",
+                bytes.len(),
+                bytes_requested,
+            );
+            let (bytes, excess) = if bytes.len() > max_chunk_size {
+                let excess = bytes.split_off(max_chunk_size);
+                (bytes, excess)
+            } else {
+                (bytes, Bytes::new())
+            };
+            // create task to deliver remaining chunks
+            let offset = range_start + bytes.len() as u64;
+            self.stream_to_actor(
+                ctx,
+                ContainerObject {
+                    container_id: bucket_id.to_string(),
+                    object_id: arg.object_id.clone(),
+                },
+                excess.into(),
+                offset,
+                offset + bytes_requested,
+                body,
+            )
+            .await;
+            Vec::from(bytes)
+        } else {
+            // no streaming required - everything in first chunk
+            Vec::from(bytes)
+        };
+        // return first chunk
+        Ok(blobstore::GetObjectResponse {
+            success: true,
+            initial_chunk: Some(Chunk {
+                is_last: (bytes.len() as u64) >= bytes_requested,
+                bytes,
+                container_id: bucket_id.to_string(),
+                object_id: arg.object_id.clone(),
+                offset: range_start,
+            }),
+            content_length: bytes_requested,
+            content_type: meta.content_type,
+            content_encoding: meta.content_encoding,
+            etag: meta.etag,
+            error: None,
+        })
     }
 
-    async fn put_chunk(&self, _ctx: &Context, _arg: &PutChunkRequest) -> RpcResult<()> {
-        error!("put_chunk is unimplemented");
-        Err(RpcError::NotImplemented)
+    /// Add a chunk to an upload started with put_object
+    #[instrument(
+        level = "debug",
+        skip(self, _ctx, arg),
+        fields(actor_id = ?_ctx.actor, bucket_id = %self.unalias(&arg.chunk.container_id), object_id = %arg.chunk.object_id, offset = %arg.chunk.offset, is_last = %arg.chunk.is_last)
+    )]
+    async fn put_chunk(&self, _ctx: &Context, arg: &PutChunkRequest) -> RpcResult<()> {
+        let stream_id = arg.stream_id.as_deref().ok_or_else(|| {
+            RpcError::InvalidParameter("Chunked storage is missing stream id".to_string())
+        })?;
+        if arg.cancel_and_remove {
+            // the object is only created when the upload completes, so there is nothing to remove
+            return self.uploads.cancel(stream_id).await;
+        }
+        let bucket_id = self.unalias(&arg.chunk.container_id);
+        self.policy
+            .check_object_write(bucket_id, &arg.chunk.object_id)?;
+        self.uploads
+            .put_chunk(stream_id, bucket_id, &arg.chunk)
+            .await
+            .map(|_| ())
     }
-}
 
-/// translate optional s3 DateTime to optional Timestamp.
-/// Invalid times return None.
-fn to_timestamp(dt: Option<aws_sdk_s3::types::DateTime>) -> Option<wasmbus_rpc::Timestamp> {
-    match dt {
-        Some(dt) => match wasmbus_rpc::Timestamp::new(dt.secs(), dt.subsec_nanos()) {
-            Ok(t) => Some(t),
-            Err(_) => None,
-        },
-        None => None,
+    /// Place or remove a legal hold on an object
+    #[instrument(level = "debug", skip(self, _ctx, arg), fields(actor_id = ?_ctx.actor, bucket_id = %self.unalias(&arg.container_id), object_id = %arg.object_id, enabled = %arg.enabled))]
+    async fn set_legal_hold(&self, _ctx: &Context, arg: &LegalHoldRequest) -> RpcResult<()> {
+        let bucket_id = self.unalias(&arg.container_id);
+        self.policy.check_object_write(bucket_id, &arg.object_id)?;
+        self.backend.set_legal_hold(bucket_id, arg).await
     }
 }
 
@@ -853,41 +593,10 @@ fn validate_bucket_name(bucket: &str) -> Result<(), &'static str> {
     Ok(())
 }
 
-/// convert optional start/end to an http range request header value
-/// If end is before start, the range is invalid, and per spec (https://www.w3.org/Protocols/rfc2616/rfc2616-sec14.html#sec14.35),
-/// the range will be ignored.
-/// If end is specified and start is None, start value of 0 is used. (Otherwise "bytes=-x" is interpreted as the last x bytes)
-fn to_range_header(start: Option<u64>, end: Option<u64>) -> Option<String> {
-    match (start, end) {
-        (Some(start), Some(end)) if start <= end => Some(format!("bytes={}-{}", start, end)),
-        (Some(start), None) => Some(format!("bytes={}-", start)),
-        (None, Some(end)) => Some(format!("bytes=0-{}", end)),
-        _ => None,
-    }
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
 
-    #[test]
-    fn range_header() {
-        assert_eq!(
-            to_range_header(Some(1), Some(99)),
-            Some("bytes=1-99".to_string())
-        );
-        assert_eq!(to_range_header(Some(10), Some(5)), None);
-        assert_eq!(
-            to_range_header(None, Some(99)),
-            Some("bytes=0-99".to_string())
-        );
-        assert_eq!(
-            to_range_header(Some(99), None),
-            Some("bytes=99-".to_string())
-        );
-        assert_eq!(to_range_header(None, None), None);
-    }
-
     #[test]
     fn bucket_name() {
         assert!(validate_bucket_name("ok").is_err(), "too short");
@@ -918,7 +627,9 @@ mod test {
         map.insert(format!("{}foo", ALIAS_PREFIX), "bar".to_string());
         let mut ld = LinkDefinition::default();
         ld.values = map;
-        let client = StorageClient::new(StorageConfig::default(), ld).await;
+        let client = StorageClient::new(StorageConfig::default(), ld)
+            .await
+            .expect("s3 client");
 
         // no alias
         assert_eq!(client.unalias("boo"), "boo");
@@ -929,4 +640,39 @@ mod test {
         // undefined alias
         assert_eq!(client.unalias(&format!("{}baz", ALIAS_PREFIX)), "baz");
     }
+
+    #[tokio::test]
+    async fn read_only_link() {
+        let mut ld = LinkDefinition::default();
+        ld.values
+            .insert("read_only".to_string(), "true".to_string());
+        let client = StorageClient::new(StorageConfig::default(), ld)
+            .await
+            .expect("s3 client");
+        let ctx = Context::default();
+
+        // rejected by the link policy, so no request is sent to s3
+        let err = client
+            .put_object(
+                &ctx,
+                &blobstore::PutObjectRequest {
+                    chunk: Chunk {
+                        container_id: "bucket".to_string(),
+                        object_id: "object".to_string(),
+                        bytes: b"data".to_vec(),
+                        offset: 0,
+                        is_last: true,
+                    },
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect_err("read-only");
+        assert!(err.to_string().contains(ACCESS_DENIED));
+        let err = client
+            .remove_containers(&ctx, &vec!["bucket".to_string()])
+            .await
+            .expect_err("read-only");
+        assert!(err.to_string().contains(ACCESS_DENIED));
+    }
 }
@@ -4,23 +4,26 @@
 use blobstore_s3_lib::{
     wasmcloud_interface_blobstore::{
         Blobstore, BlobstoreReceiver, ContainerId, ContainerIds, ContainerMetadata,
-        ContainerObject, ContainersInfo, GetObjectRequest, GetObjectResponse, ListObjectsRequest,
-        ListObjectsResponse, MultiResult, ObjectMetadata, PutChunkRequest, PutObjectRequest,
-        PutObjectResponse, RemoveObjectsRequest,
+        ContainerObject, ContainersInfo, GetObjectRequest, GetObjectResponse, LegalHoldRequest,
+        ListObjectsRequest, ListObjectsResponse, MultiResult, ObjectMetadata, PutChunkRequest,
+        PutObjectRequest, PutObjectResponse, RemoveObjectsRequest,
     },
-    StorageClient, StorageConfig,
+    StorageClient, StorageConfig, UploadLimiter,
 };
 use std::{collections::HashMap, convert::Infallible, sync::Arc};
 use tokio::sync::RwLock;
-use wasmbus_rpc::{core::LinkDefinition, provider::prelude::*};
+use wasmbus_rpc::{
+    core::{HealthCheckRequest, HealthCheckResponse, LinkDefinition},
+    provider::prelude::*,
+};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let provider = S3BlobstoreProvider::default();
+    provider.upload_limiter.register_metrics();
+
     // handle lattice control messages and forward rpc to the provider dispatch
     // returns when provider receives a shutdown control message
-    provider_main(
-        S3BlobstoreProvider::default(),
-        Some("Blobstore S3 Provider".to_string()),
-    )?;
+    provider_main(provider, Some("Blobstore S3 Provider".to_string()))?;
 
     eprintln!("blobstore-s3 provider exiting");
     Ok(())
@@ -31,6 +34,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 struct S3BlobstoreProvider {
     // store nats connection client per actor
     actors: Arc<RwLock<HashMap<String, StorageClient>>>,
+    // limits memory used by chunked uploads from all actors
+    upload_limiter: UploadLimiter,
 }
 
 // use default implementations of provider message handlers
@@ -60,7 +65,9 @@ impl ProviderHandler for S3BlobstoreProvider {
     /// If the link is allowed, return true, otherwise return false to deny the link.
     async fn put_link(&self, ld: &LinkDefinition) -> RpcResult<bool> {
         let config = StorageConfig::from_values(&ld.values)?;
-        let link = StorageClient::new(config, ld.to_owned()).await;
+        let link = StorageClient::new(config, ld.to_owned())
+            .await?
+            .with_upload_limiter(self.upload_limiter.clone());
 
         let mut update_map = self.actors.write().await;
         update_map.insert(ld.actor_id.to_string(), link);
@@ -68,6 +75,18 @@ impl ProviderHandler for S3BlobstoreProvider {
         Ok(true)
     }
 
+    /// Report health, with the number of bytes buffered and reserved for uploads in progress
+    async fn health_request(&self, _arg: &HealthCheckRequest) -> RpcResult<HealthCheckResponse> {
+        Ok(HealthCheckResponse {
+            healthy: true,
+            message: Some(format!(
+                "upload_bytes_in_flight={} upload_bytes_reserved={}",
+                self.upload_limiter.in_flight_bytes(),
+                self.upload_limiter.reserved_bytes()
+            )),
+        })
+    }
+
     /// Handle notification that a link is dropped: close the connection
     async fn delete_link(&self, actor_id: &str) {
         let mut aw = self.actors.write().await;
@@ -177,4 +196,9 @@ impl Blobstore for S3BlobstoreProvider {
         let client = self.client(ctx).await?;
         client.put_chunk(ctx, arg).await
     }
+
+    async fn set_legal_hold(&self, ctx: &Context, arg: &LegalHoldRequest) -> RpcResult<()> {
+        let client = self.client(ctx).await?;
+        client.set_legal_hold(ctx, arg).await
+    }
 }
@@ -0,0 +1,226 @@
+//! Per-link access policy: bucket allowlist and read-only mode
+//!
+//! The policy is checked by `StorageClient` after bucket aliases are resolved,
+//! and before any request is sent to the storage backend.
+
+use std::collections::HashMap;
+
+use wasmbus_rpc::error::{RpcError, RpcResult};
+
+use crate::StorageConfig;
+
+/// link value with a comma-separated list of allowed buckets
+const ALLOWED_BUCKETS_KEY: &str = "allowed_buckets";
+/// link value that makes the link read-only
+const READ_ONLY_KEY: &str = "read_only";
+
+/// One allowlist entry: a bucket, optionally limited to objects whose names begin with a prefix
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct AllowedBucket {
+    bucket: String,
+    prefix: Option<String>,
+}
+
+impl AllowedBucket {
+    /// parse "bucket" or "bucket/prefix"
+    fn parse(entry: &str) -> RpcResult<Self> {
+        let (bucket, prefix) = match entry.trim().split_once('/') {
+            Some((bucket, prefix)) => (bucket, (!prefix.is_empty()).then(|| prefix.to_string())),
+            None => (entry.trim(), None),
+        };
+        if bucket.is_empty() {
+            return Err(RpcError::InvalidParameter(format!(
+                "invalid allowed_buckets entry '{}': bucket name must not be empty",
+                entry
+            )));
+        }
+        Ok(AllowedBucket {
+            bucket: bucket.to_string(),
+            prefix,
+        })
+    }
+
+    fn allows_object(&self, object_id: &str) -> bool {
+        match &self.prefix {
+            Some(prefix) => object_id.starts_with(prefix.as_str()),
+            None => true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LinkPolicy {
+    /// allowed buckets and prefixes. If empty, all buckets are allowed
+    allowed: Vec<AllowedBucket>,
+    /// reject all requests that create, modify, or remove buckets or objects
+    read_only: bool,
+}
+
+impl LinkPolicy {
+    /// Build the policy from json config settings, plus the link values
+    /// `allowed_buckets` (comma-separated) and `read_only`
+    pub(crate) fn new(config: &StorageConfig, values: &HashMap<String, String>) -> RpcResult<Self> {
+        let mut allowed = config
+            .allowed_buckets
+            .iter()
+            .map(|entry| AllowedBucket::parse(entry))
+            .collect::<RpcResult<Vec<_>>>()?;
+        if let Some(list) = values.get(ALLOWED_BUCKETS_KEY) {
+            for entry in list.split(',').filter(|e| !e.trim().is_empty()) {
+                allowed.push(AllowedBucket::parse(entry)?);
+            }
+        }
+        let read_only = match values.get(READ_ONLY_KEY) {
+            Some(val) => val.parse::<bool>().map_err(|_| {
+                RpcError::InvalidParameter(format!(
+                    "invalid read_only value '{}': must be true or false",
+                    val
+                ))
+            })?,
+            None => config.read_only,
+        };
+        Ok(LinkPolicy { allowed, read_only })
+    }
+
+    /// Returns true if the bucket, or some prefix within it, is allowed
+    pub(crate) fn allows_bucket(&self, bucket_id: &str) -> bool {
+        self.allowed.is_empty() || self.allowed.iter().any(|a| a.bucket == bucket_id)
+    }
+
+    /// Returns true if the object is in an allowed bucket and prefix
+    pub(crate) fn allows_object(&self, bucket_id: &str, object_id: &str) -> bool {
+        self.allowed.is_empty()
+            || self
+                .allowed
+                .iter()
+                .any(|a| a.bucket == bucket_id && a.allows_object(object_id))
+    }
+
+    /// Checks read access to a bucket
+    pub(crate) fn check_bucket(&self, bucket_id: &str) -> RpcResult<()> {
+        if self.allows_bucket(bucket_id) {
+            Ok(())
+        } else {
+            Err(denied(format!("Bucket({}) is not allowed", bucket_id)))
+        }
+    }
+
+    /// Checks read access to an object
+    pub(crate) fn check_object(&self, bucket_id: &str, object_id: &str) -> RpcResult<()> {
+        if self.allows_object(bucket_id, object_id) {
+            Ok(())
+        } else {
+            Err(denied(format!(
+                "Bucket({}) Object({}) is not allowed",
+                bucket_id, object_id
+            )))
+        }
+    }
+
+    /// Checks that a bucket may be created or removed. Buckets restricted to a prefix
+    /// may not be created or removed
+    pub(crate) fn check_bucket_write(&self, bucket_id: &str) -> RpcResult<()> {
+        self.check_writable()?;
+        if self.allowed.is_empty()
+            || self
+                .allowed
+                .iter()
+                .any(|a| a.bucket == bucket_id && a.prefix.is_none())
+        {
+            Ok(())
+        } else {
+            Err(denied(format!(
+                "Bucket({}) may not be created or removed",
+                bucket_id
+            )))
+        }
+    }
+
+    /// Checks that an object may be written or removed
+    pub(crate) fn check_object_write(&self, bucket_id: &str, object_id: &str) -> RpcResult<()> {
+        self.check_writable()?;
+        self.check_object(bucket_id, object_id)
+    }
+
+    fn check_writable(&self) -> RpcResult<()> {
+        if self.read_only {
+            Err(denied("link is read-only".to_string()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Error returned when a request is rejected by the link policy
+fn denied(msg: String) -> RpcError {
+    RpcError::Other(format!("{}: {}", crate::ACCESS_DENIED, msg))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn policy(allowed: &[&str], read_only: bool) -> LinkPolicy {
+        let config = StorageConfig {
+            allowed_buckets: allowed.iter().map(|s| s.to_string()).collect(),
+            read_only,
+            ..Default::default()
+        };
+        LinkPolicy::new(&config, &HashMap::new()).unwrap()
+    }
+
+    #[test]
+    fn unrestricted() {
+        let p = policy(&[], false);
+        assert!(p.check_bucket("any").is_ok());
+        assert!(p.check_bucket_write("any").is_ok());
+        assert!(p.check_object_write("any", "thing").is_ok());
+    }
+
+    #[test]
+    fn allowlist() {
+        let p = policy(&["images", "logs/app1/"], false);
+        assert!(p.check_bucket("images").is_ok());
+        assert!(p.check_bucket("logs").is_ok());
+        assert!(p.check_bucket("other").is_err());
+
+        assert!(p.check_object("images", "a.png").is_ok());
+        assert!(p.check_object("logs", "app1/today").is_ok());
+        assert!(p.check_object("logs", "app2/today").is_err());
+
+        assert!(p.check_bucket_write("images").is_ok());
+        assert!(p.check_bucket_write("logs").is_err(), "prefix only");
+        assert!(p.check_object_write("logs", "app1/x").is_ok());
+        assert!(p.check_object_write("other", "x").is_err());
+
+        let err = p.check_bucket("other").unwrap_err();
+        assert!(err.to_string().contains(crate::ACCESS_DENIED));
+    }
+
+    #[test]
+    fn read_only() {
+        let p = policy(&["images"], true);
+        assert!(p.check_object("images", "a.png").is_ok());
+        assert!(p.check_object_write("images", "a.png").is_err());
+        assert!(p.check_bucket_write("images").is_err());
+    }
+
+    #[test]
+    fn link_values() {
+        let mut values = HashMap::new();
+        values.insert(ALLOWED_BUCKETS_KEY.to_string(), "a, b/x/,".to_string());
+        values.insert(READ_ONLY_KEY.to_string(), "true".to_string());
+        let p = LinkPolicy::new(&StorageConfig::default(), &values).unwrap();
+        assert!(p.check_bucket("a").is_ok());
+        assert!(p.check_object("b", "x/1").is_ok());
+        assert!(p.check_object("b", "y/1").is_err());
+        assert!(p.check_object_write("a", "1").is_err());
+
+        values.insert(READ_ONLY_KEY.to_string(), "yes".to_string());
+        assert!(LinkPolicy::new(&StorageConfig::default(), &values).is_err());
+
+        values.insert(READ_ONLY_KEY.to_string(), "false".to_string());
+        values.insert(ALLOWED_BUCKETS_KEY.to_string(), "/prefix".to_string());
+        assert!(LinkPolicy::new(&StorageConfig::default(), &values).is_err());
+    }
+}
@@ -0,0 +1,649 @@
+//! Streaming uploads from actor chunks
+//!
+//! An upload starts with a `PutObject` whose chunk is not the last, and continues with `PutChunk`
+//! requests carrying the returned `streamId`. Chunks are buffered only until there is enough
+//! data for a part, which is then sent to the backend, so the memory used by an upload is bounded
+//! by the part size. An [UploadLimiter] shared by all links bounds the total bytes buffered by
+//! the provider: each upload reserves a whole part when it starts, so uploads in progress can
+//! always fill and send their parts, and new uploads wait until there is room for another part.
+//! Uploads that receive no chunks for [UPLOAD_IDLE_TIMEOUT] are aborted, returning their space.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Weak,
+};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use bytes::{Bytes, BytesMut};
+use tokio::sync::{Mutex, OwnedSemaphorePermit, RwLock, Semaphore};
+use tracing::{debug, error, warn};
+use wasmbus_rpc::error::{RpcError, RpcResult};
+
+use crate::backend::Backend;
+
+/// default limit on the bytes buffered for uploads by all links (256MB)
+const DEFAULT_MAX_UPLOAD_BUFFER: usize = 256 * 1024 * 1024;
+
+/// smallest part size accepted by s3 for all parts except the last (5MB)
+pub(crate) const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+
+/// how long a new upload waits for buffer space before the request fails
+const BUFFER_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// uploads that receive no chunks for this long are aborted
+pub const UPLOAD_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Limits the memory used by uploads in progress, and tracks the number of bytes in flight
+#[derive(Clone)]
+pub struct UploadLimiter {
+    permits: Arc<Semaphore>,
+    max_bytes: usize,
+    in_flight: Arc<AtomicU64>,
+}
+
+impl Default for UploadLimiter {
+    /// Create a limiter with the limit in the environment variable MAX_UPLOAD_BUFFER (bytes),
+    /// or 256MB if it is not set
+    fn default() -> Self {
+        let max_bytes = std::env::var("MAX_UPLOAD_BUFFER")
+            .ok()
+            .and_then(|var| var.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_MAX_UPLOAD_BUFFER);
+        UploadLimiter::new(max_bytes)
+    }
+}
+
+impl UploadLimiter {
+    /// Create a limiter that allows up to `max_bytes` to be buffered
+    pub fn new(max_bytes: usize) -> Self {
+        let max_bytes = max_bytes.min(Semaphore::MAX_PERMITS);
+        UploadLimiter {
+            permits: Arc::new(Semaphore::new(max_bytes)),
+            max_bytes,
+            in_flight: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Number of bytes received from actors that have not yet been sent to storage
+    pub fn in_flight_bytes(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Number of bytes reserved by uploads in progress
+    pub fn reserved_bytes(&self) -> u64 {
+        (self.max_bytes - self.permits.available_permits()) as u64
+    }
+
+    /// Report the bytes in flight and reserved as the gauges `upload_bytes_in_flight`
+    /// and `upload_bytes_reserved` of the global OpenTelemetry meter
+    pub fn register_metrics(&self) {
+        let meter = opentelemetry::global::meter("blobstore-s3");
+        let limiter = self.clone();
+        meter
+            .u64_value_observer("upload_bytes_in_flight", move |result| {
+                result.observe(limiter.in_flight_bytes(), &[])
+            })
+            .with_description("bytes received for uploads and not yet sent to storage")
+            .init();
+        let limiter = self.clone();
+        meter
+            .u64_value_observer("upload_bytes_reserved", move |result| {
+                result.observe(limiter.reserved_bytes(), &[])
+            })
+            .with_description("bytes reserved for the buffers of uploads in progress")
+            .init();
+    }
+
+    /// reserve buffer space for one upload, waiting for other uploads to finish if necessary.
+    /// The space is returned when the permit is dropped
+    async fn reserve(&self, len: u64) -> RpcResult<OwnedSemaphorePermit> {
+        let len = u32::try_from(len)
+            .ok()
+            .filter(|len| *len as usize <= self.max_bytes)
+            .ok_or_else(|| {
+                RpcError::InvalidParameter(format!(
+                    "upload part size {} is larger than the upload buffer limit {}",
+                    len, self.max_bytes
+                ))
+            })?;
+        let acquire = self.permits.clone().acquire_many_owned(len);
+        match tokio::time::timeout(BUFFER_WAIT_TIMEOUT, acquire).await {
+            Ok(Ok(permit)) => {
+                debug!(
+                    reserved_bytes = self.reserved_bytes(),
+                    "upload buffer reserved"
+                );
+                Ok(permit)
+            }
+            Ok(Err(_)) => Err(RpcError::Other("upload limiter closed".to_string())),
+            Err(_) => {
+                error!(
+                    reserved_bytes = self.reserved_bytes(),
+                    in_flight_bytes = self.in_flight_bytes(),
+                    "timed out waiting for upload buffer space"
+                );
+                Err(RpcError::Timeout(
+                    "upload buffer is full, try again later".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// count bytes added to an upload's buffer
+    fn buffered(&self, len: usize) {
+        if len > 0 {
+            let in_flight = self.in_flight.fetch_add(len as u64, Ordering::Relaxed) + len as u64;
+            debug!(in_flight_bytes = in_flight, "upload bytes buffered");
+        }
+    }
+
+    /// count bytes that have been sent or discarded
+    fn release(&self, len: usize) {
+        if len > 0 {
+            let in_flight = self.in_flight.fetch_sub(len as u64, Ordering::Relaxed) - len as u64;
+            debug!(in_flight_bytes = in_flight, "upload bytes released");
+        }
+    }
+}
+
+/// State of one upload in progress
+struct Upload {
+    bucket_id: String,
+    object_id: String,
+    /// id of the backend's multipart upload
+    upload_id: String,
+    /// offset of the next chunk expected from the actor
+    next_offset: u64,
+    /// bytes received and not yet sent as a part
+    buffer: BytesMut,
+    /// part numbers and entity tags of parts sent
+    parts: Vec<(i32, String)>,
+    /// time the last chunk was received
+    last_active: Instant,
+    /// set when the upload has been aborted for being idle
+    expired: bool,
+    limiter: UploadLimiter,
+    /// buffer space reserved for one part, returned when the upload is dropped
+    _reservation: OwnedSemaphorePermit,
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        self.limiter.release(self.buffer.len());
+    }
+}
+
+type Streams = RwLock<HashMap<String, Arc<Mutex<Upload>>>>;
+
+/// Uploads in progress for one link, indexed by stream id
+#[derive(Clone)]
+pub(crate) struct Uploads {
+    backend: Arc<dyn Backend>,
+    limiter: UploadLimiter,
+    part_size: u64,
+    streams: Arc<Streams>,
+}
+
+impl Uploads {
+    pub(crate) fn new(backend: Arc<dyn Backend>, limiter: UploadLimiter, part_size: u64) -> Self {
+        Self::with_idle_timeout(backend, limiter, part_size, UPLOAD_IDLE_TIMEOUT)
+    }
+
+    fn with_idle_timeout(
+        backend: Arc<dyn Backend>,
+        limiter: UploadLimiter,
+        part_size: u64,
+        idle_timeout: Duration,
+    ) -> Self {
+        let uploads = Uploads {
+            backend,
+            limiter,
+            part_size,
+            streams: Arc::new(RwLock::new(HashMap::new())),
+        };
+        tokio::spawn(expire_idle(
+            Arc::downgrade(&uploads.streams),
+            uploads.backend.clone(),
+            idle_timeout,
+        ));
+        uploads
+    }
+
+    pub(crate) fn set_limiter(&mut self, limiter: UploadLimiter) {
+        self.limiter = limiter;
+    }
+
+    /// Begins an upload with the first chunk, and returns its stream id
+    pub(crate) async fn start(
+        &self,
+        bucket_id: &str,
+        arg: &crate::wasmcloud_interface_blobstore::PutObjectRequest,
+    ) -> RpcResult<String> {
+        self.check_chunk(&arg.chunk.bytes)?;
+        let reservation = self.limiter.reserve(self.part_size).await?;
+        let upload_id = self.backend.start_upload(bucket_id, arg).await?;
+        let upload = Upload {
+            bucket_id: bucket_id.to_string(),
+            object_id: arg.chunk.object_id.clone(),
+            upload_id: upload_id.clone(),
+            next_offset: 0,
+            buffer: BytesMut::new(),
+            parts: Vec::new(),
+            last_active: Instant::now(),
+            expired: false,
+            limiter: self.limiter.clone(),
+            _reservation: reservation,
+        };
+        // the backend's upload id is unique, so it is also used as the stream id
+        let upload = Arc::new(Mutex::new(upload));
+        self.streams
+            .write()
+            .await
+            .insert(upload_id.clone(), upload.clone());
+        let mut upload = upload.lock().await;
+        if let Err(e) = self.append(&mut upload, &arg.chunk.bytes, false).await {
+            self.discard(&upload_id, &mut upload).await;
+            return Err(e);
+        }
+        Ok(upload_id)
+    }
+
+    /// Adds a chunk to an upload. If the chunk is the last, the upload is completed
+    /// and the entity tag of the object is returned
+    pub(crate) async fn put_chunk(
+        &self,
+        stream_id: &str,
+        bucket_id: &str,
+        chunk: &crate::wasmcloud_interface_blobstore::Chunk,
+    ) -> RpcResult<Option<String>> {
+        let upload = self.get(stream_id).await?;
+        let mut upload = upload.lock().await;
+        if upload.expired {
+            return Err(no_upload(stream_id));
+        }
+        if upload.bucket_id != bucket_id || upload.object_id != chunk.object_id {
+            return Err(RpcError::InvalidParameter(format!(
+                "stream {} is for Bucket({}) Object({})",
+                stream_id, upload.bucket_id, upload.object_id
+            )));
+        }
+        if chunk.offset != upload.next_offset {
+            return Err(RpcError::InvalidParameter(format!(
+                "Chunk offset {} not the same as the expected offset: {}",
+                chunk.offset, upload.next_offset
+            )));
+        }
+        self.check_chunk(&chunk.bytes)?;
+        let result = match self.append(&mut upload, &chunk.bytes, chunk.is_last).await {
+            Ok(()) if chunk.is_last => self.complete(&mut upload).await,
+            Ok(()) => return Ok(None),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(etag) => {
+                self.streams.write().await.remove(stream_id);
+                Ok(etag)
+            }
+            Err(e) => {
+                self.discard(stream_id, &mut upload).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Cancels an upload, discarding any parts already sent
+    pub(crate) async fn cancel(&self, stream_id: &str) -> RpcResult<()> {
+        let upload = self.get(stream_id).await?;
+        let mut upload = upload.lock().await;
+        self.discard(stream_id, &mut upload).await;
+        Ok(())
+    }
+
+    /// Cancels all uploads in progress
+    pub(crate) async fn cancel_all(&self) {
+        let streams: Vec<String> = self.streams.read().await.keys().cloned().collect();
+        for stream_id in streams.iter() {
+            let _ = self.cancel(stream_id).await;
+        }
+    }
+
+    async fn get(&self, stream_id: &str) -> RpcResult<Arc<Mutex<Upload>>> {
+        self.streams
+            .read()
+            .await
+            .get(stream_id)
+            .cloned()
+            .ok_or_else(|| no_upload(stream_id))
+    }
+
+    /// chunks larger than a part would not fit in the space reserved for the upload
+    fn check_chunk(&self, bytes: &[u8]) -> RpcResult<()> {
+        if bytes.len() as u64 > self.part_size {
+            return Err(RpcError::InvalidParameter(format!(
+                "chunk of {} bytes is larger than the upload part size {}",
+                bytes.len(),
+                self.part_size
+            )));
+        }
+        Ok(())
+    }
+
+    /// add bytes to the buffer, sending a part whenever the buffer is full,
+    /// so the buffer never holds more than the part size reserved for it
+    async fn append(&self, upload: &mut Upload, bytes: &[u8], is_last: bool) -> RpcResult<()> {
+        upload.last_active = Instant::now();
+        upload.next_offset += bytes.len() as u64;
+        let room = (self.part_size as usize).saturating_sub(upload.buffer.len());
+        let (head, tail) = bytes.split_at(room.min(bytes.len()));
+        upload.buffer.extend_from_slice(head);
+        upload.limiter.buffered(head.len());
+        if upload.buffer.len() as u64 >= self.part_size && (!tail.is_empty() || !is_last) {
+            self.flush(upload).await?;
+        }
+        upload.buffer.extend_from_slice(tail);
+        upload.limiter.buffered(tail.len());
+        Ok(())
+    }
+
+    /// send the buffered bytes as the next part
+    async fn flush(&self, upload: &mut Upload) -> RpcResult<()> {
+        let bytes: Bytes = upload.buffer.split().freeze();
+        let len = bytes.len();
+        let part_number = upload.parts.len() as i32 + 1;
+        let result = self
+            .backend
+            .upload_part(
+                &upload.bucket_id,
+                &upload.object_id,
+                &upload.upload_id,
+                part_number,
+                bytes,
+            )
+            .await;
+        upload.limiter.release(len);
+        upload.parts.push((part_number, result?));
+        debug!(
+            upload_id = %upload.upload_id,
+            part_number,
+            part_len = len,
+            "sent upload part"
+        );
+        Ok(())
+    }
+
+    async fn complete(&self, upload: &mut Upload) -> RpcResult<Option<String>> {
+        if !upload.buffer.is_empty() || upload.parts.is_empty() {
+            self.flush(upload).await?;
+        }
+        self.backend
+            .complete_upload(
+                &upload.bucket_id,
+                &upload.object_id,
+                &upload.upload_id,
+                &upload.parts,
+            )
+            .await
+    }
+
+    /// remove the upload, free its buffer, and abort it in the backend
+    async fn discard(&self, stream_id: &str, upload: &mut Upload) {
+        self.streams.write().await.remove(stream_id);
+        abort(self.backend.as_ref(), upload).await;
+    }
+}
+
+fn no_upload(stream_id: &str) -> RpcError {
+    RpcError::InvalidParameter(format!("no upload in progress for stream {}", stream_id))
+}
+
+/// free the upload's buffer and abort it in the backend
+async fn abort(backend: &dyn Backend, upload: &mut Upload) {
+    upload.limiter.release(upload.buffer.len());
+    upload.buffer.clear();
+    if let Err(e) = backend
+        .abort_upload(&upload.bucket_id, &upload.object_id, &upload.upload_id)
+        .await
+    {
+        error!(error = %e, upload_id = %upload.upload_id, "unable to abort upload");
+    }
+}
+
+/// Periodically aborts uploads that have received no chunks for `idle_timeout`, so that
+/// actors that never send their last chunk don't keep buffer space reserved.
+/// Returns when the link's uploads are dropped
+async fn expire_idle(streams: Weak<Streams>, backend: Arc<dyn Backend>, idle_timeout: Duration) {
+    let mut interval = tokio::time::interval(idle_timeout / 2);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let streams = match streams.upgrade() {
+            Some(streams) => streams,
+            None => break,
+        };
+        let uploads: Vec<(String, Arc<Mutex<Upload>>)> = streams
+            .read()
+            .await
+            .iter()
+            .map(|(id, upload)| (id.clone(), upload.clone()))
+            .collect();
+        for (stream_id, upload) in uploads {
+            // uploads locked by a request are not idle
+            let mut upload = match upload.try_lock() {
+                Ok(upload) if upload.last_active.elapsed() >= idle_timeout => upload,
+                _ => continue,
+            };
+            warn!(
+                upload_id = %upload.upload_id,
+                bucket = %upload.bucket_id,
+                object = %upload.object_id,
+                "aborting idle upload"
+            );
+            upload.expired = true;
+            streams.write().await.remove(&stream_id);
+            abort(backend.as_ref(), &mut upload).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use wasmbus_rpc::error::{RpcError, RpcResult};
+
+    use super::{UploadLimiter, Uploads};
+    use crate::backend::{Backend, ByteStream};
+    use crate::wasmcloud_interface_blobstore::{
+        Chunk, ContainerMetadata, ContainersInfo, ListObjectsRequest, ListObjectsResponse,
+        MultiResult, ObjectMetadata, PutObjectRequest, PutObjectResponse,
+    };
+
+    /// backend that accepts multipart uploads and discards their parts
+    struct NullBackend;
+
+    #[async_trait]
+    impl Backend for NullBackend {
+        async fn container_exists(&self, _: &str) -> RpcResult<bool> {
+            Err(RpcError::NotImplemented)
+        }
+        async fn create_container(&self, _: &str) -> RpcResult<()> {
+            Err(RpcError::NotImplemented)
+        }
+        async fn get_container_info(&self, _: &str) -> RpcResult<ContainerMetadata> {
+            Err(RpcError::NotImplemented)
+        }
+        async fn list_containers(&self) -> RpcResult<ContainersInfo> {
+            Err(RpcError::NotImplemented)
+        }
+        async fn remove_containers(&self, _: &[&str]) -> RpcResult<MultiResult> {
+            Err(RpcError::NotImplemented)
+        }
+        async fn object_exists(&self, _: &str, _: &str) -> RpcResult<bool> {
+            Err(RpcError::NotImplemented)
+        }
+        async fn get_object_info(&self, _: &str, _: &str) -> RpcResult<ObjectMetadata> {
+            Err(RpcError::NotImplemented)
+        }
+        async fn list_objects(
+            &self,
+            _: &str,
+            _: &ListObjectsRequest,
+        ) -> RpcResult<ListObjectsResponse> {
+            Err(RpcError::NotImplemented)
+        }
+        async fn remove_objects(&self, _: &str, _: &[String]) -> RpcResult<MultiResult> {
+            Err(RpcError::NotImplemented)
+        }
+        async fn put_object(&self, _: &str, _: &PutObjectRequest) -> RpcResult<PutObjectResponse> {
+            Err(RpcError::NotImplemented)
+        }
+        async fn get_object(
+            &self,
+            _: &str,
+            _: &str,
+            _: Option<(u64, u64)>,
+        ) -> RpcResult<ByteStream> {
+            Err(RpcError::NotImplemented)
+        }
+        async fn start_upload(&self, _: &str, arg: &PutObjectRequest) -> RpcResult<String> {
+            Ok(arg.chunk.object_id.clone())
+        }
+        async fn upload_part(
+            &self,
+            _: &str,
+            _: &str,
+            _: &str,
+            n: i32,
+            _: Bytes,
+        ) -> RpcResult<String> {
+            Ok(n.to_string())
+        }
+        async fn complete_upload(
+            &self,
+            _: &str,
+            _: &str,
+            _: &str,
+            _: &[(i32, String)],
+        ) -> RpcResult<Option<String>> {
+            Ok(Some("etag".to_string()))
+        }
+        async fn abort_upload(&self, _: &str, _: &str, _: &str) -> RpcResult<()> {
+            Ok(())
+        }
+    }
+
+    fn chunk(object_id: &str, offset: u64, len: usize, is_last: bool) -> Chunk {
+        Chunk {
+            object_id: object_id.to_string(),
+            container_id: "bucket".to_string(),
+            bytes: vec![0u8; len],
+            offset,
+            is_last,
+        }
+    }
+
+    fn put(object_id: &str, len: usize) -> PutObjectRequest {
+        PutObjectRequest {
+            chunk: chunk(object_id, 0, len, false),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn limiter() {
+        let limiter = UploadLimiter::new(100);
+        let first = limiter.reserve(60).await.unwrap();
+        let _second = limiter.reserve(40).await.unwrap();
+        assert_eq!(limiter.reserved_bytes(), 100);
+        assert!(limiter.permits.try_acquire().is_err(), "limit reached");
+
+        drop(first);
+        assert_eq!(limiter.reserved_bytes(), 40);
+        let _third = limiter.reserve(50).await.unwrap();
+        assert_eq!(limiter.reserved_bytes(), 90);
+
+        // a part that can never fit is rejected without waiting
+        assert!(matches!(
+            limiter.reserve(101).await,
+            Err(RpcError::InvalidParameter(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn uploads_fill_parts_within_reservation() {
+        let limiter = UploadLimiter::new(100);
+        let uploads = Uploads::new(Arc::new(NullBackend), limiter.clone(), 10);
+
+        // each upload reserves a part, and buffers just under a part
+        let mut streams = Vec::new();
+        for n in 0..10 {
+            let object_id = format!("obj{}", n);
+            streams.push((
+                uploads.start("bucket", &put(&object_id, 9)).await.unwrap(),
+                object_id,
+            ));
+        }
+        assert_eq!(limiter.reserved_bytes(), 100);
+        assert_eq!(limiter.in_flight_bytes(), 90);
+
+        // uploads in progress can still fill and send their parts
+        for (stream_id, object_id) in streams.iter() {
+            let etag = uploads
+                .put_chunk(stream_id, "bucket", &chunk(object_id, 9, 10, false))
+                .await
+                .unwrap();
+            assert_eq!(etag, None);
+        }
+        assert_eq!(
+            limiter.in_flight_bytes(),
+            90,
+            "9 bytes left after each part"
+        );
+
+        // chunks larger than a part are rejected
+        let (stream_id, object_id) = &streams[0];
+        assert!(matches!(
+            uploads
+                .put_chunk(stream_id, "bucket", &chunk(object_id, 19, 11, false))
+                .await,
+            Err(RpcError::InvalidParameter(_))
+        ));
+
+        for (stream_id, object_id) in streams.iter() {
+            let etag = uploads
+                .put_chunk(stream_id, "bucket", &chunk(object_id, 19, 1, true))
+                .await
+                .unwrap();
+            assert_eq!(etag.as_deref(), Some("etag"));
+        }
+        assert_eq!(limiter.in_flight_bytes(), 0);
+        assert_eq!(limiter.reserved_bytes(), 0);
+    }
+
+    #[tokio::test]
+    async fn idle_uploads_expire() {
+        let limiter = UploadLimiter::new(100);
+        let uploads = Uploads::with_idle_timeout(
+            Arc::new(NullBackend),
+            limiter.clone(),
+            10,
+            Duration::from_millis(100),
+        );
+        let stream_id = uploads.start("bucket", &put("idle", 5)).await.unwrap();
+        assert_eq!(limiter.reserved_bytes(), 10);
+        assert_eq!(limiter.in_flight_bytes(), 5);
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(limiter.reserved_bytes(), 0);
+        assert_eq!(limiter.in_flight_bytes(), 0);
+        assert!(uploads
+            .put_chunk(&stream_id, "bucket", &chunk("idle", 5, 1, true))
+            .await
+            .is_err());
+    }
+}
@@ -1,17 +1,46 @@
 use std::env;
 
-use blobstore_s3_lib::{wasmcloud_interface_blobstore::*, StorageClient, StorageConfig};
+use wasmbus_rpc::error::RpcError;
 
-/// Helper function to create a StorageClient with local testing overrides
+use blobstore_s3_lib::{
+    wasmcloud_interface_blobstore::*, AzureConfig, BackendKind, GcsConfig, ObjectLockConfig,
+    StorageClient, StorageConfig, PRECONDITION_FAILED,
+};
+
+/// Helper function to create a StorageClient with local testing overrides.
+/// The backend is selected with TEST_BACKEND (s3, gcs, or azure), and defaults to s3.
+/// Emulators for each backend are defined in tests/docker-compose.yaml
 async fn test_client() -> StorageClient {
-    let conf = StorageConfig {
+    StorageClient::new(test_config(), Default::default())
+        .await
+        .expect("create storage client")
+}
+
+/// Storage configuration for the backend selected by TEST_BACKEND
+fn test_config() -> StorageConfig {
+    let backend = env::var("TEST_BACKEND")
+        .ok()
+        .map(|b| b.parse::<BackendKind>().expect("valid TEST_BACKEND"))
+        .unwrap_or_default();
+    StorageConfig {
+        backend,
         endpoint: env::var("AWS_ENDPOINT").ok(),
         access_key_id: env::var("AWS_ACCESS_KEY_ID").ok(),
         secret_access_key: env::var("AWS_SECRET_ACCESS_KEY").ok(),
+        gcs: GcsConfig {
+            project_id: Some("test".to_string()),
+            endpoint: Some(
+                env::var("STORAGE_EMULATOR_HOST")
+                    .unwrap_or_else(|_| "http://127.0.0.1:4443".to_string()),
+            ),
+            ..Default::default()
+        },
+        azure: AzureConfig {
+            use_emulator: true,
+            ..Default::default()
+        },
         ..Default::default()
-    };
-
-    StorageClient::new(conf, Default::default()).await
+    }
 }
 
 /// Tests
@@ -24,7 +53,7 @@ async fn test_create_container() {
     let ctx = wasmbus_rpc::common::Context::default();
 
     let num = rand::random::<u64>();
-    let bucket = format!("test.bucket.{}", num);
+    let bucket = format!("test-bucket-{}", num);
 
     assert!(
         !s3.container_exists(&ctx, &bucket).await.unwrap(),
@@ -52,7 +81,7 @@ async fn test_create_object() {
     let ctx = wasmbus_rpc::common::Context::default();
 
     let num = rand::random::<u64>();
-    let bucket = format!("test.object.{}", num);
+    let bucket = format!("test-object-{}", num);
 
     s3.create_container(&ctx, &bucket).await.unwrap();
 
@@ -70,6 +99,7 @@ async fn test_create_object() {
                 },
                 content_encoding: None,
                 content_type: None,
+                ..Default::default()
             },
         )
         .await
@@ -125,7 +155,7 @@ async fn test_list_objects() {
     let ctx = wasmbus_rpc::common::Context::default();
 
     let num = rand::random::<u64>();
-    let bucket = format!("test.list.{}", num);
+    let bucket = format!("test-list-{}", num);
 
     s3.create_container(&ctx, &bucket).await.unwrap();
 
@@ -156,6 +186,7 @@ async fn test_list_objects() {
                 },
                 content_encoding: None,
                 content_type: None,
+                ..Default::default()
             },
         )
         .await
@@ -188,7 +219,7 @@ async fn test_get_object_range() {
     let s3 = test_client().await;
     let ctx = wasmbus_rpc::common::Context::default();
     let num = rand::random::<u64>();
-    let bucket = format!("test.range.{}", num);
+    let bucket = format!("test-range-{}", num);
 
     s3.create_container(&ctx, &bucket).await.unwrap();
     let object_bytes = b"abcdefghijklmnopqrstuvwxyz".to_vec();
@@ -205,6 +236,7 @@ async fn test_get_object_range() {
                 },
                 content_encoding: None,
                 content_type: None,
+                ..Default::default()
             },
         )
         .await
@@ -278,7 +310,7 @@ async fn test_get_object_chunks() {
     let s3 = test_client().await;
     let ctx = wasmbus_rpc::common::Context::default();
     let num = rand::random::<u64>();
-    let bucket = format!("test.chunk.{}", num);
+    let bucket = format!("test-chunk-{}", num);
 
     s3.create_container(&ctx, &bucket).await.unwrap();
 
@@ -298,6 +330,7 @@ async fn test_get_object_chunks() {
                     },
                     content_encoding: None,
                     content_type: None,
+                    ..Default::default()
                 },
             )
             .await
@@ -331,3 +364,405 @@ async fn test_get_object_chunks() {
         .expect("get-object-chunk");
     assert_eq!(obj.initial_chunk.unwrap().bytes.len(), 300);
 }
+
+/// Tests
+/// - etag returned from put_object, get_object_info, and get_object
+/// - put_object with if_none_match and if_match preconditions
+#[tokio::test]
+async fn test_conditional_put() {
+    let s3 = test_client().await;
+    let ctx = wasmbus_rpc::common::Context::default();
+    let num = rand::random::<u64>();
+    let bucket = format!("test-etag-{}", num);
+
+    s3.create_container(&ctx, &bucket).await.unwrap();
+
+    let put =
+        |bytes: &[u8], if_match: Option<String>, if_none_match: Option<String>| PutObjectRequest {
+            chunk: Chunk {
+                bytes: bytes.to_vec(),
+                container_id: bucket.clone(),
+                is_last: true,
+                object_id: "object.1".to_string(),
+                offset: 0,
+            },
+            if_match,
+            if_none_match,
+            ..Default::default()
+        };
+
+    // create only if absent
+    let first = s3
+        .put_object(&ctx, &put(b"first", None, Some("*".to_string())))
+        .await
+        .expect("put if absent");
+    let etag = first.etag.expect("put returns etag");
+
+    let info = s3
+        .get_object_info(
+            &ctx,
+            &ContainerObject {
+                container_id: bucket.clone(),
+                object_id: "object.1".to_string(),
+            },
+        )
+        .await
+        .expect("get object info");
+    assert_eq!(info.etag.as_ref(), Some(&etag));
+
+    // a second create-if-absent must fail
+    let err = s3
+        .put_object(&ctx, &put(b"second", None, Some("*".to_string())))
+        .await
+        .expect_err("object already exists");
+    assert!(err.to_string().contains(PRECONDITION_FAILED));
+
+    // replace only if unchanged
+    let second = s3
+        .put_object(&ctx, &put(b"second", Some(etag.clone()), None))
+        .await
+        .expect("put if match");
+    let new_etag = second.etag.expect("put returns etag");
+    assert_ne!(new_etag, etag);
+
+    // stale etag is rejected
+    let err = s3
+        .put_object(&ctx, &put(b"third", Some(etag), None))
+        .await
+        .expect_err("stale etag");
+    assert!(err.to_string().contains(PRECONDITION_FAILED));
+
+    let obj = s3
+        .get_object(
+            &ctx,
+            &GetObjectRequest {
+                container_id: bucket.clone(),
+                object_id: "object.1".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("get object");
+    assert_eq!(obj.etag, Some(new_etag));
+    assert_eq!(obj.initial_chunk.unwrap().bytes, b"second".to_vec());
+
+    s3.remove_objects(
+        &ctx,
+        &RemoveObjectsRequest {
+            container_id: bucket.clone(),
+            objects: vec!["object.1".to_string()],
+        },
+    )
+    .await
+    .expect("remove object");
+    s3.remove_containers(&ctx, &vec![bucket])
+        .await
+        .expect("remove containers");
+}
+
+/// Tests
+/// - chunked upload with put_object and put_chunk, sent to storage in multiple parts
+/// - cancelled upload does not create the object
+#[tokio::test]
+async fn test_chunked_put() {
+    if matches!(env::var("TEST_BACKEND").as_deref(), Ok(backend) if backend != "s3") {
+        // chunked uploads are only supported by the s3 backend
+        return;
+    }
+    let s3 = test_client().await;
+    let ctx = wasmbus_rpc::common::Context::default();
+    let num = rand::random::<u64>();
+    let bucket = format!("test-upload-{}", num);
+    s3.create_container(&ctx, &bucket).await.unwrap();
+
+    let chunk = |object_id: &str, offset: u64, len: usize, is_last: bool| Chunk {
+        bytes: vec![(offset % 251) as u8; len],
+        container_id: bucket.clone(),
+        is_last,
+        object_id: object_id.to_string(),
+        offset,
+    };
+    const MB: usize = 1024 * 1024;
+
+    // two 6MB chunks fill the first part (8MB), and the last chunk completes the second
+    let resp = s3
+        .put_object(
+            &ctx,
+            &PutObjectRequest {
+                chunk: chunk("big", 0, 6 * MB, false),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("start upload");
+    let stream_id = resp.stream_id.expect("stream id");
+    let mut offset = 6 * MB as u64;
+    for (len, is_last) in [(6 * MB, false), (1000, true)] {
+        s3.put_chunk(
+            &ctx,
+            &PutChunkRequest {
+                chunk: chunk("big", offset, len, is_last),
+                stream_id: Some(stream_id.clone()),
+                cancel_and_remove: false,
+            },
+        )
+        .await
+        .expect("put chunk");
+        offset += len as u64;
+    }
+    let info = s3
+        .get_object_info(
+            &ctx,
+            &ContainerObject {
+                container_id: bucket.clone(),
+                object_id: "big".to_string(),
+            },
+        )
+        .await
+        .expect("uploaded object");
+    assert_eq!(info.content_length, offset);
+
+    // chunks must arrive in order
+    let resp = s3
+        .put_object(
+            &ctx,
+            &PutObjectRequest {
+                chunk: chunk("cancelled", 0, 1000, false),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("start upload");
+    let stream_id = resp.stream_id.expect("stream id");
+    let err = s3
+        .put_chunk(
+            &ctx,
+            &PutChunkRequest {
+                chunk: chunk("cancelled", 5000, 1000, false),
+                stream_id: Some(stream_id.clone()),
+                cancel_and_remove: false,
+            },
+        )
+        .await
+        .expect_err("wrong offset");
+    assert!(matches!(err, RpcError::InvalidParameter(_)));
+
+    s3.put_chunk(
+        &ctx,
+        &PutChunkRequest {
+            chunk: chunk("cancelled", 1000, 0, false),
+            stream_id: Some(stream_id),
+            cancel_and_remove: true,
+        },
+    )
+    .await
+    .expect("cancel upload");
+    assert!(!s3
+        .object_exists(
+            &ctx,
+            &ContainerObject {
+                container_id: bucket.clone(),
+                object_id: "cancelled".to_string(),
+            },
+        )
+        .await
+        .unwrap());
+
+    s3.remove_objects(
+        &ctx,
+        &RemoveObjectsRequest {
+            container_id: bucket.clone(),
+            objects: vec!["big".to_string()],
+        },
+    )
+    .await
+    .expect("remove object");
+    s3.remove_containers(&ctx, &vec![bucket])
+        .await
+        .expect("remove containers");
+}
+
+/// Tests
+/// - list_objects starts after start_with, and continues from the continuation
+/// - remove_objects succeeds for objects that don't exist
+#[tokio::test]
+async fn test_list_objects_pages() {
+    let s3 = test_client().await;
+    let ctx = wasmbus_rpc::common::Context::default();
+
+    let num = rand::random::<u64>();
+    let bucket = format!("test-pages-{}", num);
+    s3.create_container(&ctx, &bucket).await.unwrap();
+    for name in ["a", "b", "c", "d", "e"] {
+        s3.put_object(
+            &ctx,
+            &PutObjectRequest {
+                chunk: Chunk {
+                    bytes: name.as_bytes().to_vec(),
+                    container_id: bucket.clone(),
+                    is_last: true,
+                    object_id: name.to_string(),
+                    offset: 0,
+                },
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("put object");
+    }
+
+    let mut req = ListObjectsRequest {
+        container_id: bucket.clone(),
+        max_items: Some(2),
+        start_with: Some("b".to_string()),
+        ..Default::default()
+    };
+    let mut names = Vec::new();
+    loop {
+        let objs = s3.list_objects(&ctx, &req).await.expect("list objects");
+        assert!(objs.objects.len() <= 2, "{:?}", objs.objects);
+        names.extend(objs.objects.into_iter().map(|o| o.object_id));
+        if objs.is_last {
+            break;
+        }
+        req.continuation = objs.continuation;
+        assert!(req.continuation.is_some());
+    }
+    assert_eq!(names, vec!["c", "d", "e"]);
+
+    let results = s3
+        .remove_objects(
+            &ctx,
+            &RemoveObjectsRequest {
+                container_id: bucket.clone(),
+                objects: vec!["a".to_string(), "missing".to_string()],
+            },
+        )
+        .await
+        .expect("remove objects");
+    assert!(results.is_empty(), "{:?}", results);
+
+    s3.remove_containers(&ctx, &vec![bucket])
+        .await
+        .expect("remove containers");
+}
+
+/// Tests
+/// - object_lock mode without retain_days is rejected when the link is created
+/// - put_object with default retention, and with a legal hold, in a bucket with object lock
+/// - remove_objects reports objects under retention or legal hold as errors
+/// - set_legal_hold removes a legal hold, so the object can be removed
+#[tokio::test]
+async fn test_object_lock() {
+    if matches!(env::var("TEST_BACKEND").as_deref(), Ok(backend) if backend != "s3") {
+        // object lock is only supported by the s3 backend
+        return;
+    }
+    let values = std::collections::HashMap::from([(
+        "config_json".to_string(),
+        r#"{"object_lock":{"enabled":true,"mode":"GOVERNANCE"}}"#.to_string(),
+    )]);
+    assert!(matches!(
+        StorageConfig::from_values(&values),
+        Err(RpcError::InvalidParameter(_))
+    ));
+
+    // objects written through this link are retained for a day
+    let mut conf = test_config();
+    conf.object_lock = ObjectLockConfig {
+        enabled: true,
+        mode: Some("GOVERNANCE".to_string()),
+        retain_days: Some(1),
+    };
+    let retaining = StorageClient::new(conf, Default::default())
+        .await
+        .expect("create storage client");
+    // objects written through this link have no retention
+    let mut conf = test_config();
+    conf.object_lock = ObjectLockConfig {
+        enabled: true,
+        ..Default::default()
+    };
+    let s3 = StorageClient::new(conf, Default::default())
+        .await
+        .expect("create storage client");
+    let ctx = wasmbus_rpc::common::Context::default();
+    let num = rand::random::<u64>();
+    let bucket = format!("test-lock-{}", num);
+    s3.create_container(&ctx, &bucket).await.unwrap();
+
+    let put = |object_id: &str, legal_hold: Option<bool>| PutObjectRequest {
+        chunk: Chunk {
+            bytes: b"audit record".to_vec(),
+            container_id: bucket.clone(),
+            is_last: true,
+            object_id: object_id.to_string(),
+            offset: 0,
+        },
+        legal_hold,
+        ..Default::default()
+    };
+    retaining
+        .put_object(&ctx, &put("retained", None))
+        .await
+        .expect("put with retention");
+    s3.put_object(&ctx, &put("held", Some(true)))
+        .await
+        .expect("put with legal hold");
+    s3.put_object(&ctx, &put("unlocked", None))
+        .await
+        .expect("put without object lock");
+
+    let results = s3
+        .remove_objects(
+            &ctx,
+            &RemoveObjectsRequest {
+                container_id: bucket.clone(),
+                objects: vec![
+                    "retained".to_string(),
+                    "held".to_string(),
+                    "unlocked".to_string(),
+                ],
+            },
+        )
+        .await
+        .expect("remove objects");
+    let mut failed = results
+        .iter()
+        .filter(|r| !r.success && r.error.is_some())
+        .map(|r| r.key.as_str())
+        .collect::<Vec<_>>();
+    failed.sort_unstable();
+    assert_eq!(failed, vec!["held", "retained"]);
+    let exists = |object_id: &str| ContainerObject {
+        container_id: bucket.clone(),
+        object_id: object_id.to_string(),
+    };
+    assert!(s3.object_exists(&ctx, &exists("retained")).await.unwrap());
+    assert!(s3.object_exists(&ctx, &exists("held")).await.unwrap());
+    assert!(!s3.object_exists(&ctx, &exists("unlocked")).await.unwrap());
+
+    s3.set_legal_hold(
+        &ctx,
+        &LegalHoldRequest {
+            container_id: bucket.clone(),
+            object_id: "held".to_string(),
+            enabled: false,
+        },
+    )
+    .await
+    .expect("remove legal hold");
+    let results = s3
+        .remove_objects(
+            &ctx,
+            &RemoveObjectsRequest {
+                container_id: bucket.clone(),
+                objects: vec!["held".to_string()],
+            },
+        )
+        .await
+        .expect("remove objects");
+    assert!(results.is_empty(), "{:?}", results);
+    assert!(!s3.object_exists(&ctx, &exists("held")).await.unwrap());
+    // the retained object, and so the bucket, can't be removed until its retention expires
+}
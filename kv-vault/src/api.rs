@@ -0,0 +1,247 @@
+//! Vault api endpoints that vaultrs doesn't provide: KV v1 secrets, secrets from other
+//! engines, leases, the transit engine, and cert auth. They are declared the same way as vaultrs declares its endpoints.
+//!
+// the Endpoint derive implements its traits inside a const block
+#![allow(unknown_lints, non_local_definitions)]
+
+use rustify::endpoint::Endpoint;
+use rustify::errors::ClientError as RestClientError;
+use rustify_derive::Endpoint;
+use serde::Deserialize;
+use serde_json::Value;
+use vaultrs::api::{EndpointError, EndpointResult};
+use vaultrs::client::Client;
+use vaultrs::error::ClientError;
+
+/// Reads a KV v1 secret, or a secret from any engine that returns its value
+/// from a GET on its path, such as database credentials
+#[derive(Debug, Endpoint)]
+#[endpoint(path = "{self.mount}/{self.path}", response = "Value")]
+pub struct ReadSecretRequest {
+    #[endpoint(skip)]
+    pub mount: String,
+    #[endpoint(skip)]
+    pub path: String,
+}
+
+/// Writes a KV v1 secret. The body is the json map of the secret's data
+#[derive(Debug, Endpoint)]
+#[endpoint(path = "{self.mount}/{self.path}", method = "POST")]
+pub struct SetSecretRequest {
+    #[endpoint(skip)]
+    pub mount: String,
+    #[endpoint(skip)]
+    pub path: String,
+    #[endpoint(raw)]
+    pub data: Vec<u8>,
+}
+
+/// Deletes a KV v1 secret
+#[derive(Debug, Endpoint)]
+#[endpoint(path = "{self.mount}/{self.path}", method = "DELETE")]
+pub struct DeleteSecretRequest {
+    #[endpoint(skip)]
+    pub mount: String,
+    #[endpoint(skip)]
+    pub path: String,
+}
+
+/// Lists the keys at a path
+#[derive(Debug, Endpoint)]
+#[endpoint(
+    path = "{self.mount}/{self.path}",
+    method = "LIST",
+    response = "ListSecretsResponse"
+)]
+pub struct ListSecretsRequest {
+    #[endpoint(skip)]
+    pub mount: String,
+    #[endpoint(skip)]
+    pub path: String,
+}
+
+/// Response from [ListSecretsRequest]
+#[derive(Debug, Deserialize)]
+pub struct ListSecretsResponse {
+    pub keys: Vec<String>,
+}
+
+/// Revokes a lease
+#[derive(Debug, Endpoint)]
+#[endpoint(path = "sys/leases/revoke", method = "PUT")]
+pub struct RevokeLeaseRequest {
+    pub lease_id: String,
+}
+
+/// Logs in with the TLS client certificate
+#[derive(Debug, Endpoint)]
+#[endpoint(path = "auth/{self.mount}/login", method = "POST")]
+pub struct CertLoginRequest {
+    #[endpoint(skip)]
+    pub mount: String,
+    pub name: Option<String>,
+}
+
+/// Encrypts base64-encoded plaintext with a transit key
+#[derive(Debug, Endpoint)]
+#[endpoint(
+    path = "{self.mount}/encrypt/{self.name}",
+    method = "POST",
+    response = "CiphertextResponse"
+)]
+pub struct EncryptRequest {
+    #[endpoint(skip)]
+    pub mount: String,
+    #[endpoint(skip)]
+    pub name: String,
+    pub plaintext: String,
+    pub context: Option<String>,
+}
+
+/// Decrypts ciphertext with a transit key
+#[derive(Debug, Endpoint)]
+#[endpoint(
+    path = "{self.mount}/decrypt/{self.name}",
+    method = "POST",
+    response = "PlaintextResponse"
+)]
+pub struct DecryptRequest {
+    #[endpoint(skip)]
+    pub mount: String,
+    #[endpoint(skip)]
+    pub name: String,
+    pub ciphertext: String,
+    pub context: Option<String>,
+}
+
+/// Encrypts ciphertext again with the latest version of a transit key
+#[derive(Debug, Endpoint)]
+#[endpoint(
+    path = "{self.mount}/rewrap/{self.name}",
+    method = "POST",
+    response = "CiphertextResponse"
+)]
+pub struct RewrapRequest {
+    #[endpoint(skip)]
+    pub mount: String,
+    #[endpoint(skip)]
+    pub name: String,
+    pub ciphertext: String,
+    pub context: Option<String>,
+}
+
+/// Signs base64-encoded input with a transit key
+#[derive(Debug, Endpoint)]
+#[endpoint(
+    path = "{self.mount}/sign/{self.name}",
+    method = "POST",
+    response = "SignResponse"
+)]
+pub struct SignRequest {
+    #[endpoint(skip)]
+    pub mount: String,
+    #[endpoint(skip)]
+    pub name: String,
+    pub input: String,
+    pub hash_algorithm: Option<String>,
+}
+
+/// Verifies a signature or HMAC of base64-encoded input
+#[derive(Debug, Endpoint)]
+#[endpoint(
+    path = "{self.mount}/verify/{self.name}",
+    method = "POST",
+    response = "VerifyResponse"
+)]
+pub struct VerifyRequest {
+    #[endpoint(skip)]
+    pub mount: String,
+    #[endpoint(skip)]
+    pub name: String,
+    pub input: String,
+    pub signature: Option<String>,
+    pub hmac: Option<String>,
+    pub hash_algorithm: Option<String>,
+}
+
+/// Returns the HMAC of base64-encoded input with a transit key
+#[derive(Debug, Endpoint)]
+#[endpoint(
+    path = "{self.mount}/hmac/{self.name}",
+    method = "POST",
+    response = "HmacResponse"
+)]
+pub struct HmacRequest {
+    #[endpoint(skip)]
+    pub mount: String,
+    #[endpoint(skip)]
+    pub name: String,
+    pub input: String,
+    pub algorithm: Option<String>,
+}
+
+/// Response from [EncryptRequest] and [RewrapRequest]
+#[derive(Debug, Deserialize)]
+pub struct CiphertextResponse {
+    pub ciphertext: String,
+}
+
+/// Response from [DecryptRequest]. The plaintext is base64-encoded
+#[derive(Debug, Deserialize)]
+pub struct PlaintextResponse {
+    pub plaintext: String,
+}
+
+/// Response from [SignRequest]
+#[derive(Debug, Deserialize)]
+pub struct SignResponse {
+    pub signature: String,
+}
+
+/// Response from [VerifyRequest]
+#[derive(Debug, Deserialize)]
+pub struct VerifyResponse {
+    pub valid: bool,
+}
+
+/// Response from [HmacRequest]
+#[derive(Debug, Deserialize)]
+pub struct HmacResponse {
+    pub hmac: String,
+}
+
+/// Executes the endpoint, and returns the whole response, including the lease of the secret,
+/// instead of only its data
+pub async fn exec_with_lease<E>(
+    client: &impl Client,
+    endpoint: E,
+) -> Result<EndpointResult<E::Response>, ClientError>
+where
+    E: Endpoint,
+{
+    endpoint
+        .with_middleware(client.middle())
+        .exec(client.http())
+        .await
+        .map_err(parse_err)?
+        .wrap::<EndpointResult<_>>()
+        .map_err(ClientError::from)
+}
+
+/// Converts an error response to ClientError::APIError with the errors from the response,
+/// as vaultrs does for its endpoints
+fn parse_err(e: RestClientError) -> ClientError {
+    if let RestClientError::ServerResponseError {
+        code,
+        content: Some(content),
+    } = &e
+    {
+        if let Ok(err) = serde_json::from_str::<EndpointError>(content) {
+            return ClientError::APIError {
+                code: *code,
+                errors: err.errors,
+            };
+        }
+    }
+    ClientError::from(e)
+}
@@ -0,0 +1,185 @@
+//! Read-through cache of secret values
+//!
+//! Each link may keep the values of the secrets it reads for a short time, so that actors
+//! reading the same secret repeatedly don't make a round-trip to vault for each read.
+//! Writes and deletes through the link remove the secret from the cache. Writes by other
+//! clients are seen once the cached value expires.
+//!
+//! A value read while the secret is being changed may be the old value, so each path has a
+//! generation, which changes when the path is invalidated. A value is only added if the
+//! generation of its path is the same as before it was read.
+//!
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+use tracing::{debug, info};
+
+/// Hit and miss counts are logged after every STATS_LOG_INTERVAL lookups
+const STATS_LOG_INTERVAL: u64 = 1000;
+
+/// Cached secret values, by path
+pub struct SecretCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<String, Entry>>,
+    generations: Mutex<Generations>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct Entry {
+    value: Value,
+    expires: Instant,
+}
+
+/// Generations of the invalidated paths
+#[derive(Default)]
+struct Generations {
+    paths: HashMap<String, u64>,
+    /// the generation of the last invalidation
+    latest: u64,
+    /// the generation of paths that aren't in `paths`. Set to `latest` when `paths` is cleared,
+    /// so that values read before then aren't added
+    base: u64,
+}
+
+impl Generations {
+    fn get(&self, path: &str) -> u64 {
+        self.paths.get(path).copied().unwrap_or(self.base)
+    }
+}
+
+/// Number of lookups that found, or didn't find, an unexpired value in the cache
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl SecretCache {
+    /// Creates a cache that keeps values for `ttl`, and keeps at most `max_entries` values
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        SecretCache {
+            ttl,
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+            generations: Mutex::new(Generations::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the cached value of the secret, if it hasn't expired
+    pub fn get(&self, path: &str) -> Option<Value> {
+        let value = {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.get(path) {
+                Some(entry) if entry.expires > Instant::now() => Some(entry.value.clone()),
+                Some(_) => {
+                    entries.remove(path);
+                    None
+                }
+                None => None,
+            }
+        };
+        let (hits, misses) = match value {
+            Some(_) => (self.hits.fetch_add(1, Ordering::Relaxed) + 1, self.misses()),
+            None => (self.hits(), self.misses.fetch_add(1, Ordering::Relaxed) + 1),
+        };
+        debug!(%path, hit = value.is_some(), hits, misses, "secret cache lookup");
+        if (hits + misses) % STATS_LOG_INTERVAL == 0 {
+            self.log_stats();
+        }
+        value
+    }
+
+    /// Returns the generation of the path, to be passed to [insert](SecretCache::insert)
+    /// with the value read after calling this
+    pub fn generation(&self, path: &str) -> u64 {
+        self.generations.lock().unwrap().get(path)
+    }
+
+    /// Adds the value of the secret, unless the path was invalidated since `generation`
+    /// was returned by [generation](SecretCache::generation). If the cache is full, expired
+    /// values are removed, and then, if it's still full, the value that expires first
+    pub fn insert(&self, path: &str, value: Value, generation: u64) {
+        if self.max_entries == 0 {
+            return;
+        }
+        // held while adding the value, so the path can't be invalidated in between
+        let generations = self.generations.lock().unwrap();
+        if generations.get(path) != generation {
+            debug!(%path, "secret changed while it was read, not caching it");
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries && !entries.contains_key(path) {
+            entries.retain(|_, entry| entry.expires > now);
+            if entries.len() >= self.max_entries {
+                if let Some(oldest) = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.expires)
+                    .map(|(path, _)| path.clone())
+                {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+        entries.insert(
+            path.to_string(),
+            Entry {
+                value,
+                expires: now + self.ttl,
+            },
+        );
+    }
+
+    /// Removes the secret from the cache, and changes the generation of its path
+    pub fn invalidate(&self, path: &str) {
+        let mut generations = self.generations.lock().unwrap();
+        generations.latest += 1;
+        let latest = generations.latest;
+        generations.paths.insert(path.to_string(), latest);
+        // the generations are kept for at most as many paths as values
+        if generations.paths.len() > self.max_entries {
+            generations.paths.clear();
+            generations.base = latest;
+        }
+        self.entries.lock().unwrap().remove(path);
+    }
+
+    /// Returns the hit and miss counts
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits(),
+            misses: self.misses(),
+        }
+    }
+
+    /// Logs the hit and miss counts
+    pub fn log_stats(&self) {
+        let CacheStats { hits, misses } = self.stats();
+        info!(
+            hits,
+            misses,
+            entries = self.entries.lock().unwrap().len(),
+            "secret cache stats"
+        );
+    }
+
+    fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
@@ -1,50 +1,144 @@
 //! Hashicorp vault client
 //!
-use std::{string::ToString, sync::Arc};
+use std::{
+    string::ToString,
+    sync::{Arc, RwLock},
+    time::Instant,
+};
 
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use rustify::clients::reqwest::Client as HttpClient;
 use serde::{de::DeserializeOwned, Serialize};
 use std::time::Duration;
 use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
-use tracing::{debug, error, info};
-use vaultrs::api::kv2::responses::SecretVersionMetadata;
+use tracing::{debug, error, info, warn};
+use vaultrs::api::kv2::requests::{ReadSecretRequest, SetSecretRequestOptions};
+use vaultrs::api::kv2::responses::{ReadSecretMetadataResponse, SecretVersionMetadata};
+use vaultrs::api::{AuthInfo, EndpointMiddleware};
 use vaultrs::client::{Client as ClientTrait, VaultClient, VaultClientSettings};
+use vaultrs::error::ClientError;
 
-use crate::{config::Config, error::VaultError};
+use crate::{
+    api,
+    cache::{CacheStats, SecretCache},
+    config::{Auth, ClientCert, Config, Credential, Engine, Transit},
+    error::VaultError,
+};
 
 /// Vault HTTP api version. As of Vault 1.9.x (Feb 2022), all http api calls use version 1
 const API_VERSION: u8 = 1;
 
+/// Header with the Vault Enterprise namespace of a request
+const NAMESPACE_HEADER: &str = "X-Vault-Namespace";
+
 /// Default TTL for tokens used by this provider. Defaults to 72 hours.
 pub const TOKEN_INCREMENT_TTL: &str = "72h";
 pub const TOKEN_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60 * 12); // 12 hours
 
+/// Delay before retrying a failed login, doubled after each failure up to MAX_LOGIN_RETRY_DELAY
+const LOGIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_LOGIN_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Number of times a check-and-set write is attempted before giving up
+const CAS_ATTEMPTS: u32 = 20;
+/// Delay before retrying a check-and-set write, multiplied by the number of failed attempts
+const CAS_RETRY_DELAY: Duration = Duration::from_millis(10);
+
 /// Vault client connection information.
 pub struct Client {
-    inner: Arc<vaultrs::client::VaultClient>,
+    /// The current client. Replaced with a client for the new token after each login
+    inner: Arc<RwLock<Arc<VaultClient>>>,
+    auth: Auth,
     namespace: String,
+    engine: Engine,
+    path_prefix: Option<String>,
+    transit: Option<Transit>,
+    /// Values of secrets recently read, if the link has a cache
+    cache: Option<SecretCache>,
+    /// Leases of secrets read from a dynamic secrets engine, revoked when the link is deleted
+    leases: std::sync::Mutex<Vec<Lease>>,
     token_increment_ttl: String,
     token_refresh_interval: Duration,
     renew_task: Mutex<Option<JoinHandle<()>>>,
 }
 
+/// Removes a secret from the cache when dropped, including when a write fails or is cancelled
+struct Invalidate<'a> {
+    cache: Option<&'a SecretCache>,
+    path: &'a str,
+}
+
+impl Invalidate<'_> {
+    fn invalidate(&self) {
+        if let Some(cache) = self.cache {
+            cache.invalidate(self.path);
+        }
+    }
+}
+
+impl Drop for Invalidate<'_> {
+    fn drop(&mut self) {
+        self.invalidate();
+    }
+}
+
+/// A lease on a secret from a dynamic secrets engine
+struct Lease {
+    id: String,
+    expires: Instant,
+}
+
 impl Client {
     /// Creates a new Vault client. See [config](./config.rs) for explanation of parameters.
     ///
     /// Note that this constructor does not attempt to connect to the vault server,
     /// so the vault server does not need to be running at the time a LinkDefinition to this provider is created.
+    /// With any auth method other than a token, the client has no token until
+    /// [set_renewal](Client::set_renewal) logs in.
+    /// Returns Err(Tls) if the certificate files can't be read or parsed, and Err(Config)
+    /// if the namespace can't be sent in a header
     pub fn new(config: Config) -> Result<Self, VaultError> {
+        let token = match &config.auth {
+            Auth::Token(token) => token.clone(),
+            _ => String::new(),
+        };
+        let settings = VaultClientSettings {
+            token: token.clone(),
+            address: config.addr,
+            ca_certs: config.certs,
+            verify: !config.tls_skip_verify,
+            version: API_VERSION,
+            wrapping: false,
+            timeout: None,
+        };
+        // vaultrs doesn't support client certificates or namespaces, so the http client
+        // is built here instead of by VaultClient::new
+        let http = http_client(
+            &settings,
+            config.client_cert.as_ref(),
+            config.namespace.as_deref(),
+        )?;
+        let client = VaultClient {
+            http: HttpClient::new(settings.address.as_str(), http),
+            middle: EndpointMiddleware {
+                token,
+                version: format!("v{}", API_VERSION),
+                wrap: None,
+            },
+            settings,
+        };
         Ok(Client {
-            inner: Arc::new(VaultClient::new(VaultClientSettings {
-                token: config.token,
-                address: config.addr,
-                ca_certs: config.certs,
-                verify: false,
-                version: API_VERSION,
-                wrapping: false,
-                timeout: None,
-            })?),
+            inner: Arc::new(RwLock::new(Arc::new(client))),
+            auth: config.auth,
             namespace: config.mount,
+            engine: config.engine,
+            path_prefix: config.path_prefix,
+            transit: config.transit,
+            cache: config
+                .cache
+                .map(|cache| SecretCache::new(cache.ttl, cache.max_entries)),
+            leases: std::sync::Mutex::new(Vec::new()),
             token_increment_ttl: config
                 .token_increment_ttl
                 .unwrap_or(TOKEN_INCREMENT_TTL.into()),
@@ -56,12 +150,58 @@ impl Client {
     }
 
     pub fn inner_client(&self) -> Arc<VaultClient> {
-        self.inner.clone()
+        self.inner.read().unwrap().clone()
+    }
+
+    /// Returns the path of the key under the link's path prefix.
+    /// Returns Err(InvalidPath) if the key has a `..` segment
+    pub fn path(&self, key: &str) -> Result<String, VaultError> {
+        if key.split('/').any(|segment| segment == "..") {
+            return Err(VaultError::InvalidPath {
+                path: key.to_string(),
+            });
+        }
+        let key = key.trim_start_matches('/');
+        Ok(match &self.path_prefix {
+            Some(prefix) if key.is_empty() => prefix.clone(),
+            Some(prefix) => format!("{}/{}", prefix, key),
+            None => key.to_string(),
+        })
     }
 
-    /// Reads value of secret using namespace and key path
+    /// Reads value of secret using namespace and key path. With a dynamic secrets engine,
+    /// each read creates a new secret, whose lease is revoked by [revoke_leases](Client::revoke_leases).
+    /// Otherwise, if the link has a cache, the value may be read from the cache
     pub async fn read_secret<D: DeserializeOwned>(&self, path: &str) -> Result<D, VaultError> {
-        match vaultrs::kv2::read(self.inner.as_ref(), &self.namespace, path).await {
+        let cache = self
+            .cache
+            .as_ref()
+            .filter(|_| self.engine != Engine::Dynamic);
+        if let Some(value) = cache.and_then(|cache| cache.get(path)) {
+            return serde_json::from_value(value)
+                .map_err(|e| ClientError::JsonParseError { source: e }.into());
+        }
+        let generation = cache.map(|cache| cache.generation(path));
+        let client = self.inner_client();
+        let result: Result<serde_json::Value, ClientError> = match self.engine {
+            Engine::Kv2 => vaultrs::kv2::read(client.as_ref(), &self.namespace, path).await,
+            Engine::Kv1 => {
+                vaultrs::api::exec_with_result(client.as_ref(), self.secret_request(path)).await
+            }
+            Engine::Dynamic => api::exec_with_lease(client.as_ref(), self.secret_request(path))
+                .await
+                .and_then(|secret| {
+                    self.add_lease(&secret.lease_id, secret.lease_duration);
+                    secret.data.ok_or(ClientError::ResponseDataEmptyError)
+                }),
+        };
+        if let (Some(cache), Some(generation), Ok(value)) = (cache, generation, &result) {
+            cache.insert(path, value.clone(), generation);
+        }
+        let result = result.and_then(|value| {
+            serde_json::from_value(value).map_err(|e| ClientError::JsonParseError { source: e })
+        });
+        match result {
             Err(vaultrs::error::ClientError::APIError {
                 code: 404,
                 errors: _,
@@ -74,28 +214,296 @@ impl Client {
         }
     }
 
+    /// Returns true if there is a secret at the path. Not supported by dynamic secrets engines,
+    /// where reading the path would create a credential
+    pub async fn secret_exists(&self, path: &str) -> Result<bool, VaultError> {
+        if self.engine == Engine::Dynamic {
+            return Err(self.unsupported("checking whether secrets exist"));
+        }
+        match self.read_secret::<serde_json::Value>(path).await {
+            Ok(_) => Ok(true),
+            Err(VaultError::NotFound { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
     /// Writes value of secret using namespace and key path
-    pub async fn write_secret<T: Serialize>(
+    pub async fn write_secret<T: Serialize>(&self, path: &str, data: &T) -> Result<(), VaultError> {
+        let client = self.inner_client();
+        let _invalidate = self.invalidate_on_drop(path);
+        match self.engine {
+            Engine::Kv2 => vaultrs::kv2::set(client.as_ref(), &self.namespace, path, data)
+                .await
+                .map(|_| ()),
+            Engine::Kv1 => {
+                let endpoint = api::SetSecretRequest {
+                    mount: self.namespace.clone(),
+                    path: path.to_string(),
+                    data: serde_json::to_vec(data)
+                        .map_err(|e| ClientError::JsonParseError { source: e })?,
+                };
+                vaultrs::api::exec_with_empty(client.as_ref(), endpoint).await
+            }
+            Engine::Dynamic => return Err(self.unsupported("writing secrets")),
+        }
+        .map_err(VaultError::from)
+    }
+
+    /// Reads the latest version of the secret, and its version number. If the secret doesn't
+    /// exist or its latest version is deleted, returns None and the current version number,
+    /// which is 0 if the secret was never written or all its versions were deleted with
+    /// [delete_all](Client::delete_all).
+    /// Returns Err(InvalidValue) if the secret can't be deserialized as `D`
+    pub async fn read_secret_versioned<D: DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<(Option<D>, u64), VaultError> {
+        self.require_kv2("check-and-set")?;
+        let endpoint = ReadSecretRequest::builder()
+            .mount(&self.namespace)
+            .path(path)
+            .build()
+            .unwrap();
+        match vaultrs::api::exec_with_result(self.inner_client().as_ref(), endpoint).await {
+            Ok(secret) => {
+                let data =
+                    serde_json::from_value(secret.data).map_err(|_| VaultError::InvalidValue {
+                        namespace: self.namespace.clone(),
+                        path: path.to_string(),
+                    })?;
+                Ok((Some(data), secret.metadata.version))
+            }
+            Err(ClientError::APIError { code: 404, .. }) => {
+                // the latest version may be deleted or destroyed, while older versions remain
+                match vaultrs::kv2::read_metadata(
+                    self.inner_client().as_ref(),
+                    &self.namespace,
+                    path,
+                )
+                .await
+                {
+                    Ok(metadata) => Ok((None, metadata.current_version)),
+                    Err(ClientError::APIError { code: 404, .. }) => Ok((None, 0)),
+                    Err(e) => Err(e.into()),
+                }
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes value of secret if its current version is `version`, or, if `version` is 0,
+    /// if the secret doesn't exist. Returns Err(VersionConflict) if the version doesn't match
+    pub async fn write_secret_cas<T: Serialize>(
         &self,
         path: &str,
         data: &T,
+        version: u64,
     ) -> Result<SecretVersionMetadata, VaultError> {
-        vaultrs::kv2::set(self.inner.as_ref(), &self.namespace, path, data)
+        let options = SetSecretRequestOptions {
+            cas: version as u32,
+        };
+        let _invalidate = self.invalidate_on_drop(path);
+        match vaultrs::kv2::set_with_options(
+            self.inner_client().as_ref(),
+            &self.namespace,
+            path,
+            data,
+            options,
+        )
+        .await
+        {
+            Err(ClientError::APIError { code: 400, errors })
+                if errors.iter().any(|e| e.contains("check-and-set")) =>
+            {
+                Err(VaultError::VersionConflict {
+                    namespace: self.namespace.clone(),
+                    path: path.to_string(),
+                })
+            }
+            Err(e) => Err(e.into()),
+            Ok(metadata) => Ok(metadata),
+        }
+    }
+
+    /// Reads the latest version of the secret, and writes the value returned by `update`
+    /// with check-and-set, so that the write fails if another client wrote the secret in
+    /// between. After a conflict, the secret is read and `update` is called again.
+    ///
+    /// `update` is called with the current value, or None if the secret doesn't exist, and
+    /// returns the new value, or None to leave the secret unchanged, and the result to return
+    /// once the secret is written. Returns Err(VersionConflict) if every attempt conflicts
+    pub async fn update_secret<D, R, F>(&self, path: &str, mut update: F) -> Result<R, VaultError>
+    where
+        D: DeserializeOwned + Serialize,
+        F: FnMut(Option<D>) -> (Option<D>, R),
+    {
+        for attempt in 1..=CAS_ATTEMPTS {
+            let (current, version) = self.read_secret_versioned::<D>(path).await?;
+            let (value, result) = update(current);
+            let value = match value {
+                Some(value) => value,
+                None => return Ok(result),
+            };
+            match self.write_secret_cas(path, &value, version).await {
+                Ok(_) => return Ok(result),
+                Err(VaultError::VersionConflict { .. }) => {
+                    debug!(%path, attempt, "secret changed while updating, retrying");
+                    tokio::time::sleep(CAS_RETRY_DELAY * attempt).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(VaultError::VersionConflict {
+            namespace: self.namespace.clone(),
+            path: path.to_string(),
+        })
+    }
+
+    /// Reads a version of the secret. Returns Err(NotFound) if the version doesn't exist,
+    /// or is deleted or destroyed
+    pub async fn read_secret_version<D: DeserializeOwned>(
+        &self,
+        path: &str,
+        version: u64,
+    ) -> Result<D, VaultError> {
+        self.require_kv2("reading versions")?;
+        match vaultrs::kv2::read_version(
+            self.inner_client().as_ref(),
+            &self.namespace,
+            path,
+            version,
+        )
+        .await
+        {
+            Err(ClientError::APIError { code: 404, .. }) => Err(VaultError::NotFound {
+                namespace: self.namespace.clone(),
+                path: path.to_string(),
+            }),
+            Err(e) => Err(e.into()),
+            Ok(val) => Ok(val),
+        }
+    }
+
+    /// Reads the metadata of the secret and its versions.
+    /// Returns Err(NotFound) if the secret doesn't exist
+    pub async fn read_metadata(
+        &self,
+        path: &str,
+    ) -> Result<ReadSecretMetadataResponse, VaultError> {
+        self.require_kv2("reading metadata")?;
+        match vaultrs::kv2::read_metadata(self.inner_client().as_ref(), &self.namespace, path).await
+        {
+            Err(ClientError::APIError { code: 404, .. }) => Err(VaultError::NotFound {
+                namespace: self.namespace.clone(),
+                path: path.to_string(),
+            }),
+            Err(e) => Err(e.into()),
+            Ok(metadata) => Ok(metadata),
+        }
+    }
+
+    /// Restores deleted versions of the secret
+    pub async fn undelete_versions(
+        &self,
+        path: &str,
+        versions: Vec<u64>,
+    ) -> Result<(), VaultError> {
+        self.require_kv2("undeleting versions")?;
+        let _invalidate = self.invalidate_on_drop(path);
+        vaultrs::kv2::undelete_versions(
+            self.inner_client().as_ref(),
+            &self.namespace,
+            path,
+            versions,
+        )
+        .await
+        .map_err(VaultError::from)
+    }
+
+    /// Permanently removes the data of versions of the secret
+    pub async fn destroy_versions(&self, path: &str, versions: Vec<u64>) -> Result<(), VaultError> {
+        self.require_kv2("destroying versions")?;
+        let _invalidate = self.invalidate_on_drop(path);
+        vaultrs::kv2::destroy_versions(
+            self.inner_client().as_ref(),
+            &self.namespace,
+            path,
+            versions,
+        )
+        .await
+        .map_err(VaultError::from)
+    }
+
+    /// Writes the value of `version` as the latest version of the secret, with check-and-set so
+    /// that a concurrent write is not overwritten. Returns the new version number, or
+    /// Err(NotFound) if the version doesn't exist, or is deleted or destroyed
+    pub async fn rollback(&self, path: &str, version: u64) -> Result<u64, VaultError> {
+        let data: serde_json::Value = self.read_secret_version(path, version).await?;
+        for attempt in 1..=CAS_ATTEMPTS {
+            let current = self.read_metadata(path).await?.current_version;
+            match self.write_secret_cas(path, &data, current).await {
+                Ok(metadata) => return Ok(metadata.version),
+                Err(VaultError::VersionConflict { .. }) => {
+                    debug!(%path, attempt, "secret changed during rollback, retrying");
+                    tokio::time::sleep(CAS_RETRY_DELAY * attempt).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(VaultError::VersionConflict {
+            namespace: self.namespace.clone(),
+            path: path.to_string(),
+        })
+    }
+
+    /// Deletes all versions and the metadata of the secret.
+    /// Returns Ok if the secret was deleted, or Err for any other error including key not found
+    pub async fn delete_all(&self, path: &str) -> Result<(), VaultError> {
+        self.read_metadata(path).await?;
+        let _invalidate = self.invalidate_on_drop(path);
+        vaultrs::kv2::delete_metadata(self.inner_client().as_ref(), &self.namespace, path)
             .await
             .map_err(VaultError::from)
     }
 
     /// Deletes the latest version of the secret. Note that if versions are in use, only the latest is deleted
     /// Returns Ok if the key was deleted, or Err for any other error including key not found
+    /// With KV v1, which doesn't keep versions, the secret is deleted
     pub async fn delete_latest<T: Serialize>(&self, path: &str) -> Result<(), VaultError> {
-        vaultrs::kv2::delete_latest(self.inner.as_ref(), &self.namespace, path)
-            .await
-            .map_err(VaultError::from)
+        let client = self.inner_client();
+        let _invalidate = self.invalidate_on_drop(path);
+        match self.engine {
+            Engine::Kv2 => {
+                vaultrs::kv2::delete_latest(client.as_ref(), &self.namespace, path).await
+            }
+            Engine::Kv1 => {
+                let endpoint = api::DeleteSecretRequest {
+                    mount: self.namespace.clone(),
+                    path: path.to_string(),
+                };
+                vaultrs::api::exec_with_empty(client.as_ref(), endpoint).await
+            }
+            Engine::Dynamic => return Err(self.unsupported("deleting secrets")),
+        }
+        .map_err(VaultError::from)
     }
 
     /// Lists keys at the path
     pub async fn list_secrets(&self, path: &str) -> Result<Vec<String>, VaultError> {
-        match vaultrs::kv2::list(self.inner.as_ref(), &self.namespace, path).await {
+        let client = self.inner_client();
+        let result = match self.engine {
+            Engine::Kv2 => vaultrs::kv2::list(client.as_ref(), &self.namespace, path).await,
+            Engine::Kv1 | Engine::Dynamic => {
+                let endpoint = api::ListSecretsRequest {
+                    mount: self.namespace.clone(),
+                    path: path.to_string(),
+                };
+                vaultrs::api::exec_with_result(client.as_ref(), endpoint)
+                    .await
+                    .map(|list| list.keys)
+            }
+        };
+        match result {
             Err(vaultrs::error::ClientError::APIError {
                 code: 404,
                 errors: _,
@@ -108,29 +516,417 @@ impl Client {
         }
     }
 
+    /// Encrypts data with the link's transit key, and returns the ciphertext.
+    /// `context` is required for keys with key derivation
+    pub async fn encrypt(
+        &self,
+        plaintext: &[u8],
+        context: Option<&[u8]>,
+    ) -> Result<String, VaultError> {
+        let transit = self.transit()?;
+        let endpoint = api::EncryptRequest {
+            mount: transit.mount.clone(),
+            name: transit.key.clone(),
+            plaintext: BASE64.encode(plaintext),
+            context: context.map(|c| BASE64.encode(c)),
+        };
+        let response =
+            vaultrs::api::exec_with_result(self.inner_client().as_ref(), endpoint).await?;
+        Ok(response.ciphertext)
+    }
+
+    /// Decrypts ciphertext with the link's transit key, and returns the plaintext
+    pub async fn decrypt(
+        &self,
+        ciphertext: &str,
+        context: Option<&[u8]>,
+    ) -> Result<Vec<u8>, VaultError> {
+        let transit = self.transit()?;
+        let endpoint = api::DecryptRequest {
+            mount: transit.mount.clone(),
+            name: transit.key.clone(),
+            ciphertext: ciphertext.to_string(),
+            context: context.map(|c| BASE64.encode(c)),
+        };
+        let response =
+            vaultrs::api::exec_with_result(self.inner_client().as_ref(), endpoint).await?;
+        BASE64
+            .decode(response.plaintext)
+            .map_err(|e| VaultError::Transit(format!("invalid plaintext from vault: {}", e)))
+    }
+
+    /// Encrypts ciphertext again with the latest version of the link's transit key,
+    /// and returns the new ciphertext
+    pub async fn rewrap(
+        &self,
+        ciphertext: &str,
+        context: Option<&[u8]>,
+    ) -> Result<String, VaultError> {
+        let transit = self.transit()?;
+        let endpoint = api::RewrapRequest {
+            mount: transit.mount.clone(),
+            name: transit.key.clone(),
+            ciphertext: ciphertext.to_string(),
+            context: context.map(|c| BASE64.encode(c)),
+        };
+        let response =
+            vaultrs::api::exec_with_result(self.inner_client().as_ref(), endpoint).await?;
+        Ok(response.ciphertext)
+    }
+
+    /// Signs data with the link's transit key, and returns the signature
+    pub async fn sign(
+        &self,
+        input: &[u8],
+        hash_algorithm: Option<&str>,
+    ) -> Result<String, VaultError> {
+        let transit = self.transit()?;
+        let endpoint = api::SignRequest {
+            mount: transit.mount.clone(),
+            name: transit.key.clone(),
+            input: BASE64.encode(input),
+            hash_algorithm: hash_algorithm.map(String::from),
+        };
+        let response =
+            vaultrs::api::exec_with_result(self.inner_client().as_ref(), endpoint).await?;
+        Ok(response.signature)
+    }
+
+    /// Verifies a signature, or an HMAC, of data with the link's transit key
+    pub async fn verify(
+        &self,
+        input: &[u8],
+        signature: Option<&str>,
+        hmac: Option<&str>,
+        hash_algorithm: Option<&str>,
+    ) -> Result<bool, VaultError> {
+        let transit = self.transit()?;
+        let endpoint = api::VerifyRequest {
+            mount: transit.mount.clone(),
+            name: transit.key.clone(),
+            input: BASE64.encode(input),
+            signature: signature.map(String::from),
+            hmac: hmac.map(String::from),
+            hash_algorithm: hash_algorithm.map(String::from),
+        };
+        let response =
+            vaultrs::api::exec_with_result(self.inner_client().as_ref(), endpoint).await?;
+        Ok(response.valid)
+    }
+
+    /// Returns the HMAC of data with the link's transit key
+    pub async fn hmac(&self, input: &[u8], algorithm: Option<&str>) -> Result<String, VaultError> {
+        let transit = self.transit()?;
+        let endpoint = api::HmacRequest {
+            mount: transit.mount.clone(),
+            name: transit.key.clone(),
+            input: BASE64.encode(input),
+            algorithm: algorithm.map(String::from),
+        };
+        let response =
+            vaultrs::api::exec_with_result(self.inner_client().as_ref(), endpoint).await?;
+        Ok(response.hmac)
+    }
+
+    /// Returns the link's transit key, or Err(Transit) if the link has none
+    fn transit(&self) -> Result<&Transit, VaultError> {
+        self.transit
+            .as_ref()
+            .ok_or_else(|| VaultError::Transit("link has no transit_key".to_string()))
+    }
+
+    /// Returns the hit and miss counts of the cache, or None if the link has no cache
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(SecretCache::stats)
+    }
+
+    /// Logs the hit and miss counts of the cache, if the link has a cache
+    pub fn log_cache_stats(&self) {
+        if let Some(cache) = &self.cache {
+            cache.log_stats();
+        }
+    }
+
+    /// Removes the secret from the cache, now and again when the returned guard is dropped,
+    /// so that a value read while the secret is being changed isn't kept
+    fn invalidate_on_drop<'a>(&'a self, path: &'a str) -> Invalidate<'a> {
+        let guard = Invalidate {
+            cache: self.cache.as_ref(),
+            path,
+        };
+        guard.invalidate();
+        guard
+    }
+
+    /// Revokes the leases of all unexpired secrets read from a dynamic secrets engine.
+    /// Errors are logged, and the remaining leases are still revoked
+    pub async fn revoke_leases(&self) {
+        let leases = std::mem::take(&mut *self.leases.lock().unwrap());
+        let now = Instant::now();
+        let client = self.inner_client();
+        for lease in leases.into_iter().filter(|lease| lease.expires > now) {
+            let endpoint = api::RevokeLeaseRequest {
+                lease_id: lease.id.clone(),
+            };
+            match vaultrs::api::exec_with_empty(client.as_ref(), endpoint).await {
+                Ok(_) => debug!(lease_id = %lease.id, "revoked lease"),
+                Err(e) => error!(lease_id = %lease.id, "error revoking lease: {}", e),
+            }
+        }
+    }
+
+    /// Records the lease of a secret, so that it can be revoked. Expired leases are dropped
+    fn add_lease(&self, lease_id: &str, lease_duration: u32) {
+        if lease_id.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        let mut leases = self.leases.lock().unwrap();
+        leases.retain(|lease| lease.expires > now);
+        leases.push(Lease {
+            id: lease_id.to_string(),
+            expires: now + Duration::from_secs(lease_duration as u64),
+        });
+    }
+
+    /// Returns the endpoint that reads the secret at the path, for engines other than KV v2
+    fn secret_request(&self, path: &str) -> api::ReadSecretRequest {
+        api::ReadSecretRequest {
+            mount: self.namespace.clone(),
+            path: path.to_string(),
+        }
+    }
+
+    /// Returns Err(Unsupported) unless the mount is KV v2
+    fn require_kv2(&self, operation: &str) -> Result<(), VaultError> {
+        match self.engine {
+            Engine::Kv2 => Ok(()),
+            _ => Err(self.unsupported(operation)),
+        }
+    }
+
+    fn unsupported(&self, operation: &str) -> VaultError {
+        VaultError::Unsupported {
+            engine: self.engine.to_string(),
+            operation: operation.to_string(),
+        }
+    }
+
     /// Sets up a background task to renew the token at the configured interval. This function
     /// attempts to lock the renew_task mutex and will deadlock if called without first ensuring
     /// the lock is available.
+    ///
+    /// With a login auth method, this first logs in, so that the client has a token when this
+    /// returns. If the login fails, the error is logged and the background task retries it.
+    /// The task logs in again if renewing the token fails, or the token is close to its max TTL
     pub async fn set_renewal(&self) {
         let mut renew_task = self.renew_task.lock().await;
         if let Some(handle) = renew_task.take() {
             handle.abort();
         }
-        let client = self.inner.clone();
+        let shared = self.inner.clone();
         let interval = self.token_refresh_interval;
         let ttl = self.token_increment_ttl.clone();
 
+        if let Auth::Token(_) = self.auth {
+            *renew_task = Some(tokio::spawn(async move {
+                let mut next_interval = tokio::time::interval(interval);
+                loop {
+                    next_interval.tick().await;
+                    let client = shared.read().unwrap().clone();
+                    // NOTE(brooksmtownsend): Errors are appropriately logged in the function
+                    let _ = renew_self(&client, ttl.as_str()).await;
+                }
+            }));
+            return;
+        }
+
+        let auth = self.auth.clone();
+        let lease = login(&shared, &auth).await.ok();
         *renew_task = Some(tokio::spawn(async move {
-            let mut next_interval = tokio::time::interval(interval);
+            let mut lease = lease;
+            let mut failures = 0;
             loop {
-                next_interval.tick().await;
-                // NOTE(brooksmtownsend): Errors are appropriately logged in the function
-                let _ = renew_self(&client, ttl.as_str()).await;
+                let info = match lease.take() {
+                    Some(info) => info,
+                    None => match login(&shared, &auth).await {
+                        Ok(info) => info,
+                        Err(_) => {
+                            failures += 1;
+                            tokio::time::sleep(login_retry_delay(failures)).await;
+                            continue;
+                        }
+                    },
+                };
+                failures = 0;
+                keep_renewed(&shared, &info, interval, &ttl).await;
             }
         }));
     }
 }
 
+/// Renews the token from a login until renewal fails, the token can't be renewed,
+/// or the token is close to its max TTL, when it's time to log in again
+async fn keep_renewed(
+    shared: &RwLock<Arc<VaultClient>>,
+    info: &AuthInfo,
+    interval: Duration,
+    increment: &str,
+) {
+    let lease = Duration::from_secs(info.lease_duration);
+    // once a renewal returns less than this, the token is near its max TTL
+    let grace = lease / 10;
+    let mut ttl = lease;
+    loop {
+        tokio::time::sleep(renewal_delay(interval, ttl)).await;
+        if !info.renewable {
+            debug!("token is not renewable, logging in again");
+            return;
+        }
+        let client = shared.read().unwrap().clone();
+        match renew_self(&client, increment).await {
+            Ok(renewed) if renewed > grace || lease.is_zero() => ttl = renewed,
+            Ok(_) => {
+                info!("token is close to its max TTL, logging in again");
+                return;
+            }
+            // NOTE: Errors are logged in renew_self
+            Err(_) => return,
+        }
+    }
+}
+
+/// Returns the time to wait before renewing a token that expires in `ttl`:
+/// the refresh interval, or sooner if the token would expire before then
+fn renewal_delay(interval: Duration, ttl: Duration) -> Duration {
+    if ttl.is_zero() {
+        return interval;
+    }
+    interval.min(ttl * 2 / 3).max(Duration::from_secs(1))
+}
+
+/// Builds the http client, with the CA certificates, TLS verification, and client certificate
+/// of the link, and the namespace header
+fn http_client(
+    settings: &VaultClientSettings,
+    client_cert: Option<&ClientCert>,
+    namespace: Option<&str>,
+) -> Result<reqwest::Client, VaultError> {
+    let mut builder = reqwest::ClientBuilder::new();
+    if !settings.verify {
+        warn!("TLS verification is disabled, the server's certificate is not checked");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    for path in &settings.ca_certs {
+        let cert = reqwest::Certificate::from_pem(&read_file(path)?)
+            .map_err(|e| VaultError::Tls(format!("invalid CA certificate in '{}': {}", path, e)))?;
+        builder = builder.add_root_certificate(cert);
+    }
+    if let Some(ClientCert { cert, key }) = client_cert {
+        // the identity is parsed from a PEM file with both the certificate and the key
+        let mut pem = read_file(cert)?;
+        pem.push(b'\n');
+        pem.extend(read_file(key)?);
+        let identity = reqwest::Identity::from_pem(&pem).map_err(|e| {
+            VaultError::Tls(format!(
+                "invalid client certificate '{}' or key '{}': {}",
+                cert, key, e
+            ))
+        })?;
+        builder = builder.identity(identity);
+    }
+    if let Some(namespace) = namespace {
+        let value = reqwest::header::HeaderValue::from_str(namespace)
+            .map_err(|_| VaultError::Config(format!("invalid namespace '{}'", namespace)))?;
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(NAMESPACE_HEADER, value);
+        builder = builder.default_headers(headers);
+    }
+    builder
+        .build()
+        .map_err(|e| VaultError::Tls(format!("building http client: {}", e)))
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, VaultError> {
+    std::fs::read(path).map_err(|e| VaultError::Tls(format!("reading '{}': {}", path, e)))
+}
+
+/// Returns the delay before the next login, after `failures` failed attempts
+fn login_retry_delay(failures: u32) -> Duration {
+    LOGIN_RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(MAX_LOGIN_RETRY_DELAY)
+}
+
+/// Logs in with the auth method, and replaces the shared client with a client using the new token
+async fn login(shared: &RwLock<Arc<VaultClient>>, auth: &Auth) -> Result<AuthInfo, VaultError> {
+    let client = shared.read().unwrap().clone();
+    let result = match auth {
+        // tokens are not obtained by logging in
+        Auth::Token(_) => return Err(VaultError::Login("token auth has no login".to_string())),
+        Auth::AppRole {
+            mount,
+            role_id,
+            secret_id,
+        } => {
+            let secret_id = read_credential(secret_id)?;
+            vaultrs::auth::approle::login(client.as_ref(), mount, role_id, &secret_id).await
+        }
+        Auth::Kubernetes { mount, role, jwt } => {
+            let jwt = read_credential(jwt)?;
+            vaultrs::auth::kubernetes::login(client.as_ref(), mount, role, &jwt).await
+        }
+        Auth::Jwt { mount, role, jwt } => {
+            let jwt = read_credential(jwt)?;
+            vaultrs::auth::oidc::login(client.as_ref(), mount, &jwt, role.clone()).await
+        }
+        Auth::Cert { mount, role } => {
+            let endpoint = api::CertLoginRequest {
+                mount: mount.clone(),
+                name: role.clone(),
+            };
+            vaultrs::api::auth(client.as_ref(), endpoint).await
+        }
+    };
+    let info = result.map_err(|e| {
+        error!("error logging in to vault: {}", e);
+        VaultError::from(e)
+    })?;
+
+    // the new client shares the http client, with its TLS settings and headers
+    let mut settings = client.settings.clone();
+    settings.token = info.client_token.clone();
+    let client = VaultClient {
+        http: HttpClient::new(&client.http.base, client.http.http.clone()),
+        middle: EndpointMiddleware {
+            token: info.client_token.clone(),
+            ..client.middle.clone()
+        },
+        settings,
+    };
+    *shared.write().unwrap() = Arc::new(client);
+    info!(
+        accessor = %info.accessor,
+        lease_duration = info.lease_duration,
+        renewable = info.renewable,
+        "logged in to vault"
+    );
+    Ok(info)
+}
+
+/// Returns the value of the credential, reading it from its file if it has one
+fn read_credential(credential: &Credential) -> Result<String, VaultError> {
+    match credential {
+        Credential::Value(value) => Ok(value.clone()),
+        Credential::File(path) => std::fs::read_to_string(path)
+            .map(|value| value.trim().to_string())
+            .map_err(|e| {
+                error!(%path, "error reading vault login credential: {}", e);
+                VaultError::Login(format!("unable to read '{}': {}", path, e))
+            }),
+    }
+}
+
 impl Drop for Client {
     fn drop(&mut self) {
         // NOTE(brooksmtownsend): We're trying to lock here so we don't deadlock on dropping.
@@ -142,10 +938,11 @@ impl Drop for Client {
     }
 }
 
-/// Helper function to renew a client's token, incrementing the validity by `increment`
-async fn renew_self(client: &VaultClient, increment: &str) -> Result<(), VaultError> {
+/// Helper function to renew a client's token, incrementing the validity by `increment`.
+/// Returns the renewed token's TTL
+async fn renew_self(client: &VaultClient, increment: &str) -> Result<Duration, VaultError> {
     debug!("renewing token");
-    client.renew(Some(increment)).await.map_err(|e| {
+    let renewed = client.renew(Some(increment)).await.map_err(|e| {
         error!("error renewing self token: {}", e);
         VaultError::from(e)
     })?;
@@ -157,5 +954,5 @@ async fn renew_self(client: &VaultClient, increment: &str) -> Result<(), VaultEr
 
     let expire_time = info.expire_time.unwrap_or_else(|| "None".to_string());
     info!(%expire_time, accessor = %info.accessor, "renewed token");
-    Ok(())
+    Ok(Duration::from_secs(renewed.lease_duration))
 }
@@ -0,0 +1,61 @@
+//! Lists, sets, and counters stored as secrets
+//!
+//! Vault stores a secret as a json map, so a list or set is stored as a map with a single
+//! marker key whose value is a json array of strings. Sets are kept sorted. A counter is
+//! stored like any other string value, so `get` returns its value.
+//!
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+/// A list of strings, stored as `{ "list_data___": [ ... ] }`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct List {
+    #[serde(rename = "list_data___")]
+    pub items: Vec<String>,
+}
+
+impl List {
+    /// Returns the items from `start` to `stop`, inclusive. As with Redis LRANGE,
+    /// negative indexes count from the end of the list, and a `stop` beyond the end
+    /// of the list is treated as the end of the list
+    pub fn range(&self, start: i32, stop: i32) -> Vec<String> {
+        let len = self.items.len() as i64;
+        let index = |i: i32| if i < 0 { len + i as i64 } else { i as i64 };
+        let start = index(start).max(0);
+        let stop = index(stop).min(len - 1);
+        if start > stop {
+            return Vec::new();
+        }
+        self.items[start as usize..=stop as usize].to_vec()
+    }
+}
+
+/// A set of strings, stored as `{ "set_data___": [ ... ] }`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Set {
+    #[serde(rename = "set_data___")]
+    pub members: BTreeSet<String>,
+}
+
+/// A counter, stored as `{ "string_data___": "<number>" }`, the same as a string value set
+/// with `set`. Secrets whose value is not an integer can't be deserialized as a counter
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Counter {
+    #[serde(rename = "string_data___", with = "number_string")]
+    pub value: i32,
+}
+
+/// (De)serializes a number as a string
+mod number_string {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.trim().parse().map_err(D::Error::custom)
+    }
+}
@@ -1,28 +1,161 @@
 //! Configuration for kv-vault capability provider
 //!
 use crate::client::TOKEN_REFRESH_INTERVAL;
+use reqwest::header::HeaderValue;
 use std::{collections::HashMap, env};
 use url::Url;
 use wasmbus_rpc::error::{RpcError, RpcResult};
 
 const DEFAULT_VAULT_ADDR: &str = "http://127.0.0.1:8200";
 
+/// Maximum number of values in the cache, unless set with `cache_max_entries`
+const DEFAULT_CACHE_MAX_ENTRIES: u64 = 1000;
+
+/// Service account token mounted in kubernetes pods
+const DEFAULT_KUBERNETES_JWT_FILE: &str = "/var/run/secrets/kubernetes.io/serviceaccount/token";
+
+/// How the provider authenticates to vault
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Auth {
+    /// A token, renewed until it expires
+    Token(String),
+    /// Login with an AppRole role id and secret id
+    AppRole {
+        mount: String,
+        role_id: String,
+        secret_id: Credential,
+    },
+    /// Login with a kubernetes service account token
+    Kubernetes {
+        mount: String,
+        role: String,
+        jwt: Credential,
+    },
+    /// Login with a JWT or OIDC id token. The role may be omitted if the auth mount
+    /// has a default role
+    Jwt {
+        mount: String,
+        role: Option<String>,
+        jwt: Credential,
+    },
+    /// Login with the link's TLS client certificate. The role may be omitted to log in with
+    /// any role whose certificate matches
+    Cert { mount: String, role: Option<String> },
+}
+
+/// The secrets engine mounted at the mount point
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Engine {
+    /// KV version 2, which keeps versions of each secret
+    Kv2,
+    /// KV version 1
+    Kv1,
+    /// An engine that creates a credential with a lease when it's read, such as the
+    /// database or AWS engines. Secrets can only be read
+    Dynamic,
+}
+
+impl std::fmt::Display for Engine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Engine::Kv2 => "kv2",
+            Engine::Kv1 => "kv1",
+            Engine::Dynamic => "dynamic",
+        })
+    }
+}
+
+/// The transit key used for encryption, signing, and HMAC
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transit {
+    /// mount point of the transit secrets engine
+    pub mount: String,
+    /// name of the key
+    pub key: String,
+}
+
+/// Files of the certificate and private key the client presents when vault requests
+/// a TLS client certificate
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientCert {
+    /// PEM file of the certificate
+    pub cert: String,
+    /// PEM file of the private key
+    pub key: String,
+}
+
+/// Settings of the read-through cache of secret values
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cache {
+    /// how long a value is kept
+    pub ttl: std::time::Duration,
+    /// the maximum number of values kept
+    pub max_entries: usize,
+}
+
+/// A secret used to log in, given directly or read from a file. Files are read on each login,
+/// so that credentials rotated by the platform, such as projected service account tokens,
+/// are picked up
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Credential {
+    Value(String),
+    File(String),
+}
+
 /// KV-Vault configuration
 #[derive(Clone, Debug)]
 pub struct Config {
-    /// Token for connecting to vault, can be set in environment with VAULT_TOKEN.
-    /// Required
-    pub token: String,
+    /// How to authenticate. `auth_method`, or VAULT_AUTH_METHOD, selects the method:
+    /// - `token` (default): the token from `token` or VAULT_TOKEN. Required
+    /// - `approle`: `role_id` and `secret_id` (or a `secret_id_file`)
+    /// - `kubernetes`: `role`, and the service account token from `jwt_file`
+    ///   (default: the token mounted in the pod)
+    /// - `jwt`: optionally a `role`, and `jwt` (or a `jwt_file`)
+    /// - `cert`: optionally a `role`, and the TLS client certificate in `client_cert`. Required
+    ///
+    /// `auth_mount` sets the mount point of the auth method, which defaults to the method name.
+    /// Each setting can also be set in the environment, for example VAULT_ROLE_ID
+    pub auth: Auth,
     /// Url for connecting to vault, can be set in environment with VAULT_ADDR.
     /// Defaults to 'http://127.0.0.1:8200'
     pub addr: Url,
+    /// Vault Enterprise namespace, sent in the X-Vault-Namespace header of all requests,
+    /// including logins. Can be set in the environment with VAULT_NAMESPACE, which is used
+    /// if the link doesn't set it
+    pub namespace: Option<String>,
     /// Vault mount point, can be set with in environment with VAULT_MOUNT.
     /// Efaults to "secret/"
     pub mount: String,
-    /// certificate files - path to CA certificate file(s). Setting this enables TLS
+    /// The secrets engine at the mount point: `kv2` (default), `kv1`, or `dynamic`.
+    /// Can be set in the environment with VAULT_ENGINE, which is used if the link doesn't set it
+    pub engine: Engine,
+    /// Prefix of the paths of all keys used by the link, so that links sharing a token and
+    /// mount don't see each other's secrets. Can be set in the environment with VAULT_PATH_PREFIX,
+    /// which is used if the link doesn't set it.
+    /// Keys can't contain `..` segments, so they can't refer to paths outside the prefix
+    pub path_prefix: Option<String>,
+    /// The transit key for the link's Encrypt, Decrypt, Rewrap, Sign, Verify, and Hmac
+    /// operations. Set with `transit_key`, or VAULT_TRANSIT_KEY, and `transit_mount`,
+    /// or VAULT_TRANSIT_MOUNT, which defaults to "transit". The environment variables are
+    /// used if the link doesn't set them. None if there is no key
+    pub transit: Option<Transit>,
+    /// Read-through cache of secret values, enabled by setting `cache_ttl`, or VAULT_CACHE_TTL,
+    /// to the number of seconds values are kept. `cache_max_entries`, or VAULT_CACHE_MAX_ENTRIES,
+    /// limits the number of values, and defaults to 1000. The environment variables are used
+    /// if the link doesn't set them. None if the cache is disabled
+    pub cache: Option<Cache>,
+    /// certificate files - path to CA certificate file(s), used with the system's trusted roots
+    /// to verify the server's certificate
     /// The linkdef value `certs` and the environment variable `VAULT_CERTS`
     /// are parsed as a comma-separated string of file paths to generate this list.
     pub certs: Vec<String>,
+    /// Don't verify the server's TLS certificate. Only for development servers with
+    /// self-signed certificates. `tls_skip_verify`, or VAULT_SKIP_VERIFY, set to `true`
+    /// disables verification. Defaults to false
+    pub tls_skip_verify: bool,
+    /// TLS client certificate, from the files in `client_cert` and `client_key`, or
+    /// VAULT_CLIENT_CERT and VAULT_CLIENT_KEY. None if there is no client certificate
+    pub client_cert: Option<ClientCert>,
 
     /// Renewal TTL for tokens used by this provider. Defaults to 72 hours.
     pub token_increment_ttl: Option<String>,
@@ -63,18 +196,69 @@ impl Config {
                     );
                     DEFAULT_VAULT_ADDR.parse().unwrap()
                 }),
-            token: env::var("VAULT_TOKEN")
-                .ok()
-                .or_else(|| values.get("token").cloned())
-                .or_else(|| values.get("TOKEN").cloned())
-                .ok_or_else(|| {
-                    RpcError::ProviderInit("missing setting for 'token' or VAULT_TOKEN".to_string())
-                })?,
+            auth: auth_from_values(values)?,
+            namespace: match link_setting(values, "VAULT_NAMESPACE", "namespace")
+                .map(|namespace| namespace.trim_matches('/').to_string())
+                .filter(|namespace| !namespace.is_empty())
+            {
+                // the namespace is sent in a header, so it can only have visible ascii characters
+                Some(namespace) if HeaderValue::from_str(&namespace).is_err() => {
+                    return Err(RpcError::ProviderInit(format!(
+                        "invalid namespace '{}': must only contain visible ascii characters",
+                        namespace.escape_debug()
+                    )))
+                }
+                namespace => namespace,
+            },
             mount: env::var("VAULT_MOUNT")
                 .ok()
                 .or_else(|| values.get("mount").cloned())
                 .or_else(|| values.get("MOUNT").cloned())
                 .unwrap_or_else(|| "secret".to_string()),
+            engine: engine_from_values(values)?,
+            path_prefix: match link_setting(values, "VAULT_PATH_PREFIX", "path_prefix") {
+                Some(prefix) if prefix.split('/').any(|segment| segment == "..") => {
+                    return Err(RpcError::ProviderInit(format!(
+                        "invalid path_prefix '{}': must not contain '..'",
+                        prefix
+                    )))
+                }
+                Some(prefix) => {
+                    Some(prefix.trim_matches('/').to_string()).filter(|p| !p.is_empty())
+                }
+                None => None,
+            },
+            transit: link_setting(values, "VAULT_TRANSIT_KEY", "transit_key").map(|key| Transit {
+                mount: link_setting(values, "VAULT_TRANSIT_MOUNT", "transit_mount")
+                    .unwrap_or_else(|| "transit".to_string()),
+                key,
+            }),
+            cache: cache_from_values(values)?,
+            tls_skip_verify: match setting(values, "VAULT_SKIP_VERIFY", "tls_skip_verify")
+                .map(|val| val.trim().to_ascii_lowercase())
+                .as_deref()
+            {
+                None | Some("false") | Some("0") => false,
+                Some("true") | Some("1") => true,
+                Some(val) => {
+                    return Err(RpcError::ProviderInit(format!(
+                        "invalid tls_skip_verify '{}', expected true or false",
+                        val
+                    )))
+                }
+            },
+            client_cert: match (
+                setting(values, "VAULT_CLIENT_CERT", "client_cert"),
+                setting(values, "VAULT_CLIENT_KEY", "client_key"),
+            ) {
+                (Some(cert), Some(key)) => Some(ClientCert { cert, key }),
+                (None, None) => None,
+                _ => {
+                    return Err(RpcError::ProviderInit(
+                        "client_cert and client_key must be set together".to_string(),
+                    ))
+                }
+            },
             certs: match env::var("VAULT_CERTS")
                 .ok()
                 .or_else(|| values.get("certs").cloned())
@@ -105,6 +289,217 @@ impl Config {
                 _ => None,
             },
         };
+        if matches!(config.auth, Auth::Cert { .. }) && config.client_cert.is_none() {
+            return Err(RpcError::ProviderInit(
+                "the cert auth method requires client_cert and client_key".to_string(),
+            ));
+        }
         Ok(config)
     }
 }
+
+/// Returns a setting from the environment variable, or from the link value
+/// in lowercase or uppercase
+fn setting(values: &HashMap<String, String>, env_name: &str, name: &str) -> Option<String> {
+    env::var(env_name)
+        .ok()
+        .or_else(|| values.get(name).cloned())
+        .or_else(|| values.get(&name.to_uppercase()).cloned())
+}
+
+/// Returns a setting from the link value in lowercase or uppercase, or from the environment
+/// variable. Used for settings that differ between links, which the environment of the
+/// provider can't set for only one of them
+fn link_setting(values: &HashMap<String, String>, env_name: &str, name: &str) -> Option<String> {
+    values
+        .get(name)
+        .or_else(|| values.get(&name.to_uppercase()))
+        .cloned()
+        .or_else(|| env::var(env_name).ok())
+}
+
+fn required(values: &HashMap<String, String>, env_name: &str, name: &str) -> RpcResult<String> {
+    setting(values, env_name, name).ok_or_else(|| {
+        RpcError::ProviderInit(format!("missing setting for '{}' or {}", name, env_name))
+    })
+}
+
+/// Returns the credential from the setting `name`, or the file named by `<name>_file`
+fn credential(
+    values: &HashMap<String, String>,
+    env_name: &str,
+    name: &str,
+    default_file: Option<&str>,
+) -> RpcResult<Credential> {
+    if let Some(value) = setting(values, env_name, name) {
+        return Ok(Credential::Value(value));
+    }
+    let file_name = format!("{}_file", name);
+    setting(values, &format!("{}_FILE", env_name), &file_name)
+        .or_else(|| default_file.map(String::from))
+        .map(Credential::File)
+        .ok_or_else(|| {
+            RpcError::ProviderInit(format!(
+                "missing setting for '{}' or '{}', or {}",
+                name, file_name, env_name
+            ))
+        })
+}
+
+fn auth_from_values(values: &HashMap<String, String>) -> RpcResult<Auth> {
+    let method = setting(values, "VAULT_AUTH_METHOD", "auth_method")
+        .unwrap_or_else(|| "token".to_string())
+        .to_ascii_lowercase();
+    let mount = || setting(values, "VAULT_AUTH_MOUNT", "auth_mount").unwrap_or(method.clone());
+    let auth = match method.as_str() {
+        "token" => Auth::Token(required(values, "VAULT_TOKEN", "token")?),
+        "approle" => Auth::AppRole {
+            mount: mount(),
+            role_id: required(values, "VAULT_ROLE_ID", "role_id")?,
+            secret_id: credential(values, "VAULT_SECRET_ID", "secret_id", None)?,
+        },
+        "kubernetes" => Auth::Kubernetes {
+            mount: mount(),
+            role: required(values, "VAULT_ROLE", "role")?,
+            jwt: credential(
+                values,
+                "VAULT_JWT",
+                "jwt",
+                Some(DEFAULT_KUBERNETES_JWT_FILE),
+            )?,
+        },
+        "jwt" => Auth::Jwt {
+            mount: mount(),
+            role: setting(values, "VAULT_ROLE", "role"),
+            jwt: credential(values, "VAULT_JWT", "jwt", None)?,
+        },
+        "cert" => Auth::Cert {
+            mount: mount(),
+            role: setting(values, "VAULT_ROLE", "role"),
+        },
+        _ => {
+            return Err(RpcError::ProviderInit(format!(
+                "invalid auth_method '{}', expected token, approle, kubernetes, jwt, or cert",
+                method
+            )))
+        }
+    };
+    Ok(auth)
+}
+
+fn cache_from_values(values: &HashMap<String, String>) -> RpcResult<Option<Cache>> {
+    let number = |env_name: &str, name: &str| {
+        link_setting(values, env_name, name)
+            .map(|val| {
+                val.trim().parse::<u64>().map_err(|_| {
+                    RpcError::ProviderInit(format!("invalid {} '{}', expected a number", name, val))
+                })
+            })
+            .transpose()
+    };
+    let ttl = match number("VAULT_CACHE_TTL", "cache_ttl")? {
+        None | Some(0) => return Ok(None),
+        Some(secs) => std::time::Duration::from_secs(secs),
+    };
+    let max_entries = number("VAULT_CACHE_MAX_ENTRIES", "cache_max_entries")?
+        .unwrap_or(DEFAULT_CACHE_MAX_ENTRIES);
+    Ok(Some(Cache {
+        ttl,
+        max_entries: max_entries as usize,
+    }))
+}
+
+fn engine_from_values(values: &HashMap<String, String>) -> RpcResult<Engine> {
+    match link_setting(values, "VAULT_ENGINE", "engine")
+        .map(|engine| engine.to_ascii_lowercase())
+        .as_deref()
+    {
+        None | Some("kv2") => Ok(Engine::Kv2),
+        Some("kv1") => Ok(Engine::Kv1),
+        Some("dynamic") => Ok(Engine::Dynamic),
+        Some(engine) => Err(RpcError::ProviderInit(format!(
+            "invalid engine '{}', expected kv2, kv1, or dynamic",
+            engine
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Settings that differ between links are read from the link before the environment
+    #[test]
+    fn link_values_override_environment() {
+        // (link value name, environment variable, environment value, link value)
+        let settings = [
+            ("engine", "VAULT_ENGINE", "kv1", "dynamic"),
+            ("transit_key", "VAULT_TRANSIT_KEY", "shared", "orders"),
+            (
+                "transit_mount",
+                "VAULT_TRANSIT_MOUNT",
+                "transit",
+                "transit-orders",
+            ),
+            ("path_prefix", "VAULT_PATH_PREFIX", "shared", "/actor_c/"),
+            ("cache_ttl", "VAULT_CACHE_TTL", "60", "30"),
+            ("cache_max_entries", "VAULT_CACHE_MAX_ENTRIES", "500", "10"),
+            ("namespace", "VAULT_NAMESPACE", "team-b", "team-a/"),
+        ];
+        for (_, env_name, env_value, _) in settings {
+            env::set_var(env_name, env_value);
+        }
+        let mut values: HashMap<String, String> = settings
+            .iter()
+            .map(|(name, _, _, link_value)| (name.to_string(), link_value.to_string()))
+            .collect();
+        values.insert("token".to_string(), "test-token".to_string());
+        let from_link = Config::from_values(&values);
+        let from_env = Config::from_values(&HashMap::from([(
+            "token".to_string(),
+            "test-token".to_string(),
+        )]));
+        for (_, env_name, _, _) in settings {
+            env::remove_var(env_name);
+        }
+
+        let from_link = from_link.expect("configuration to be valid");
+        assert_eq!(from_link.engine, Engine::Dynamic);
+        assert_eq!(from_link.path_prefix.as_deref(), Some("actor_c"));
+        assert_eq!(from_link.namespace.as_deref(), Some("team-a"));
+        assert_eq!(
+            from_link.cache,
+            Some(Cache {
+                ttl: std::time::Duration::from_secs(30),
+                max_entries: 10,
+            })
+        );
+        assert_eq!(
+            from_link.transit,
+            Some(Transit {
+                mount: "transit-orders".to_string(),
+                key: "orders".to_string(),
+            })
+        );
+
+        // the environment is used if the link doesn't set them
+        let from_env = from_env.expect("configuration to be valid");
+        assert_eq!(from_env.engine, Engine::Kv1);
+        assert_eq!(from_env.path_prefix.as_deref(), Some("shared"));
+        assert_eq!(from_env.namespace.as_deref(), Some("team-b"));
+        assert_eq!(
+            from_env.cache,
+            Some(Cache {
+                ttl: std::time::Duration::from_secs(60),
+                max_entries: 500,
+            })
+        );
+        assert_eq!(
+            from_env.transit,
+            Some(Transit {
+                mount: "transit".to_string(),
+                key: "shared".to_string(),
+            })
+        );
+    }
+}
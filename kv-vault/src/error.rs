@@ -9,6 +9,45 @@ pub enum VaultError {
     #[error("Key not found: namespace/key {namespace}/{path}")]
     NotFound { namespace: String, path: String },
 
+    /// The secret holds a different type of value than the operation expects,
+    /// for example a list operation on a secret that is not a list
+    #[error("Invalid value: namespace/key {namespace}/{path} has the wrong type of value")]
+    InvalidValue { namespace: String, path: String },
+
+    /// The key is not a valid path under the link's path prefix,
+    /// for example because it contains a `..` segment
+    #[error("Invalid path: {path}")]
+    InvalidPath { path: String },
+
+    /// The secret was written by another client between reading and writing it,
+    /// more times than the writer was willing to retry
+    #[error("Version conflict: namespace/key {namespace}/{path} was changed by another writer")]
+    VersionConflict { namespace: String, path: String },
+
+    /// The operation can't be used with the link's secrets engine, for example
+    /// an operation that uses versions on a KV v1 mount
+    #[error("Unsupported: {operation} is not supported by the {engine} secrets engine")]
+    Unsupported { engine: String, operation: String },
+
+    /// A transit operation was used without a transit key, or vault returned invalid data
+    #[error("Transit error: {0}")]
+    Transit(String),
+
+    /// Logging in failed before the request was sent to vault,
+    /// for example because a credential file couldn't be read
+    #[error("Login error: {0}")]
+    Login(String),
+
+    /// A setting of the link is invalid, for example a namespace that can't be sent
+    /// in a header
+    #[error("Invalid setting: {0}")]
+    Config(String),
+
+    /// The TLS or http settings of the link are invalid, for example because a certificate
+    /// file couldn't be read
+    #[error("TLS error: {0}")]
+    Tls(String),
+
     /// All other errors
     #[error("An error occurred with the request")]
     Client {
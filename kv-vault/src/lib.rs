@@ -1,4 +1,7 @@
+pub mod api;
+pub mod cache;
 pub mod client;
+pub mod collections;
 pub mod config;
 pub mod error;
 
@@ -1,9 +1,15 @@
 //! Hashicorp Vault implementation of the wasmcloud KeyValue capability contract wasmcloud:keyvalue
 //!
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::sync::Arc;
 
-use kv_vault_lib::{client::Client, config::Config, error::VaultError, STRING_VALUE_MARKER};
+use kv_vault_lib::{
+    client::Client,
+    collections::{Counter, List, Set},
+    config::Config,
+    error::VaultError,
+    STRING_VALUE_MARKER,
+};
 use serde_json::Value;
 use tokio::sync::RwLock;
 use tracing::{debug, instrument};
@@ -13,6 +19,17 @@ use wasmcloud_interface_keyvalue::{
     ListRangeRequest, SetAddRequest, SetDelRequest, SetRequest, StringList,
 };
 
+// this is not an external library - built locally via build.rs & codegen.toml
+#[allow(dead_code)]
+pub mod wasmcloud_interface_kvvault {
+    include!(concat!(env!("OUT_DIR"), "/gen/kvvault.rs"));
+}
+use wasmcloud_interface_kvvault::{
+    DecryptRequest, EncryptRequest, GetVersionRequest, GetVersionResponse, HmacRequest, KvVault,
+    KvVaultReceiver, RollbackRequest, SignRequest, VerifyRequest, VersionInfo, VersionsRequest,
+    VersionsResponse,
+};
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // handle lattice control messages and forward rpc to the provider dispatch
     // returns when provider receives a shutdown control message
@@ -27,7 +44,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 /// Redis keyValue provider implementation.
 #[derive(Default, Clone, Provider)]
-#[services(KeyValue)]
+#[services(KeyValue, KvVault)]
 struct KvVaultProvider {
     // store vault connection per actor
     actors: Arc<RwLock<HashMap<String, Arc<Client>>>>,
@@ -49,16 +66,26 @@ impl ProviderHandler for KvVaultProvider {
         let client = Client::new(config).map_err(to_rpc_err)?;
         client.set_renewal().await;
         let mut update_map = self.actors.write().await;
-        update_map.insert(ld.actor_id.to_string(), Arc::new(client));
+        let previous = update_map.insert(ld.actor_id.to_string(), Arc::new(client));
+        drop(update_map);
+        // the replaced client's dynamic secrets are revoked, as when the link is deleted
+        if let Some(client) = previous {
+            debug!("replacing link for actor");
+            client.log_cache_stats();
+            client.revoke_leases().await;
+        }
         Ok(true)
     }
 
-    /// Handle notification that a link is dropped - close the connection
+    /// Handle notification that a link is dropped - revoke the leases of dynamic secrets
+    /// read by the actor, and close the connection
     #[instrument(level = "debug", skip(self))]
     async fn delete_link(&self, actor_id: &str) {
         let mut aw = self.actors.write().await;
         if let Some(client) = aw.remove(actor_id) {
             debug!("deleting link for actor");
+            client.log_cache_stats();
+            client.revoke_leases().await;
             drop(client)
         }
     }
@@ -68,6 +95,8 @@ impl ProviderHandler for KvVaultProvider {
         let mut aw = self.actors.write().await;
         // empty the actor link data and stop all servers
         for (_, client) in aw.drain() {
+            client.log_cache_stats();
+            client.revoke_leases().await;
             drop(client)
         }
         Ok(())
@@ -75,36 +104,84 @@ impl ProviderHandler for KvVaultProvider {
 }
 
 fn to_rpc_err(e: VaultError) -> RpcError {
-    RpcError::Other(format!("vault error: {}", e))
+    match e {
+        VaultError::InvalidValue { .. }
+        | VaultError::InvalidPath { .. }
+        | VaultError::Transit(_)
+        | VaultError::Config(_) => RpcError::InvalidParameter(e.to_string()),
+        VaultError::Unsupported { .. } => {
+            debug!(error = %e, "unsupported operation");
+            RpcError::NotImplemented
+        }
+        e => RpcError::Other(format!("vault error: {}", e)),
+    }
+}
+
+/// Converts a secret to the value returned by get and get_version, as described on get
+fn value_to_string(value: Value) -> String {
+    match value {
+        Value::Object(mut map) => {
+            if let Some(Value::String(value)) = map.remove(STRING_VALUE_MARKER) {
+                value
+            } else {
+                serde_json::to_string(&map).unwrap()
+            }
+        }
+        Value::String(value) => value,
+        value => serde_json::to_string(&value).unwrap(),
+    }
 }
 
 /// Handle KeyValue methods that interact with redis
 #[async_trait]
 impl KeyValue for KvVaultProvider {
-    /// Increments a numeric value, returning the new value
-    async fn increment(&self, _ctx: &Context, _arg: &IncrementRequest) -> RpcResult<i32> {
-        Err(RpcError::NotImplemented)
+    /// Increments a numeric value, returning the new value. A key that doesn't exist is
+    /// treated as 0. The new value is written with check-and-set, so concurrent increments
+    /// are retried rather than lost
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.key))]
+    async fn increment(&self, ctx: &Context, arg: &IncrementRequest) -> RpcResult<i32> {
+        let (client, path) = self.get_client_path(ctx, &arg.key).await?;
+        let value = client
+            .update_secret(&path, |counter: Option<Counter>| {
+                match counter.unwrap_or_default().value.checked_add(arg.value) {
+                    Some(value) => (Some(Counter { value }), Some(value)),
+                    None => (None, None),
+                }
+            })
+            .await
+            .map_err(to_rpc_err)?;
+        value.ok_or_else(|| {
+            RpcError::InvalidParameter(format!("increment of '{}' would overflow", arg.key))
+        })
     }
 
-    /// Returns true if the store contains the key
+    /// Returns true if the store contains the key. Not supported with a dynamic secrets engine,
+    /// which would create a credential for each check
     #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, arg = %arg.to_string()))]
     async fn contains<TS: ToString + ?Sized + Sync>(
         &self,
         ctx: &Context,
         arg: &TS,
     ) -> RpcResult<bool> {
-        Ok(matches!(
-            self.get(ctx, arg).await,
-            Ok(GetResponse { exists: true, .. })
-        ))
+        let (client, path) = match self.get_client_path(ctx, &arg.to_string()).await {
+            Ok(client_path) => client_path,
+            Err(_) => return Ok(false),
+        };
+        match client.secret_exists(&path).await {
+            Ok(exists) => Ok(exists),
+            Err(e @ VaultError::Unsupported { .. }) => Err(to_rpc_err(e)),
+            Err(e) => {
+                debug!(error = %e, "vault read: other error");
+                Ok(false)
+            }
+        }
     }
 
     /// Deletes a key, returning true if the key was deleted
     #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, arg = %arg.to_string()))]
     async fn del<TS: ToString + ?Sized + Sync>(&self, ctx: &Context, arg: &TS) -> RpcResult<bool> {
-        let client = self.get_client(ctx).await?;
-
-        match client.delete_latest::<String>(&arg.to_string()).await {
+        let (client, path) = self.get_client_path(ctx, &arg.to_string()).await?;
+        match client.delete_latest::<String>(&path).await {
             Ok(_) => Ok(true),
             Err(VaultError::NotFound { namespace, path }) => {
                 debug!(%namespace, %path, "vault delete NotFound error");
@@ -128,27 +205,10 @@ impl KeyValue for KvVaultProvider {
         ctx: &Context,
         arg: &TS,
     ) -> RpcResult<GetResponse> {
-        let client = self.get_client(ctx).await?;
-        match client.read_secret::<Value>(&arg.to_string()).await {
-            Ok(Value::Object(mut map)) => {
-                if let Some(Value::String(value)) = map.remove(STRING_VALUE_MARKER) {
-                    Ok(GetResponse {
-                        value,
-                        exists: true,
-                    })
-                } else {
-                    Ok(GetResponse {
-                        value: serde_json::to_string(&map).unwrap(),
-                        exists: true,
-                    })
-                }
-            }
-            Ok(Value::String(value)) => Ok(GetResponse {
-                value,
-                exists: true,
-            }),
+        let (client, path) = self.get_client_path(ctx, &arg.to_string()).await?;
+        match client.read_secret::<Value>(&path).await {
             Ok(value) => Ok(GetResponse {
-                value: serde_json::to_string(&value).unwrap(),
+                value: value_to_string(value),
                 exists: true,
             }),
             Err(VaultError::NotFound { namespace, path }) => {
@@ -169,52 +229,85 @@ impl KeyValue for KvVaultProvider {
     }
 
     /// Append a value onto the end of a list. Returns the new list size
-    async fn list_add(&self, _ctx: &Context, _arg: &ListAddRequest) -> RpcResult<u32> {
-        Err(RpcError::NotImplemented)
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, list_name = %arg.list_name))]
+    async fn list_add(&self, ctx: &Context, arg: &ListAddRequest) -> RpcResult<u32> {
+        let (client, path) = self.get_client_path(ctx, &arg.list_name).await?;
+        client
+            .update_secret(&path, |list: Option<List>| {
+                let mut list = list.unwrap_or_default();
+                list.items.push(arg.value.clone());
+                let len = list.items.len() as u32;
+                (Some(list), len)
+            })
+            .await
+            .map_err(to_rpc_err)
     }
 
-    /// Deletes a list and its contents
+    /// Deletes a list and its contents, including all versions
     /// input: list name
     /// returns: true if the list existed and was deleted
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, arg = %arg.to_string()))]
     async fn list_clear<TS: ToString + ?Sized + Sync>(
         &self,
-        _ctx: &Context,
-        _arg: &TS,
+        ctx: &Context,
+        arg: &TS,
     ) -> RpcResult<bool> {
-        Err(RpcError::NotImplemented)
+        self.delete_all(ctx, &arg.to_string()).await
     }
 
     /// Deletes an item from a list. Returns true if the item was removed.
-    async fn list_del(&self, _ctx: &Context, _arg: &ListDelRequest) -> RpcResult<bool> {
-        Err(RpcError::NotImplemented)
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, list_name = %arg.list_name))]
+    async fn list_del(&self, ctx: &Context, arg: &ListDelRequest) -> RpcResult<bool> {
+        let (client, path) = self.get_client_path(ctx, &arg.list_name).await?;
+        client
+            .update_secret(&path, |list: Option<List>| {
+                let mut list = list.unwrap_or_default();
+                match list.items.iter().position(|item| *item == arg.value) {
+                    Some(index) => {
+                        list.items.remove(index);
+                        (Some(list), true)
+                    }
+                    None => (None, false),
+                }
+            })
+            .await
+            .map_err(to_rpc_err)
     }
 
     /// Retrieves a range of values from a list using 0-based indices.
     /// Start and end values are inclusive, for example, (0,10) returns
     /// 11 items if the list contains at least 11 items. If the stop value
     /// is beyond the end of the list, it is treated as the end of the list.
-    async fn list_range(&self, _ctx: &Context, _arg: &ListRangeRequest) -> RpcResult<StringList> {
-        Err(RpcError::NotImplemented)
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, list_name = %arg.list_name))]
+    async fn list_range(&self, ctx: &Context, arg: &ListRangeRequest) -> RpcResult<StringList> {
+        let (client, path) = self.get_client_path(ctx, &arg.list_name).await?;
+        let (list, _) = client
+            .read_secret_versioned::<List>(&path)
+            .await
+            .map_err(to_rpc_err)?;
+        Ok(list.unwrap_or_default().range(arg.start, arg.stop))
     }
 
-    /// Sets the value of a key.
+    /// Sets the value of a key. A value that is a json map is stored as the secret's data,
+    /// and any other value is stored as a string.
     /// expiration times are not supported by this api and should be 0.
     #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.key))]
     async fn set(&self, ctx: &Context, arg: &SetRequest) -> RpcResult<()> {
-        let client = self.get_client(ctx).await?;
-        let value: Value = serde_json::from_str(&arg.value).unwrap_or_else(|_| {
-            let mut map = serde_json::Map::new();
-            map.insert(
-                STRING_VALUE_MARKER.to_string(),
-                Value::String(arg.value.clone()),
-            );
-            Value::Object(map)
-        });
-        match client.write_secret(&arg.key, &value).await {
-            Ok(metadata) => {
-                debug!(?metadata, "set returned metadata");
-                Ok(())
+        let (client, path) = self.get_client_path(ctx, &arg.key).await?;
+        let value = match serde_json::from_str(&arg.value) {
+            Ok(Value::Object(map)) => Value::Object(map),
+            // vault secrets are maps, so other values are wrapped like strings
+            _ => {
+                let mut map = serde_json::Map::new();
+                map.insert(
+                    STRING_VALUE_MARKER.to_string(),
+                    Value::String(arg.value.clone()),
+                );
+                Value::Object(map)
             }
+        };
+        match client.write_secret(&path, &value).await {
+            Ok(()) => Ok(()),
             Err(VaultError::NotFound { namespace, path }) => {
                 debug!(
                     %namespace, %path,
@@ -230,32 +323,64 @@ impl KeyValue for KvVaultProvider {
     }
 
     /// Add an item into a set. Returns number of items added
-    async fn set_add(&self, _ctx: &Context, _arg: &SetAddRequest) -> RpcResult<u32> {
-        Err(RpcError::NotImplemented)
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, set_name = %arg.set_name))]
+    async fn set_add(&self, ctx: &Context, arg: &SetAddRequest) -> RpcResult<u32> {
+        let (client, path) = self.get_client_path(ctx, &arg.set_name).await?;
+        client
+            .update_secret(&path, |set: Option<Set>| {
+                let mut set = set.unwrap_or_default();
+                if set.members.insert(arg.value.clone()) {
+                    (Some(set), 1)
+                } else {
+                    (None, 0)
+                }
+            })
+            .await
+            .map_err(to_rpc_err)
     }
 
-    /// Remove a item from the set. Returns
-    async fn set_del(&self, _ctx: &Context, _arg: &SetDelRequest) -> RpcResult<u32> {
-        Err(RpcError::NotImplemented)
+    /// Remove a item from the set. Returns the number of items removed
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, set_name = %arg.set_name))]
+    async fn set_del(&self, ctx: &Context, arg: &SetDelRequest) -> RpcResult<u32> {
+        let (client, path) = self.get_client_path(ctx, &arg.set_name).await?;
+        client
+            .update_secret(&path, |set: Option<Set>| {
+                let mut set = set.unwrap_or_default();
+                if set.members.remove(&arg.value) {
+                    (Some(set), 1)
+                } else {
+                    (None, 0)
+                }
+            })
+            .await
+            .map_err(to_rpc_err)
     }
 
+    /// Returns the members that are in all of the sets. Sets that don't exist are empty
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor))]
     async fn set_intersection(
         &self,
-        _ctx: &Context,
-        _arg: &StringList,
+        ctx: &Context,
+        arg: &StringList,
     ) -> Result<StringList, RpcError> {
-        Err(RpcError::NotImplemented)
+        let mut sets = self.read_sets(ctx, arg).await?.into_iter();
+        let first = sets.next().unwrap_or_default();
+        let intersection = sets.fold(first, |acc, set| &acc & &set);
+        Ok(intersection.into_iter().collect())
     }
 
-    /// returns a list of all secrets at the path
+    /// If the path is a set, returns its members. Otherwise, returns a list of all secrets at the path
     #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, arg = %arg.to_string()))]
     async fn set_query<TS: ToString + ?Sized + Sync>(
         &self,
         ctx: &Context,
         arg: &TS,
     ) -> RpcResult<StringList> {
-        let client = self.get_client(ctx).await?;
-        match client.list_secrets(&arg.to_string()).await {
+        let (client, path) = self.get_client_path(ctx, &arg.to_string()).await?;
+        if let Ok((Some(set), _)) = client.read_secret_versioned::<Set>(&path).await {
+            return Ok(set.members.into_iter().collect());
+        }
+        match client.list_secrets(&path).await {
             Ok(list) => Ok(list),
             Err(VaultError::NotFound { namespace, path }) => {
                 debug!(
@@ -271,19 +396,200 @@ impl KeyValue for KvVaultProvider {
         }
     }
 
-    async fn set_union(&self, _ctx: &Context, _arg: &StringList) -> RpcResult<StringList> {
-        Err(RpcError::NotImplemented)
+    /// Returns the members that are in any of the sets
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor))]
+    async fn set_union(&self, ctx: &Context, arg: &StringList) -> RpcResult<StringList> {
+        let union: BTreeSet<String> = self
+            .read_sets(ctx, arg)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect();
+        Ok(union.into_iter().collect())
     }
 
-    /// Deletes a set and its contents
+    /// Deletes a set and its contents, including all versions
     /// input: set name
     /// returns: true if the set existed and was deleted
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, arg = %arg.to_string()))]
     async fn set_clear<TS: ToString + ?Sized + Sync>(
         &self,
-        _ctx: &Context,
-        _arg: &TS,
+        ctx: &Context,
+        arg: &TS,
     ) -> RpcResult<bool> {
-        Err(RpcError::NotImplemented)
+        self.delete_all(ctx, &arg.to_string()).await
+    }
+}
+
+/// Handle vault-specific methods for secret versions
+#[async_trait]
+impl KvVault for KvVaultProvider {
+    /// Gets the value of a version of a secret, in the same form as get
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.key, version = arg.version))]
+    async fn get_version(
+        &self,
+        ctx: &Context,
+        arg: &GetVersionRequest,
+    ) -> RpcResult<GetVersionResponse> {
+        let (client, path) = self.get_client_path(ctx, &arg.key).await?;
+        match client
+            .read_secret_version::<Value>(&path, arg.version)
+            .await
+        {
+            Ok(value) => Ok(GetVersionResponse {
+                value: value_to_string(value),
+                exists: true,
+            }),
+            Err(VaultError::NotFound { namespace, path }) => {
+                debug!(%namespace, %path, version = arg.version, "vault read version NotFound error");
+                Ok(GetVersionResponse {
+                    exists: false,
+                    ..Default::default()
+                })
+            }
+            Err(e) => Err(to_rpc_err(e)),
+        }
+    }
+
+    /// Returns the metadata of each version of a secret
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, arg = %arg.to_string()))]
+    async fn list_versions<TS: ToString + ?Sized + Sync>(
+        &self,
+        ctx: &Context,
+        arg: &TS,
+    ) -> RpcResult<VersionsResponse> {
+        let (client, path) = self.get_client_path(ctx, &arg.to_string()).await?;
+        let metadata = match client.read_metadata(&path).await {
+            Ok(metadata) => metadata,
+            Err(VaultError::NotFound { namespace, path }) => {
+                debug!(%namespace, %path, "vault read metadata NotFound error");
+                return Ok(VersionsResponse::default());
+            }
+            Err(e) => return Err(to_rpc_err(e)),
+        };
+        let mut versions = metadata
+            .versions
+            .into_iter()
+            .filter_map(|(version, info)| {
+                Some(VersionInfo {
+                    version: version.parse().ok()?,
+                    created_time: info.created_time,
+                    deletion_time: Some(info.deletion_time).filter(|t| !t.is_empty()),
+                    destroyed: info.destroyed,
+                })
+            })
+            .collect::<Vec<_>>();
+        versions.sort_by_key(|v| v.version);
+        Ok(VersionsResponse {
+            current_version: metadata.current_version,
+            oldest_version: metadata.oldest_version,
+            versions,
+        })
+    }
+
+    /// Restores deleted versions of a secret
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.key, versions = ?arg.versions))]
+    async fn undelete_versions(&self, ctx: &Context, arg: &VersionsRequest) -> RpcResult<()> {
+        let (client, path) = self.get_client_path(ctx, &arg.key).await?;
+        client
+            .undelete_versions(&path, arg.versions.clone())
+            .await
+            .map_err(to_rpc_err)
+    }
+
+    /// Permanently removes the data of versions of a secret
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.key, versions = ?arg.versions))]
+    async fn destroy_versions(&self, ctx: &Context, arg: &VersionsRequest) -> RpcResult<()> {
+        let (client, path) = self.get_client_path(ctx, &arg.key).await?;
+        client
+            .destroy_versions(&path, arg.versions.clone())
+            .await
+            .map_err(to_rpc_err)
+    }
+
+    /// Writes the value of a previous version as a new version, and returns the new version number
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.key, version = arg.version))]
+    async fn rollback(&self, ctx: &Context, arg: &RollbackRequest) -> RpcResult<u64> {
+        let (client, path) = self.get_client_path(ctx, &arg.key).await?;
+        match client.rollback(&path, arg.version).await {
+            Err(VaultError::NotFound { namespace, path }) => {
+                Err(RpcError::InvalidParameter(format!(
+                    "version {} of {}/{} doesn't exist, or is deleted or destroyed",
+                    arg.version, namespace, path
+                )))
+            }
+            result => result.map_err(to_rpc_err),
+        }
+    }
+
+    /// Encrypts data with the link's transit key
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, plaintext_len = arg.plaintext.len()))]
+    async fn encrypt(&self, ctx: &Context, arg: &EncryptRequest) -> RpcResult<String> {
+        let client = self.get_client(ctx).await?;
+        client
+            .encrypt(&arg.plaintext, arg.context.as_deref())
+            .await
+            .map_err(to_rpc_err)
+    }
+
+    /// Decrypts ciphertext with the link's transit key
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor))]
+    async fn decrypt(&self, ctx: &Context, arg: &DecryptRequest) -> RpcResult<Vec<u8>> {
+        let client = self.get_client(ctx).await?;
+        client
+            .decrypt(&arg.ciphertext, arg.context.as_deref())
+            .await
+            .map_err(to_rpc_err)
+    }
+
+    /// Encrypts ciphertext again with the latest version of the link's transit key
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor))]
+    async fn rewrap(&self, ctx: &Context, arg: &DecryptRequest) -> RpcResult<String> {
+        let client = self.get_client(ctx).await?;
+        client
+            .rewrap(&arg.ciphertext, arg.context.as_deref())
+            .await
+            .map_err(to_rpc_err)
+    }
+
+    /// Signs data with the link's transit key
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, input_len = arg.input.len()))]
+    async fn sign(&self, ctx: &Context, arg: &SignRequest) -> RpcResult<String> {
+        let client = self.get_client(ctx).await?;
+        client
+            .sign(&arg.input, arg.hash_algorithm.as_deref())
+            .await
+            .map_err(to_rpc_err)
+    }
+
+    /// Verifies a signature or HMAC with the link's transit key
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, input_len = arg.input.len()))]
+    async fn verify(&self, ctx: &Context, arg: &VerifyRequest) -> RpcResult<bool> {
+        if arg.signature.is_some() == arg.hmac.is_some() {
+            return Err(RpcError::InvalidParameter(
+                "verify requires either a signature or an hmac".to_string(),
+            ));
+        }
+        let client = self.get_client(ctx).await?;
+        client
+            .verify(
+                &arg.input,
+                arg.signature.as_deref(),
+                arg.hmac.as_deref(),
+                arg.hash_algorithm.as_deref(),
+            )
+            .await
+            .map_err(to_rpc_err)
+    }
+
+    /// Returns the HMAC of data with the link's transit key
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, input_len = arg.input.len()))]
+    async fn hmac(&self, ctx: &Context, arg: &HmacRequest) -> RpcResult<String> {
+        let client = self.get_client(ctx).await?;
+        client
+            .hmac(&arg.input, arg.algorithm.as_deref())
+            .await
+            .map_err(to_rpc_err)
     }
 }
 
@@ -301,4 +607,42 @@ impl KvVaultProvider {
             .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))?;
         Ok(client.clone())
     }
+
+    /// Helper function to get client, and the path of the key under the link's path prefix
+    async fn get_client_path(&self, ctx: &Context, key: &str) -> RpcResult<(Arc<Client>, String)> {
+        let client = self.get_client(ctx).await?;
+        let path = client.path(key).map_err(to_rpc_err)?;
+        Ok((client, path))
+    }
+
+    /// Reads the members of each set. Sets that don't exist are empty
+    async fn read_sets(&self, ctx: &Context, names: &[String]) -> RpcResult<Vec<BTreeSet<String>>> {
+        let client = self.get_client(ctx).await?;
+        let mut sets = Vec::with_capacity(names.len());
+        for name in names {
+            let path = client.path(name).map_err(to_rpc_err)?;
+            let (set, _) = client
+                .read_secret_versioned::<Set>(&path)
+                .await
+                .map_err(to_rpc_err)?;
+            sets.push(set.unwrap_or_default().members);
+        }
+        Ok(sets)
+    }
+
+    /// Deletes all versions of a secret. Returns true if the secret existed
+    async fn delete_all(&self, ctx: &Context, key: &str) -> RpcResult<bool> {
+        let (client, path) = self.get_client_path(ctx, key).await?;
+        match client.delete_all(&path).await {
+            Ok(_) => Ok(true),
+            Err(VaultError::NotFound { namespace, path }) => {
+                debug!(%namespace, %path, "vault delete all NotFound error");
+                Ok(false)
+            }
+            Err(e) => {
+                debug!(error = %e, "Error while deleting all versions from vault");
+                Err(to_rpc_err(e))
+            }
+        }
+    }
 }
@@ -1,12 +1,19 @@
 //! Tests kv-vault
 //!
-use kv_vault_lib::STRING_VALUE_MARKER;
+use kv_vault_lib::{
+    cache::{CacheStats, SecretCache},
+    config::{Auth, ClientCert, Engine, Transit},
+    error::VaultError,
+    STRING_VALUE_MARKER,
+};
 use serde_json::Value;
+use vaultrs::client::Client as _;
 use wasmbus_rpc::{
     error::{RpcError, RpcResult},
     provider::prelude::Context,
 };
 use wasmcloud_interface_keyvalue::*;
+use wasmcloud_interface_kvvault::*;
 use wasmcloud_test_util::{
     check, check_eq,
     cli::print_test_results,
@@ -15,6 +22,12 @@ use wasmcloud_test_util::{
     testing::TestOptions,
 };
 
+// generated from the provider's kvvault.smithy by build.rs
+#[allow(dead_code)]
+mod wasmcloud_interface_kvvault {
+    include!(concat!(env!("OUT_DIR"), "/gen/kvvault.rs"));
+}
+
 #[tokio::test]
 async fn run_all() {
     let opts = TestOptions::default();
@@ -25,7 +38,21 @@ async fn run_all() {
         get_set,
         contains_del,
         json_values,
+        increment,
+        concurrent_increment,
+        lists,
+        sets,
+        versions,
+        kv1_engine,
+        dynamic_engine,
+        path_prefix,
+        cache,
+        transit,
         renewal,
+        approle_login,
+        approle_relogin,
+        tls,
+        namespace_header,
     );
     print_test_results(&res);
 
@@ -186,6 +213,538 @@ async fn json_values(_opt: &TestOptions) -> RpcResult<()> {
     Ok(())
 }
 
+/// increment
+async fn increment(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    // create client and ctx
+    let kv = KeyValueSender::via(prov);
+    let ctx = Context::default();
+
+    let key = new_key("test_incr/counter");
+    let incr = |value| IncrementRequest {
+        key: key.clone(),
+        value,
+    };
+
+    // a missing key starts at zero
+    check_eq!(kv.increment(&ctx, &incr(5)).await?, 5)?;
+    check_eq!(kv.increment(&ctx, &incr(-7)).await?, -2)?;
+    check_eq!(kv.get(&ctx, &key).await?.value.as_str(), "-2")?;
+
+    // a counter can be set like any other value
+    set(&kv, &ctx, &key, "40").await?;
+    check_eq!(kv.increment(&ctx, &incr(2)).await?, 42)?;
+
+    // non-numeric values can't be incremented
+    set(&kv, &ctx, &key, "Alice").await?;
+    let res = kv.increment(&ctx, &incr(1)).await;
+    check!(matches!(res, Err(RpcError::InvalidParameter(_))))?;
+
+    // clean up
+    let _ = kv.del(&ctx, &key).await?;
+    Ok(())
+}
+
+/// increments from concurrent callers are not lost
+async fn concurrent_increment(_opt: &TestOptions) -> RpcResult<()> {
+    const CALLERS: i32 = 10;
+    const INCREMENTS: i32 = 5;
+    let key = new_key("test_incr/concurrent");
+
+    let callers: Vec<_> = (0..CALLERS)
+        .map(|_| {
+            let key = key.clone();
+            tokio::spawn(async move {
+                let kv = KeyValueSender::via(test_provider().await);
+                let ctx = Context::default();
+                for _ in 0..INCREMENTS {
+                    kv.increment(
+                        &ctx,
+                        &IncrementRequest {
+                            key: key.clone(),
+                            value: 1,
+                        },
+                    )
+                    .await?;
+                }
+                RpcResult::Ok(())
+            })
+        })
+        .collect();
+    for caller in callers {
+        caller
+            .await
+            .map_err(|e| RpcError::Other(format!("increment task failed: {}", e)))??;
+    }
+
+    let kv = KeyValueSender::via(test_provider().await);
+    let ctx = Context::default();
+    let get_resp = kv.get(&ctx, &key).await?;
+    check_eq!(get_resp.value, (CALLERS * INCREMENTS).to_string())?;
+
+    // clean up
+    let _ = kv.del(&ctx, &key).await?;
+    Ok(())
+}
+
+/// list operations
+async fn lists(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    // create client and ctx
+    let kv = KeyValueSender::via(prov);
+    let ctx = Context::default();
+
+    let list = new_key("test_lists/list");
+    for (i, value) in ["a", "b", "c", "b"].iter().enumerate() {
+        let len = kv
+            .list_add(
+                &ctx,
+                &ListAddRequest {
+                    list_name: list.clone(),
+                    value: value.to_string(),
+                },
+            )
+            .await?;
+        check_eq!(len, i as u32 + 1)?;
+    }
+
+    let range = |start, stop| ListRangeRequest {
+        list_name: list.clone(),
+        start,
+        stop,
+    };
+    check_eq!(
+        kv.list_range(&ctx, &range(0, -1)).await?,
+        vec!["a", "b", "c", "b"]
+    )?;
+    check_eq!(kv.list_range(&ctx, &range(1, 2)).await?, vec!["b", "c"])?;
+    check_eq!(kv.list_range(&ctx, &range(-2, 10)).await?, vec!["c", "b"])?;
+    check!(kv.list_range(&ctx, &range(3, 1)).await?.is_empty())?;
+
+    // removes the first matching item only
+    let removed = kv
+        .list_del(
+            &ctx,
+            &ListDelRequest {
+                list_name: list.clone(),
+                value: "b".to_string(),
+            },
+        )
+        .await?;
+    check!(removed)?;
+    let removed = kv
+        .list_del(
+            &ctx,
+            &ListDelRequest {
+                list_name: list.clone(),
+                value: "z".to_string(),
+            },
+        )
+        .await?;
+    check!(!removed)?;
+    check_eq!(
+        kv.list_range(&ctx, &range(0, -1)).await?,
+        vec!["a", "c", "b"]
+    )?;
+
+    // list operations on a string value fail
+    let not_list = new_key("test_lists/string");
+    set(&kv, &ctx, &not_list, "Alice").await?;
+    let res = kv
+        .list_add(
+            &ctx,
+            &ListAddRequest {
+                list_name: not_list.clone(),
+                value: "x".to_string(),
+            },
+        )
+        .await;
+    check!(matches!(res, Err(RpcError::InvalidParameter(_))))?;
+    kv.del(&ctx, &not_list).await?;
+
+    // clear deletes all versions
+    check!(kv.list_clear(&ctx, &list).await?)?;
+    check!(!kv.contains(&ctx, &list).await?)?;
+    check!(kv.list_range(&ctx, &range(0, -1)).await?.is_empty())?;
+    check!(!kv.list_clear(&ctx, &list).await?)?;
+
+    Ok(())
+}
+
+/// set operations
+async fn sets(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    // create client and ctx
+    let kv = KeyValueSender::via(prov);
+    let ctx = Context::default();
+
+    let set1 = new_key("test_sets/one");
+    let set2 = new_key("test_sets/two");
+    let add = |set_name: &str, value: &str| SetAddRequest {
+        set_name: set_name.to_string(),
+        value: value.to_string(),
+    };
+
+    check_eq!(kv.set_add(&ctx, &add(&set1, "b")).await?, 1)?;
+    check_eq!(kv.set_add(&ctx, &add(&set1, "a")).await?, 1)?;
+    check_eq!(kv.set_add(&ctx, &add(&set1, "a")).await?, 0)?;
+    check_eq!(kv.set_add(&ctx, &add(&set2, "b")).await?, 1)?;
+    check_eq!(kv.set_add(&ctx, &add(&set2, "c")).await?, 1)?;
+
+    check_eq!(kv.set_query(&ctx, &set1).await?, vec!["a", "b"])?;
+    check_eq!(
+        kv.set_union(&ctx, &vec![set1.clone(), set2.clone()])
+            .await?,
+        vec!["a", "b", "c"]
+    )?;
+    check_eq!(
+        kv.set_intersection(&ctx, &vec![set1.clone(), set2.clone()])
+            .await?,
+        vec!["b"]
+    )?;
+    // missing sets are empty
+    check!(kv
+        .set_intersection(&ctx, &vec![set1.clone(), new_key("test_sets/none")])
+        .await?
+        .is_empty())?;
+
+    let del = |set_name: &str, value: &str| SetDelRequest {
+        set_name: set_name.to_string(),
+        value: value.to_string(),
+    };
+    check_eq!(kv.set_del(&ctx, &del(&set1, "a")).await?, 1)?;
+    check_eq!(kv.set_del(&ctx, &del(&set1, "a")).await?, 0)?;
+    check_eq!(kv.set_query(&ctx, &set1).await?, vec!["b"])?;
+
+    check!(kv.set_clear(&ctx, &set1).await?)?;
+    check!(kv.set_clear(&ctx, &set2).await?)?;
+    check!(!kv.set_clear(&ctx, &set1).await?)?;
+
+    Ok(())
+}
+
+/// version history, undelete, destroy, and rollback
+async fn versions(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    // create clients and ctx
+    let kv = KeyValueSender::via(prov.clone());
+    let kvv = KvVaultSender::via(prov);
+    let ctx = Context::default();
+
+    let key = new_key("versions");
+    set(&kv, &ctx, &key, "one").await?;
+    set(&kv, &ctx, &key, "two").await?;
+
+    let version = |version| GetVersionRequest {
+        key: key.clone(),
+        version,
+    };
+    let v1 = kvv.get_version(&ctx, &version(1)).await?;
+    check!(v1.exists)?;
+    check_eq!(v1.value.as_str(), "one")?;
+    check!(!kvv.get_version(&ctx, &version(3)).await?.exists)?;
+
+    let history = kvv.list_versions(&ctx, &key).await?;
+    check_eq!(history.current_version, 2)?;
+    check_eq!(history.versions.len(), 2)?;
+    check_eq!(history.versions[0].version, 1)?;
+    check!(history.versions[1].deletion_time.is_none())?;
+
+    // delete and undelete the latest version
+    let versions = |versions: Vec<u64>| VersionsRequest {
+        key: key.clone(),
+        versions,
+    };
+    check!(kv.del(&ctx, &key).await?)?;
+    check!(!kv.get(&ctx, &key).await?.exists)?;
+    check!(kvv.list_versions(&ctx, &key).await?.versions[1]
+        .deletion_time
+        .is_some())?;
+    kvv.undelete_versions(&ctx, &versions(vec![2])).await?;
+    check_eq!(kv.get(&ctx, &key).await?.value.as_str(), "two")?;
+
+    // rollback writes the old value as a new version
+    let rollback = RollbackRequest {
+        key: key.clone(),
+        version: 1,
+    };
+    check_eq!(kvv.rollback(&ctx, &rollback).await?, 3)?;
+    check_eq!(kv.get(&ctx, &key).await?.value.as_str(), "one")?;
+
+    // destroyed versions can't be read or rolled back to
+    kvv.destroy_versions(&ctx, &versions(vec![1])).await?;
+    check!(!kvv.get_version(&ctx, &version(1)).await?.exists)?;
+    check!(kvv.list_versions(&ctx, &key).await?.versions[0].destroyed)?;
+    check!(kvv.rollback(&ctx, &rollback).await.is_err())?;
+
+    // a secret that doesn't exist has no versions
+    let missing = kvv.list_versions(&ctx, &new_key("versions")).await?;
+    check_eq!(missing.current_version, 0)?;
+    check!(missing.versions.is_empty())?;
+
+    Ok(())
+}
+
+/// KV v1 mounts, and the read-only dynamic engine
+async fn kv1_engine(_opt: &TestOptions) -> RpcResult<()> {
+    let mount = std::env::var("KV1_MOUNT")
+        .expect("kv1 mount to exist in env. Run this test with `run-test.sh`.");
+    let mut config = kv_vault_lib::config::Config::from_values(&Default::default())
+        .expect("configuration to be valid");
+    config.mount = mount;
+    config.engine = Engine::Kv1;
+    let kv1 = kv_vault_lib::client::Client::new(config.clone()).expect("kv1 client");
+
+    let path = new_key("kv1");
+    let value = serde_json::json!({ "user": "alice" });
+    check!(matches!(
+        kv1.read_secret::<Value>(&path).await,
+        Err(VaultError::NotFound { .. })
+    ))?;
+    kv1.write_secret(&path, &value)
+        .await
+        .expect("should be able to write secret");
+    let secret: Value = kv1.read_secret(&path).await.expect("secret to exist");
+    check_eq!(secret, value)?;
+    check!(kv1.list_secrets("").await.unwrap().contains(&path))?;
+
+    // there are no versions in KV v1
+    check!(matches!(
+        kv1.read_secret_versioned::<Value>(&path).await,
+        Err(VaultError::Unsupported { .. })
+    ))?;
+
+    kv1.delete_latest::<String>(&path)
+        .await
+        .expect("should be able to delete secret");
+    check!(kv1.read_secret::<Value>(&path).await.is_err())?;
+
+    // secrets from dynamic engines can't be written
+    config.engine = Engine::Dynamic;
+    let dynamic = kv_vault_lib::client::Client::new(config).expect("dynamic client");
+    check!(matches!(
+        dynamic.write_secret(&path, &value).await,
+        Err(VaultError::Unsupported { .. })
+    ))?;
+    // and checking for a secret would create a credential
+    check!(matches!(
+        dynamic.secret_exists(&path).await,
+        Err(VaultError::Unsupported { .. })
+    ))?;
+
+    Ok(())
+}
+
+/// secrets read from a dynamic engine have leases, which are revoked with revoke_leases.
+/// Uses the database engine and postgres started by `run-test.sh`
+async fn dynamic_engine(_opt: &TestOptions) -> RpcResult<()> {
+    let mount = std::env::var("DYNAMIC_MOUNT")
+        .expect("dynamic mount to exist in env. Run this test with `run-test.sh`.");
+    let role = std::env::var("DYNAMIC_ROLE").expect("dynamic role to exist in env");
+    let mut config = kv_vault_lib::config::Config::from_values(&Default::default())
+        .expect("configuration to be valid");
+    config.mount = mount.clone();
+    config.engine = Engine::Dynamic;
+    let dynamic = kv_vault_lib::client::Client::new(config).expect("dynamic client");
+
+    // the leases of the role's credentials, listed with the root token
+    let path = format!("creds/{}", role);
+    let leases = || async {
+        let endpoint = kv_vault_lib::api::ListSecretsRequest {
+            mount: "sys/leases/lookup".to_string(),
+            path: format!("{}/{}/", mount, path),
+        };
+        match vaultrs::api::exec_with_result(dynamic.inner_client().as_ref(), endpoint).await {
+            Ok(leases) => leases.keys,
+            Err(vaultrs::error::ClientError::APIError { code: 404, .. }) => Vec::new(),
+            Err(e) => panic!("listing leases: {}", e),
+        }
+    };
+    let before = leases().await;
+
+    // each read creates new credentials, with their own lease
+    let first: Value = dynamic.read_secret(&path).await.expect("credentials");
+    let second: Value = dynamic.read_secret(&path).await.expect("credentials");
+    check!(first["username"].is_string())?;
+    check!(first["password"].is_string())?;
+    check!(first["username"] != second["username"])?;
+    let created: Vec<String> = leases()
+        .await
+        .into_iter()
+        .filter(|lease| !before.contains(lease))
+        .collect();
+    check_eq!(created.len(), 2)?;
+
+    dynamic.revoke_leases().await;
+    let remaining = leases().await;
+    check!(created.iter().all(|lease| !remaining.contains(lease)))?;
+
+    Ok(())
+}
+
+/// keys of links with a path prefix are isolated from each other
+async fn path_prefix(_opt: &TestOptions) -> RpcResult<()> {
+    let mut config = kv_vault_lib::config::Config::from_values(&Default::default())
+        .expect("configuration to be valid");
+    config.path_prefix = Some(new_key("actor_a"));
+    let a = kv_vault_lib::client::Client::new(config.clone()).expect("client a");
+    config.path_prefix = Some(new_key("actor_b"));
+    let b = kv_vault_lib::client::Client::new(config).expect("client b");
+
+    let key = new_key("prefixed");
+    let path_a = a.path(&key).expect("valid key");
+    check_eq!(path_a, format!("{}/{}", a.path("").unwrap(), key))?;
+    a.write_secret(&path_a, &"a".to_string())
+        .await
+        .expect("should be able to write secret");
+    let path_b = b.path(&key).expect("valid key");
+    check!(b.read_secret::<String>(&path_b).await.is_err())?;
+
+    // keys can't refer to paths outside the prefix
+    for escape in ["..", "../x", "x/../../y", "/../x"] {
+        check!(matches!(
+            b.path(escape),
+            Err(VaultError::InvalidPath { .. })
+        ))?;
+    }
+    let mut values = std::collections::HashMap::new();
+    values.insert("path_prefix".to_string(), "team/../other".to_string());
+    check!(kv_vault_lib::config::Config::from_values(&values).is_err())?;
+
+    a.delete_all(&path_a)
+        .await
+        .expect("should be able to delete secret");
+    Ok(())
+}
+
+/// values are read from the cache until they expire, or are changed through the link
+async fn cache(_opt: &TestOptions) -> RpcResult<()> {
+    let mut config = kv_vault_lib::config::Config::from_values(&Default::default())
+        .expect("configuration to be valid");
+    let uncached = kv_vault_lib::client::Client::new(config.clone()).expect("client");
+    config.cache = Some(kv_vault_lib::config::Cache {
+        ttl: std::time::Duration::from_secs(1),
+        max_entries: 10,
+    });
+    let cached = kv_vault_lib::client::Client::new(config).expect("cached client");
+
+    let path = new_key("cached");
+    cached
+        .write_secret(&path, &"one".to_string())
+        .await
+        .expect("should be able to write secret");
+    check_eq!(cached.read_secret::<String>(&path).await.unwrap(), "one")?;
+
+    // a write by another client isn't seen until the cached value expires
+    uncached
+        .write_secret(&path, &"two".to_string())
+        .await
+        .expect("should be able to write secret");
+    check_eq!(cached.read_secret::<String>(&path).await.unwrap(), "one")?;
+    check_eq!(
+        cached.cache_stats(),
+        Some(CacheStats { hits: 1, misses: 1 })
+    )?;
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    check_eq!(cached.read_secret::<String>(&path).await.unwrap(), "two")?;
+
+    // writes and deletes through the link remove the cached value
+    cached
+        .write_secret(&path, &"three".to_string())
+        .await
+        .expect("should be able to write secret");
+    check_eq!(cached.read_secret::<String>(&path).await.unwrap(), "three")?;
+    cached
+        .delete_latest::<String>(&path)
+        .await
+        .expect("should be able to delete secret");
+    check!(matches!(
+        cached.read_secret::<String>(&path).await,
+        Err(VaultError::NotFound { .. })
+    ))?;
+    check_eq!(
+        cached.cache_stats(),
+        Some(CacheStats { hits: 1, misses: 4 })
+    )?;
+
+    // a value read before the secret was changed through the link isn't cached
+    let secrets = SecretCache::new(std::time::Duration::from_secs(30), 10);
+    let generation = secrets.generation(&path);
+    secrets.invalidate(&path);
+    secrets.insert(&path, Value::from("stale"), generation);
+    check!(secrets.get(&path).is_none())?;
+    secrets.insert(&path, Value::from("fresh"), secrets.generation(&path));
+    check_eq!(secrets.get(&path), Some(Value::from("fresh")))?;
+
+    uncached
+        .delete_all(&path)
+        .await
+        .expect("should be able to delete secret");
+    Ok(())
+}
+
+/// encryption, signing, and HMAC with the transit engine
+async fn transit(_opt: &TestOptions) -> RpcResult<()> {
+    let key = |name: &str| {
+        let mut config = kv_vault_lib::config::Config::from_values(&Default::default())
+            .expect("configuration to be valid");
+        config.transit = Some(Transit {
+            mount: "transit".to_string(),
+            key: name.to_string(),
+        });
+        kv_vault_lib::client::Client::new(config).expect("transit client")
+    };
+    // keys created by run-test.sh
+    let aes = key("kv-vault-aes");
+    let ed25519 = key("kv-vault-ed25519");
+
+    let ciphertext = aes.encrypt(b"hello", None).await.expect("encrypt");
+    check!(ciphertext.starts_with("vault:v1:"))?;
+    check_eq!(
+        aes.decrypt(&ciphertext, None).await.expect("decrypt"),
+        b"hello"
+    )?;
+    let rewrapped = aes.rewrap(&ciphertext, None).await.expect("rewrap");
+    check_eq!(
+        aes.decrypt(&rewrapped, None).await.expect("decrypt"),
+        b"hello"
+    )?;
+    check!(aes.decrypt("vault:v1:invalid", None).await.is_err())?;
+
+    let hmac = aes.hmac(b"hello", None).await.expect("hmac");
+    check!(aes.verify(b"hello", None, Some(&hmac), None).await.unwrap())?;
+    check!(!aes
+        .verify(b"goodbye", None, Some(&hmac), None)
+        .await
+        .unwrap())?;
+
+    let signature = ed25519.sign(b"hello", None).await.expect("sign");
+    check!(ed25519
+        .verify(b"hello", Some(&signature), None, None)
+        .await
+        .unwrap())?;
+    check!(!ed25519
+        .verify(b"goodbye", Some(&signature), None, None)
+        .await
+        .unwrap())?;
+
+    // links without a transit key
+    let mut config = kv_vault_lib::config::Config::from_values(&Default::default())
+        .expect("configuration to be valid");
+    config.transit = None;
+    let client = kv_vault_lib::client::Client::new(config).expect("client");
+    check!(matches!(
+        client.encrypt(b"hello", None).await,
+        Err(VaultError::Transit(_))
+    ))?;
+
+    Ok(())
+}
+
 /// tests renewal of token
 async fn renewal(_opt: &TestOptions) -> RpcResult<()> {
     let token = std::env::var("SHORT_LIVED_TOKEN")
@@ -224,7 +783,7 @@ async fn renewal(_opt: &TestOptions) -> RpcResult<()> {
         );
         Value::Object(map)
     });
-    let _put = vault_direct
+    vault_direct
         .write_secret(secret_path, &value)
         .await
         .expect("should be able to write secret");
@@ -255,3 +814,214 @@ async fn renewal(_opt: &TestOptions) -> RpcResult<()> {
 
     Ok(())
 }
+
+/// tests logging in with approle
+async fn approle_login(_opt: &TestOptions) -> RpcResult<()> {
+    let role_id = std::env::var("APPROLE_ROLE_ID")
+        .expect("role id to exist in env. Run this test with `run-test.sh`.");
+    let secret_id = std::env::var("APPROLE_SECRET_ID").expect("secret id to exist in env");
+
+    // the secret id is read from a file, as it would be when delivered by an orchestrator
+    let secret_id_file = std::env::temp_dir().join(format!("kv_vault_{}", new_key("secret_id")));
+    std::fs::write(&secret_id_file, format!("{}\n", secret_id)).expect("secret id file");
+
+    let config_values = std::collections::HashMap::from_iter([
+        ("auth_method".to_string(), "approle".to_string()),
+        ("role_id".to_string(), role_id),
+        (
+            "secret_id_file".to_string(),
+            secret_id_file.to_string_lossy().to_string(),
+        ),
+    ]);
+    let config = kv_vault_lib::config::Config::from_values(&config_values)
+        .expect("configuration to be valid");
+    let vault_direct = kv_vault_lib::client::Client::new(config).expect("client from defaults");
+    vault_direct.set_renewal().await;
+    let _ = std::fs::remove_file(&secret_id_file);
+
+    let info = vault_direct
+        .inner_client()
+        .lookup()
+        .await
+        .expect("login to create a token");
+    check!(info.ttl > 0)?;
+    check_eq!(info.path.as_str(), "auth/approle/login")?;
+
+    let secret_path = new_key("approle");
+    let mut value = serde_json::Map::new();
+    value.insert(
+        STRING_VALUE_MARKER.to_string(),
+        Value::String("BOB".to_string()),
+    );
+    let value = Value::Object(value);
+    vault_direct
+        .write_secret(&secret_path, &value)
+        .await
+        .expect("should be able to write secret");
+    let secret: Value = vault_direct
+        .read_secret(&secret_path)
+        .await
+        .expect("should be able to read secret");
+    check_eq!(secret, value)?;
+
+    Ok(())
+}
+
+/// the client logs in again when its approle token reaches its max TTL, which `run-test.sh`
+/// sets to 120 seconds, and reads keep succeeding with the new token
+async fn approle_relogin(_opt: &TestOptions) -> RpcResult<()> {
+    let role_id = std::env::var("APPROLE_ROLE_ID")
+        .expect("role id to exist in env. Run this test with `run-test.sh`.");
+    let secret_id = std::env::var("APPROLE_SECRET_ID").expect("secret id to exist in env");
+
+    let config_values = std::collections::HashMap::from_iter([
+        ("auth_method".to_string(), "approle".to_string()),
+        ("role_id".to_string(), role_id),
+        ("secret_id".to_string(), secret_id),
+        ("token_refresh_interval".to_string(), "5".to_string()),
+    ]);
+    let config = kv_vault_lib::config::Config::from_values(&config_values)
+        .expect("configuration to be valid");
+    let vault_direct = kv_vault_lib::client::Client::new(config).expect("client from defaults");
+    vault_direct.set_renewal().await;
+
+    let first = vault_direct
+        .inner_client()
+        .lookup()
+        .await
+        .expect("login to create a token");
+
+    let secret_path = new_key("approle_relogin");
+    vault_direct
+        .write_secret(&secret_path, &"DAVE".to_string())
+        .await
+        .expect("should be able to write secret");
+
+    // read until well past the max TTL of the first token
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(135);
+    while std::time::Instant::now() < deadline {
+        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+        let secret = vault_direct.read_secret::<String>(&secret_path).await;
+        check!(matches!(secret.as_deref(), Ok("DAVE")))?;
+    }
+
+    let current = vault_direct
+        .inner_client()
+        .lookup()
+        .await
+        .expect("client to have a valid token");
+    check!(current.accessor != first.accessor)?;
+    check_eq!(current.path.as_str(), "auth/approle/login")?;
+
+    Ok(())
+}
+
+/// server certificates are verified, and the client certificate is used to log in,
+/// with the TLS dev server started by `run-test.sh`
+async fn tls(_opt: &TestOptions) -> RpcResult<()> {
+    let env = |name: &str| {
+        std::env::var(name).unwrap_or_else(|_| {
+            panic!(
+                "{} to exist in env. Run this test with `run-test.sh`.",
+                name
+            )
+        })
+    };
+    let mut config = kv_vault_lib::config::Config::from_values(&Default::default())
+        .expect("configuration to be valid");
+    config.addr = env("TLS_VAULT_ADDR").parse().expect("valid url");
+    config.auth = Auth::Token(env("TLS_VAULT_TOKEN"));
+
+    // the dev server's certificate is self-signed, so it's rejected without its CA
+    let unverified = kv_vault_lib::client::Client::new(config.clone()).expect("client");
+    check!(matches!(
+        unverified.list_secrets("").await,
+        Err(VaultError::Client { .. })
+    ))?;
+    config.tls_skip_verify = true;
+    let insecure = kv_vault_lib::client::Client::new(config.clone()).expect("insecure client");
+    check!(insecure.list_secrets("").await.is_ok())?;
+    config.tls_skip_verify = false;
+
+    config.certs = vec![env("TLS_VAULT_CACERT")];
+    config.client_cert = Some(ClientCert {
+        cert: env("TLS_CLIENT_CERT"),
+        key: env("TLS_CLIENT_KEY"),
+    });
+    config.auth = Auth::Cert {
+        mount: "cert".to_string(),
+        role: Some("kv-vault-test".to_string()),
+    };
+    let client = kv_vault_lib::client::Client::new(config).expect("client");
+    client.set_renewal().await;
+    let info = client
+        .inner_client()
+        .lookup()
+        .await
+        .expect("login to create a token");
+    check_eq!(info.path.as_str(), "auth/cert/login")?;
+
+    let path = new_key("tls");
+    client
+        .write_secret(&path, &"CAROL".to_string())
+        .await
+        .expect("should be able to write secret");
+    check_eq!(client.read_secret::<String>(&path).await.unwrap(), "CAROL")?;
+
+    Ok(())
+}
+
+/// the namespace is sent in the X-Vault-Namespace header
+async fn namespace_header(_opt: &TestOptions) -> RpcResult<()> {
+    use std::io::{BufRead, BufReader, Write};
+
+    // a server that records the headers of one request, and responds with not found
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().expect("connection");
+        let mut headers = Vec::new();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).expect("request line");
+            if line.trim().is_empty() {
+                break;
+            }
+            headers.push(line.trim().to_ascii_lowercase());
+        }
+        let body = r#"{"errors":[]}"#;
+        write!(
+            &stream,
+            "HTTP/1.1 404 Not Found\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .expect("response");
+        headers
+    });
+
+    let mut config = kv_vault_lib::config::Config::from_values(&Default::default())
+        .expect("configuration to be valid");
+    config.addr = format!("http://{}", addr).parse().unwrap();
+    config.namespace = Some("team-a/dev".to_string());
+    let client = kv_vault_lib::client::Client::new(config).expect("client");
+    check!(matches!(
+        client.read_secret::<Value>("key").await,
+        Err(VaultError::NotFound { .. })
+    ))?;
+    let headers = server.join().unwrap();
+    check!(headers.contains(&"x-vault-namespace: team-a/dev".to_string()))?;
+
+    // namespaces that can't be sent in a header are rejected as invalid settings
+    let mut values = std::collections::HashMap::new();
+    values.insert("namespace".to_string(), "team-a\ndev".to_string());
+    check!(kv_vault_lib::config::Config::from_values(&values).is_err())?;
+    let mut config = kv_vault_lib::config::Config::from_values(&Default::default())
+        .expect("configuration to be valid");
+    config.namespace = Some("team-a\ndev".to_string());
+    let invalid = kv_vault_lib::client::Client::new(config);
+    check!(matches!(invalid, Err(VaultError::Config(_))))?;
+
+    Ok(())
+}
@@ -0,0 +1,7 @@
+const CONFIG: &str = "./codegen.toml";
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=kvredis.smithy");
+    weld_codegen::rust_build_into(CONFIG, &std::env::var("OUT_DIR").unwrap())?;
+    Ok(())
+}
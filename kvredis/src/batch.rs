@@ -0,0 +1,230 @@
+//! Batch operations
+//!
+//! A batch is a list of keyvalue operations sent as one MULTI/EXEC transaction, so
+//! commands from other clients can't run between them. Redis does not roll back a
+//! transaction: if one command fails, for example because its key holds the wrong type,
+//! the other commands still run, and the batch returns the error.
+
+use redis::{from_redis_value, FromRedisValue, Pipeline, Value};
+use wasmbus_rpc::error::{RpcError, RpcResult};
+
+use crate::wasmcloud_interface_kvredis::{BatchOperation, BatchResult};
+use wasmcloud_provider_kvredis::{link::ActorLink, to_rpc_err};
+
+/// Operations that can be used in a batch
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BatchOp {
+    Get,
+    Set,
+    /// Del, ListClear, and SetClear
+    Del,
+    Contains,
+    Increment,
+    ListAdd,
+    ListDel,
+    ListRange,
+    SetAdd,
+    SetDel,
+    SetQuery,
+}
+
+impl BatchOp {
+    /// Parses the operation name (case-insensitive)
+    fn parse(name: &str) -> Option<Self> {
+        let op = match name.to_ascii_lowercase().as_str() {
+            "get" => BatchOp::Get,
+            "set" => BatchOp::Set,
+            "del" | "listclear" | "setclear" => BatchOp::Del,
+            "contains" => BatchOp::Contains,
+            "increment" => BatchOp::Increment,
+            "listadd" => BatchOp::ListAdd,
+            "listdel" => BatchOp::ListDel,
+            "listrange" => BatchOp::ListRange,
+            "setadd" => BatchOp::SetAdd,
+            "setdel" => BatchOp::SetDel,
+            "setquery" => BatchOp::SetQuery,
+            _ => return None,
+        };
+        Some(op)
+    }
+}
+
+/// Builds the transaction for a batch, and returns it with the parsed operations.
+/// Fails without sending anything if any operation is invalid
+pub(crate) fn transaction(
+    link: &ActorLink,
+    operations: &[BatchOperation],
+) -> RpcResult<(Pipeline, Vec<BatchOp>)> {
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    let mut ops = Vec::with_capacity(operations.len());
+    for (i, operation) in operations.iter().enumerate() {
+        let op = BatchOp::parse(&operation.op).ok_or_else(|| {
+            RpcError::InvalidParameter(format!(
+                "batch operation {}: unknown operation '{}'",
+                i, operation.op
+            ))
+        })?;
+        let key = link.key(&operation.key);
+        let value = || {
+            operation.value.as_deref().ok_or_else(|| {
+                RpcError::InvalidParameter(format!(
+                    "batch operation {} ({}) requires a value",
+                    i, operation.op
+                ))
+            })
+        };
+        match op {
+            BatchOp::Get => pipe.get(key),
+            BatchOp::Set => match operation.expires.unwrap_or_default() {
+                0 => pipe.set(key, value()?),
+                expires => pipe.set_ex(key, value()?, expires as u64),
+            },
+            BatchOp::Del => pipe.del(key),
+            BatchOp::Contains => pipe.exists(key),
+            BatchOp::Increment => pipe.incr(key, operation.delta.unwrap_or(1)),
+            BatchOp::ListAdd => pipe.rpush(key, value()?),
+            BatchOp::ListDel => pipe.lrem(key, 1, value()?),
+            BatchOp::ListRange => pipe.lrange(
+                key,
+                operation.start.unwrap_or(0) as isize,
+                operation.stop.unwrap_or(-1) as isize,
+            ),
+            BatchOp::SetAdd => pipe.sadd(key, value()?),
+            BatchOp::SetDel => pipe.srem(key, value()?),
+            BatchOp::SetQuery => pipe.smembers(key),
+        };
+        ops.push(op);
+    }
+    Ok((pipe, ops))
+}
+
+/// Converts the replies from the transaction into results, in the same order
+pub(crate) fn results(ops: &[BatchOp], replies: Vec<Value>) -> RpcResult<Vec<BatchResult>> {
+    if ops.len() != replies.len() {
+        return Err(RpcError::Other(format!(
+            "redis error: expected {} replies to batch, got {}",
+            ops.len(),
+            replies.len()
+        )));
+    }
+    ops.iter()
+        .zip(replies.iter())
+        .map(|(op, reply)| result(*op, reply))
+        .collect()
+}
+
+fn result(op: BatchOp, reply: &Value) -> RpcResult<BatchResult> {
+    let done = BatchResult {
+        exists: true,
+        ..Default::default()
+    };
+    let result = match op {
+        BatchOp::Get => {
+            let value: Option<String> = convert(reply)?;
+            BatchResult {
+                exists: value.is_some(),
+                value,
+                ..Default::default()
+            }
+        }
+        BatchOp::Set => done,
+        BatchOp::Del | BatchOp::ListDel => BatchResult {
+            exists: convert::<i64>(reply)? > 0,
+            ..Default::default()
+        },
+        BatchOp::Contains => BatchResult {
+            exists: convert(reply)?,
+            ..Default::default()
+        },
+        BatchOp::Increment | BatchOp::ListAdd | BatchOp::SetAdd | BatchOp::SetDel => BatchResult {
+            number: Some(convert(reply)?),
+            ..done
+        },
+        BatchOp::ListRange | BatchOp::SetQuery => BatchResult {
+            values: Some(convert(reply)?),
+            ..done
+        },
+    };
+    Ok(result)
+}
+
+fn convert<T: FromRedisValue>(reply: &Value) -> RpcResult<T> {
+    from_redis_value(reply).map_err(to_rpc_err)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wasmcloud_provider_kvredis::options::ConnectionOptions;
+    use wasmcloud_provider_kvredis::pool::Topology;
+
+    fn link() -> ActorLink {
+        ActorLink::new(
+            "actor",
+            Topology::Single("redis://127.0.0.1:6379/".to_string()),
+            ConnectionOptions::default(),
+            1,
+            "p:".to_string(),
+        )
+    }
+
+    fn operation(op: &str, key: &str, value: Option<&str>) -> BatchOperation {
+        BatchOperation {
+            op: op.to_string(),
+            key: key.to_string(),
+            value: value.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_operations() {
+        assert_eq!(BatchOp::parse("SetAdd"), Some(BatchOp::SetAdd));
+        assert_eq!(BatchOp::parse("listclear"), Some(BatchOp::Del));
+        assert_eq!(BatchOp::parse("SetIntersection"), None);
+    }
+
+    #[test]
+    fn build_transaction() {
+        let (pipe, ops) = transaction(
+            &link(),
+            &[
+                operation("Set", "a", Some("1")),
+                operation("Increment", "n", None),
+                operation("Get", "a", None),
+            ],
+        )
+        .unwrap();
+        assert_eq!(ops, vec![BatchOp::Set, BatchOp::Increment, BatchOp::Get]);
+        let packed = String::from_utf8_lossy(&pipe.get_packed_pipeline()).to_string();
+        assert!(packed.starts_with("*1\r\n$5\r\nMULTI\r\n"), "{}", packed);
+        assert!(packed.contains("$3\r\np:a\r\n"), "{}", packed);
+        assert!(packed.ends_with("*1\r\n$4\r\nEXEC\r\n"), "{}", packed);
+
+        // missing value
+        assert!(transaction(&link(), &[operation("ListAdd", "l", None)]).is_err());
+        // unknown operation
+        assert!(transaction(&link(), &[operation("Flush", "a", None)]).is_err());
+    }
+
+    #[test]
+    fn convert_replies() {
+        let ops = [BatchOp::Get, BatchOp::Get, BatchOp::Del, BatchOp::SetQuery];
+        let replies = vec![
+            Value::Data(b"x".to_vec()),
+            Value::Nil,
+            Value::Int(0),
+            Value::Bulk(vec![Value::Data(b"m".to_vec())]),
+        ];
+        let converted = results(&ops, replies).unwrap();
+        assert_eq!(converted[0].value.as_deref(), Some("x"));
+        assert!(converted[0].exists);
+        assert!(!converted[1].exists);
+        assert!(!converted[2].exists);
+        assert_eq!(converted[3].values, Some(vec!["m".to_string()]));
+
+        // an aborted transaction has no replies
+        assert!(results(&ops, Vec::new()).is_err());
+    }
+}
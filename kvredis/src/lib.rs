@@ -0,0 +1,172 @@
+//! Redis connection management shared by the Redis capability providers
+//!
+//! Each actor link has an [ActorLink](link::ActorLink), which connects to a single server,
+//! a Redis Cluster, or a Sentinel-managed primary ([pool]), with the TLS and authentication
+//! settings from link values or provider config ([options]). The kvredis provider uses it
+//! for `wasmcloud:keyvalue`, and the redis-streams provider for `wasmcloud:messaging`.
+
+use std::collections::HashMap;
+
+use redis::RedisError;
+use serde::Deserialize;
+use wasmbus_rpc::error::{RpcError, RpcResult};
+
+pub mod link;
+pub mod options;
+pub mod pool;
+
+use options::ConnectionConfig;
+use pool::MAX_POOL_SIZE;
+
+/// Link value with the url of the redis server
+pub const REDIS_URL_KEY: &str = "URL";
+/// Link value with the number of pooled connections
+pub const POOL_SIZE_KEY: &str = "POOL_SIZE";
+/// Url used when neither the link values nor the provider config have one
+pub const DEFAULT_CONNECT_URL: &str = "redis://127.0.0.1:6379/";
+
+/// Provider configuration from `config_json`, with the settings used for links
+/// that don't override them in link values
+#[derive(Deserialize)]
+pub struct KvRedisConfig {
+    /// Default URL to connect when actor doesn't provide one on a link
+    #[serde(alias = "URL", alias = "Url")]
+    pub url: String,
+    /// Default number of connections per link, for links without a `POOL_SIZE` value
+    #[serde(default, alias = "POOL_SIZE")]
+    pub pool_size: Option<usize>,
+    /// Default TLS and authentication settings, for settings not in link values
+    #[serde(flatten)]
+    pub connection: ConnectionConfig,
+}
+
+impl Default for KvRedisConfig {
+    fn default() -> Self {
+        KvRedisConfig {
+            url: DEFAULT_CONNECT_URL.to_string(),
+            pool_size: None,
+            connection: ConnectionConfig::default(),
+        }
+    }
+}
+
+/// Converts a redis error into the error returned to the actor
+pub fn to_rpc_err(e: RedisError) -> RpcError {
+    RpcError::Other(format!("redis error: {}", e))
+}
+
+/// Returns the url from the `URL` link value (case-insensitive), or the default url
+pub fn get_redis_url(link_values: &HashMap<String, String>, default_connect_url: &str) -> String {
+    link_values
+        .iter()
+        .find(|(key, _value)| key.eq_ignore_ascii_case(REDIS_URL_KEY))
+        .map(|(_key, url)| url.to_owned())
+        .unwrap_or_else(|| default_connect_url.to_owned())
+}
+
+/// Returns the number of connections for the link, from the `POOL_SIZE` link value
+/// (case-insensitive), or the default
+pub fn get_pool_size(
+    link_values: &HashMap<String, String>,
+    default_size: usize,
+) -> RpcResult<usize> {
+    let size = match link_values
+        .iter()
+        .find(|(key, _value)| key.eq_ignore_ascii_case(POOL_SIZE_KEY))
+    {
+        Some((_key, value)) => value.trim().parse::<usize>().map_err(|_| {
+            RpcError::InvalidParameter(format!("invalid {} '{}'", POOL_SIZE_KEY, value))
+        })?,
+        None => default_size,
+    };
+    if size == 0 || size > MAX_POOL_SIZE {
+        return Err(RpcError::InvalidParameter(format!(
+            "{} must be between 1 and {}",
+            POOL_SIZE_KEY, MAX_POOL_SIZE
+        )));
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::{get_pool_size, get_redis_url, pool::DEFAULT_POOL_SIZE, KvRedisConfig};
+
+    const PROPER_URL: &str = "redis://127.0.0.1:6379";
+
+    #[test]
+    fn can_deserialize_config_case_insensitive() {
+        let lowercase_config = format!("{{\"url\": \"{}\"}}", PROPER_URL);
+        let uppercase_config = format!("{{\"URL\": \"{}\"}}", PROPER_URL);
+        let initial_caps_config = format!("{{\"Url\": \"{}\"}}", PROPER_URL);
+
+        assert_eq!(
+            PROPER_URL,
+            serde_json::from_str::<KvRedisConfig>(&lowercase_config)
+                .unwrap()
+                .url
+        );
+        assert_eq!(
+            PROPER_URL,
+            serde_json::from_str::<KvRedisConfig>(&uppercase_config)
+                .unwrap()
+                .url
+        );
+        assert_eq!(
+            PROPER_URL,
+            serde_json::from_str::<KvRedisConfig>(&initial_caps_config)
+                .unwrap()
+                .url
+        );
+    }
+
+    #[test]
+    fn can_accept_case_insensitive_url_parameters() {
+        let mut lowercase_map = HashMap::new();
+        lowercase_map.insert("url".to_string(), PROPER_URL.to_string());
+
+        assert_eq!(get_redis_url(&lowercase_map, ""), PROPER_URL);
+
+        let mut uppercase_map = HashMap::new();
+        uppercase_map.insert("URL".to_string(), PROPER_URL.to_string());
+
+        assert_eq!(get_redis_url(&uppercase_map, ""), PROPER_URL);
+
+        let mut spongebob_map_one = HashMap::new();
+        spongebob_map_one.insert("uRl".to_string(), PROPER_URL.to_string());
+
+        assert_eq!(get_redis_url(&spongebob_map_one, ""), PROPER_URL);
+
+        let mut spongebob_map_two = HashMap::new();
+        spongebob_map_two.insert("UrL".to_string(), PROPER_URL.to_string());
+
+        assert_eq!(get_redis_url(&spongebob_map_two, ""), PROPER_URL);
+    }
+
+    #[test]
+    fn can_read_pool_size() {
+        let mut values = HashMap::new();
+        assert_eq!(
+            get_pool_size(&values, DEFAULT_POOL_SIZE).unwrap(),
+            DEFAULT_POOL_SIZE
+        );
+
+        values.insert("pool_size".to_string(), "16".to_string());
+        assert_eq!(get_pool_size(&values, DEFAULT_POOL_SIZE).unwrap(), 16);
+
+        values.insert("pool_size".to_string(), "0".to_string());
+        assert!(get_pool_size(&values, DEFAULT_POOL_SIZE).is_err());
+
+        values.insert("pool_size".to_string(), "many".to_string());
+        assert!(get_pool_size(&values, DEFAULT_POOL_SIZE).is_err());
+
+        let config: KvRedisConfig = serde_json::from_str(&format!(
+            "{{\"url\": \"{}\", \"pool_size\": 8}}",
+            PROPER_URL
+        ))
+        .unwrap();
+        assert_eq!(config.pool_size, Some(8));
+    }
+}
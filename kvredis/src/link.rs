@@ -0,0 +1,417 @@
+//! Connections and settings for one actor link
+//!
+//! Each actor public key has an [ActorLink] with a pool of multiplexed connections.
+//! A request takes a clone of the next connection in the pool, and no lock is held while
+//! the command is in progress, so requests from several instances of the same actor are
+//! pipelined on the connections instead of waiting for each other.
+//!
+//! The connections are opened when they are first needed. If they can't be opened, requests
+//! fail with the connection error, and the next attempt is delayed with exponential backoff.
+//!
+//! If the link has a `KEY_PREFIX` value, the prefix is added to every key the actor uses,
+//! so actors sharing a database don't see each other's keys.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use redis::{FromRedisValue, Pipeline};
+use tokio::{sync::OnceCell, task::JoinHandle};
+use tracing::{info, warn};
+use wasmbus_rpc::error::{RpcError, RpcResult};
+
+use crate::options::ConnectionOptions;
+use crate::pool::{ConnectionPool, Topology};
+
+const KEY_PREFIX_KEY: &str = "KEY_PREFIX";
+
+/// How long to wait for the link's connections to open
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Delay before retrying after the first failed connection attempt.
+/// The delay doubles after each failure, up to MAX_RETRY_DELAY
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// The most recent failure to connect
+struct ConnectFailure {
+    error: String,
+    attempts: u32,
+    retry_at: Instant,
+}
+
+/// Connection state of a link, reported in health checks
+#[derive(Debug, PartialEq, Eq)]
+pub enum LinkHealth {
+    Connected,
+    /// no connection attempt has finished yet
+    Connecting,
+    /// the last attempt failed, with this error
+    Failed(String),
+}
+
+pub struct ActorLink {
+    actor_id: String,
+    topology: Topology,
+    options: ConnectionOptions,
+    pool_size: usize,
+    key_prefix: String,
+    /// connections are opened by the first request, or by [connect](ActorLink::connect)
+    pool: OnceCell<ConnectionPool>,
+    failure: Mutex<Option<ConnectFailure>>,
+    /// connection error from the latest request, cleared when a request succeeds
+    request_failure: Mutex<Option<String>>,
+    /// task sending key change notifications to the actor, stopped when the link is dropped
+    notifier: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl ActorLink {
+    /// Creates a link, without connecting
+    pub fn new(
+        actor_id: &str,
+        topology: Topology,
+        options: ConnectionOptions,
+        pool_size: usize,
+        key_prefix: String,
+    ) -> Self {
+        ActorLink {
+            actor_id: actor_id.to_string(),
+            topology,
+            options,
+            pool_size,
+            key_prefix,
+            pool: OnceCell::new(),
+            failure: Mutex::new(None),
+            request_failure: Mutex::new(None),
+            notifier: Mutex::new(None),
+        }
+    }
+
+    /// Keeps the task that sends key change notifications for this link,
+    /// so it stops when the link is removed
+    pub fn set_notifier(&self, task: JoinHandle<()>) {
+        if let Some(previous) = self.notifier.lock().unwrap().replace(task) {
+            previous.abort();
+        }
+    }
+
+    /// Returns the prefix added to the actor's keys
+    pub fn key_prefix(&self) -> &str {
+        &self.key_prefix
+    }
+
+    /// Returns the redis key for the actor's key
+    pub fn key(&self, key: &str) -> String {
+        format!("{}{}", self.key_prefix, key)
+    }
+
+    /// Returns the redis keys for the actor's keys
+    pub fn keys(&self, keys: &[String]) -> Vec<String> {
+        keys.iter().map(|key| self.key(key)).collect()
+    }
+
+    /// Returns the redis pattern for a pattern of the actor's keys
+    pub fn key_pattern(&self, pattern: &str) -> String {
+        format!("{}{}", escape_pattern(&self.key_prefix), pattern)
+    }
+
+    /// Returns the actor's key for a redis key, without the prefix
+    pub fn strip_key(&self, key: &str) -> String {
+        key.strip_prefix(self.key_prefix.as_str())
+            .unwrap_or(key)
+            .to_string()
+    }
+
+    /// Returns true if the link connects to a Redis Cluster
+    pub fn is_cluster(&self) -> bool {
+        matches!(self.topology, Topology::Cluster(_))
+    }
+
+    /// Returns the connection state of the link. After the connections are opened,
+    /// the link is reported as failed while requests are losing their connection
+    /// or can't reach the server
+    pub fn health(&self) -> LinkHealth {
+        if self.pool.initialized() {
+            return match self.request_failure.lock().unwrap().as_ref() {
+                Some(error) => LinkHealth::Failed(error.clone()),
+                None => LinkHealth::Connected,
+            };
+        }
+        match self.failure.lock().unwrap().as_ref() {
+            Some(failure) => LinkHealth::Failed(failure.error.clone()),
+            None => LinkHealth::Connecting,
+        }
+    }
+
+    /// Execute a redis command on one of the link's connections
+    pub async fn exec<T: FromRedisValue>(&self, cmd: &redis::Cmd) -> RpcResult<T> {
+        let pool = self.connect().await?;
+        let generation = pool.generation();
+        let result = cmd.query_async(&mut pool.get()).await;
+        self.request_finished(pool, generation, result).await
+    }
+
+    /// Execute a redis command on the cluster node that owns the hash slot.
+    /// For links that don't connect to a cluster, the slot is ignored
+    pub async fn exec_on_slot<T: FromRedisValue>(
+        &self,
+        cmd: &redis::Cmd,
+        slot: u16,
+    ) -> RpcResult<T> {
+        let pool = self.connect().await?;
+        let generation = pool.generation();
+        let result = pool.get().query_on_slot(cmd, slot).await;
+        self.request_finished(pool, generation, result).await
+    }
+
+    /// Execute a pipeline of redis commands on one of the link's connections.
+    /// Commands in the pipeline are sent together, and are not interleaved with
+    /// other requests on the connection.
+    pub async fn exec_pipeline<T: FromRedisValue>(&self, pipe: &Pipeline) -> RpcResult<T> {
+        let pool = self.connect().await?;
+        let generation = pool.generation();
+        let result = pipe.query_async(&mut pool.get()).await;
+        self.request_finished(pool, generation, result).await
+    }
+
+    /// Records whether the request reached the server, for health checks, and checks
+    /// for a failover if it didn't
+    async fn request_finished<T>(
+        &self,
+        pool: &ConnectionPool,
+        generation: u64,
+        result: redis::RedisResult<T>,
+    ) -> RpcResult<T> {
+        match result {
+            Ok(val) => {
+                self.request_failure.lock().unwrap().take();
+                Ok(val)
+            }
+            Err(e) => {
+                if is_connection_error(&e) {
+                    *self.request_failure.lock().unwrap() = Some(e.to_string());
+                }
+                pool.check_failover(&e, generation).await;
+                Err(crate::to_rpc_err(e))
+            }
+        }
+    }
+
+    /// Returns the link's connections, opening them if this is the first request.
+    /// After a failed attempt, requests fail with the same error without connecting,
+    /// until the retry delay has passed.
+    pub async fn connect(&self) -> RpcResult<&ConnectionPool> {
+        self.pool
+            .get_or_try_init(|| async {
+                // another request may have failed while this one was waiting
+                if let Some(e) = self.backing_off() {
+                    return Err(e);
+                }
+                let result = tokio::time::timeout(
+                    CONNECT_TIMEOUT,
+                    ConnectionPool::connect(
+                        self.topology.clone(),
+                        self.options.clone(),
+                        self.pool_size,
+                    ),
+                )
+                .await;
+                match result {
+                    Ok(Ok(pool)) => {
+                        info!(actor_id = %self.actor_id, pool_size = pool.size(), "connected to redis");
+                        self.failure.lock().unwrap().take();
+                        Ok(pool)
+                    }
+                    Ok(Err(e)) => Err(self.connect_failed(e.to_string())),
+                    Err(_) => Err(self.connect_failed(format!(
+                        "timed out after {}s",
+                        CONNECT_TIMEOUT.as_secs()
+                    ))),
+                }
+            })
+            .await
+    }
+
+    /// Returns the last connection error, if the retry delay after it has not yet passed
+    fn backing_off(&self) -> Option<RpcError> {
+        let failure = self.failure.lock().unwrap();
+        match failure.as_ref() {
+            Some(f) if Instant::now() < f.retry_at => Some(self.not_connected(f)),
+            _ => None,
+        }
+    }
+
+    /// Records a failed connection attempt, and returns the error for the request
+    fn connect_failed(&self, error: String) -> RpcError {
+        let mut failure = self.failure.lock().unwrap();
+        let attempts = failure.as_ref().map(|f| f.attempts).unwrap_or_default() + 1;
+        let delay = retry_delay(attempts);
+        warn!(
+            actor_id = %self.actor_id,
+            %error,
+            attempts,
+            retry_in_secs = delay.as_secs(),
+            "unable to connect to redis"
+        );
+        let f = failure.insert(ConnectFailure {
+            error,
+            attempts,
+            retry_at: Instant::now() + delay,
+        });
+        self.not_connected(f)
+    }
+
+    fn not_connected(&self, failure: &ConnectFailure) -> RpcError {
+        RpcError::Other(format!(
+            "redis error: unable to connect for actor {} after {} attempt(s): {}. Retrying in {}s",
+            self.actor_id,
+            failure.attempts,
+            failure.error,
+            failure
+                .retry_at
+                .saturating_duration_since(Instant::now())
+                .as_secs()
+        ))
+    }
+}
+
+impl Drop for ActorLink {
+    fn drop(&mut self) {
+        if let Some(task) = self.notifier.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+}
+
+/// Returns true if the error means the server couldn't be reached, rather than
+/// a failed command
+fn is_connection_error(error: &redis::RedisError) -> bool {
+    error.is_io_error()
+        || error.is_connection_dropped()
+        || error.is_connection_refusal()
+        || error.is_timeout()
+}
+
+/// Delay before the next attempt after `attempts` failures
+pub fn retry_delay(attempts: u32) -> Duration {
+    MIN_RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Returns the key prefix from the `KEY_PREFIX` link value (case-insensitive), or an empty prefix
+pub fn get_key_prefix(link_values: &HashMap<String, String>) -> String {
+    link_values
+        .iter()
+        .find(|(key, _value)| key.eq_ignore_ascii_case(KEY_PREFIX_KEY))
+        .map(|(_key, prefix)| prefix.to_owned())
+        .unwrap_or_default()
+}
+
+/// Escapes characters with special meaning in a SCAN MATCH or PSUBSCRIBE pattern
+pub fn escape_pattern(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn key_prefix_from_link() {
+        let mut values = HashMap::new();
+        assert_eq!(get_key_prefix(&values), "");
+
+        values.insert("key_prefix".to_string(), "team1:".to_string());
+        assert_eq!(get_key_prefix(&values), "team1:");
+    }
+
+    #[test]
+    fn prefixed_patterns() {
+        let link = ActorLink::new(
+            "actor",
+            Topology::Single("redis://127.0.0.1:6379/".to_string()),
+            ConnectionOptions::default(),
+            1,
+            "team[1]:".to_string(),
+        );
+        assert_eq!(link.key_pattern("user:*"), "team\\[1\\]:user:*");
+        assert_eq!(link.strip_key("team[1]:user:7"), "user:7");
+    }
+
+    #[test]
+    fn backoff() {
+        assert_eq!(retry_delay(1), MIN_RETRY_DELAY);
+        assert_eq!(retry_delay(2), MIN_RETRY_DELAY * 2);
+        assert_eq!(retry_delay(4), MIN_RETRY_DELAY * 8);
+        assert_eq!(retry_delay(100), MAX_RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn failed_connect_is_reported() {
+        // nothing listens on port 1
+        let link = ActorLink::new(
+            "actor",
+            Topology::Single("redis://127.0.0.1:1/".to_string()),
+            ConnectionOptions::default(),
+            1,
+            String::new(),
+        );
+        assert_eq!(link.health(), LinkHealth::Connecting);
+
+        let err = link
+            .exec::<bool>(&redis::Cmd::exists("x"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("after 1 attempt(s)"), "{}", err);
+        assert!(matches!(link.health(), LinkHealth::Failed(_)));
+
+        // the next request fails without another attempt, with the original error
+        let err2 = link
+            .exec::<bool>(&redis::Cmd::exists("x"))
+            .await
+            .unwrap_err();
+        assert!(err2.to_string().contains("after 1 attempt(s)"), "{}", err2);
+    }
+
+    #[tokio::test]
+    async fn lost_connection_is_reported() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // answers the connection setup, then closes the connection
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let link = ActorLink::new(
+            "actor",
+            Topology::Single(format!("redis://127.0.0.1:{}/", port)),
+            ConnectionOptions::default(),
+            1,
+            String::new(),
+        );
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = socket.read(&mut buf).await.unwrap();
+            socket.write_all(b"+OK\r\n+OK\r\n").await.unwrap();
+            socket
+        });
+        link.connect().await.unwrap();
+        assert_eq!(link.health(), LinkHealth::Connected);
+        drop(server.await.unwrap());
+
+        assert!(link.exec::<bool>(&redis::Cmd::exists("x")).await.is_err());
+        assert!(matches!(link.health(), LinkHealth::Failed(_)));
+    }
+
+    #[test]
+    fn escape_prefix() {
+        assert_eq!(escape_pattern("team1:"), "team1:");
+        assert_eq!(escape_pattern("a*b?[c]\\"), "a\\*b\\?\\[c\\]\\\\");
+    }
+}
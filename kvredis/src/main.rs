@@ -2,48 +2,104 @@
 //!
 //! This implementation is multi-threaded and operations between different actors
 //! use different connections and can run in parallel.
-//! A single connection is shared by all instances of the same actor id (public key),
-//! so there may be some brief lock contention if several instances of the same actor
-//! are simultaneously attempting to communicate with redis. See documentation
-//! on the [exec](#exec) function for more information.
+//! Each link has a small pool of multiplexed connections, shared by all instances
+//! of the same actor id (public key). Requests from concurrent actor instances are
+//! pipelined over the pooled connections without waiting for each other. See documentation
+//! in the [link](wasmcloud_provider_kvredis::link) module for more information.
 //!
 //!
-use std::{collections::HashMap, convert::Infallible, ops::DerefMut, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    sync::Arc,
+};
 
-use redis::{aio::ConnectionManager, FromRedisValue, RedisError};
-use serde::Deserialize;
 use tokio::sync::RwLock;
-use tracing::{info, instrument, warn};
-use wasmbus_rpc::provider::prelude::*;
+use tracing::{info, instrument};
+use wasmbus_rpc::{
+    core::{HealthCheckRequest, HealthCheckResponse},
+    provider::prelude::*,
+};
 use wasmcloud_interface_keyvalue::{
     GetResponse, IncrementRequest, KeyValue, KeyValueReceiver, ListAddRequest, ListDelRequest,
     ListRangeRequest, SetAddRequest, SetDelRequest, SetRequest, StringList,
 };
 
-const REDIS_URL_KEY: &str = "URL";
-const DEFAULT_CONNECT_URL: &str = "redis://127.0.0.1:6379/";
-
-#[derive(Deserialize)]
-struct KvRedisConfig {
-    /// Default URL to connect when actor doesn't provide one on a link
-    #[serde(alias = "URL", alias = "Url")]
-    url: String,
+// this is not an external library - built locally via build.rs & codegen.toml
+#[allow(dead_code)]
+pub mod wasmcloud_interface_kvredis {
+    include!(concat!(env!("OUT_DIR"), "/gen/kvredis.rs"));
 }
+use wasmcloud_interface_kvredis::{
+    BatchRequest, BatchResponse, CompareAndSetRequest, DeleteKeysRequest, ExpireRequest, FieldMap,
+    HashDelRequest, HashGetRequest, HashGetResponse, HashSetRequest, KvRedis, KvRedisReceiver,
+    ListAddWithExpiryRequest, ListKeysRequest, ListKeysResponse, ScoredMember, ScoredMembers,
+    SetAddWithExpiryRequest, SortedSetAddRequest, SortedSetIncrementRequest,
+    SortedSetRangeByRankRequest, SortedSetRangeByScoreRequest, TtlResponse,
+};
+
+use wasmcloud_provider_kvredis::{
+    get_pool_size, get_redis_url,
+    link::{get_key_prefix, ActorLink, LinkHealth},
+    options::ConnectionConfig,
+    pool::{same_slot, Topology, DEFAULT_POOL_SIZE},
+    KvRedisConfig,
+};
+
+mod batch;
+mod notify;
+use notify::NotifyConfig;
+mod scan;
+
+/// Adds ARGV[1] seconds to the time to live of KEYS[1]. Keys without an expiration
+/// are left unchanged. Returns 1 if the key exists, otherwise 0
+const EXTEND_EXPIRY_SCRIPT: &str = r#"
+local ttl = redis.call('TTL', KEYS[1])
+if ttl == -2 then
+  return 0
+end
+if ttl >= 0 then
+  redis.call('EXPIRE', KEYS[1], ttl + tonumber(ARGV[1]))
+end
+return 1
+"#;
+
+/// Sets KEYS[1] to ARGV[3], or deletes it if ARGV[2] is '0', if its current value
+/// is ARGV[5], or if ARGV[1] is '0' and the key doesn't exist. ARGV[4] is the expiration
+/// in seconds, or 0 for none. The '0'/'1' flags keep empty strings distinct from
+/// missing values. Returns 1 if the key was changed, otherwise 0
+const COMPARE_AND_SET_SCRIPT: &str = r#"
+local current = redis.call('GET', KEYS[1])
+if ARGV[1] == '0' then
+  if current then
+    return 0
+  end
+elseif current ~= ARGV[5] then
+  return 0
+end
+if ARGV[2] == '0' then
+  redis.call('DEL', KEYS[1])
+elseif tonumber(ARGV[4]) > 0 then
+  redis.call('SET', KEYS[1], ARGV[3], 'EX', ARGV[4])
+else
+  redis.call('SET', KEYS[1], ARGV[3])
+end
+return 1
+"#;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let hd = load_host_data()?;
 
-    let default_connect_url = if let Some(raw_config) = hd.config_json.as_ref() {
-        match serde_json::from_str(&raw_config) {
-            Ok(KvRedisConfig { url }) => url,
-            _ => DEFAULT_CONNECT_URL.to_string(),
-        }
+    let config = if let Some(raw_config) = hd.config_json.as_ref() {
+        // fail rather than connect without the configured TLS and credentials
+        serde_json::from_str(raw_config)
+            .map_err(|e| format!("invalid provider configuration: {}", e))?
     } else {
-        DEFAULT_CONNECT_URL.to_string()
+        KvRedisConfig::default()
     };
 
     provider_start(
-        KvRedisProvider::new(&default_connect_url),
+        KvRedisProvider::new(config),
         hd,
         Some("KeyValue Redis Provider".to_string()),
     )?;
@@ -54,18 +110,24 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 /// Redis keyValue provider implementation.
 #[derive(Default, Clone, Provider)]
-#[services(KeyValue)]
+#[services(KeyValue, KvRedis)]
 struct KvRedisProvider {
     // store redis connections per actor
-    actors: Arc<RwLock<HashMap<String, RwLock<ConnectionManager>>>>,
+    actors: Arc<RwLock<HashMap<String, Arc<ActorLink>>>>,
     // Default connection URL for actors without a `URL` link value
     default_connect_url: String,
+    // Default number of connections for actors without a `POOL_SIZE` link value
+    default_pool_size: usize,
+    // Default TLS and authentication settings
+    default_connection: ConnectionConfig,
 }
 
 impl KvRedisProvider {
-    fn new(default_connect_url: &str) -> Self {
+    fn new(config: KvRedisConfig) -> Self {
         KvRedisProvider {
-            default_connect_url: default_connect_url.to_string(),
+            default_connect_url: config.url,
+            default_pool_size: config.pool_size.unwrap_or(DEFAULT_POOL_SIZE),
+            default_connection: config.connection,
             ..Default::default()
         }
     }
@@ -83,30 +145,67 @@ impl ProviderHandler for KvRedisProvider {
     #[instrument(level = "debug", skip(self, ld), fields(actor_id = %ld.actor_id))]
     async fn put_link(&self, ld: &LinkDefinition) -> RpcResult<bool> {
         let redis_url = get_redis_url(&ld.values, &self.default_connect_url);
-
-        if let Ok(client) = redis::Client::open(redis_url.clone()) {
-            match client.get_connection_manager().await {
-                Ok(conn_manager) => {
-                    let mut update_map = self.actors.write().await;
-                    update_map.insert(ld.actor_id.to_string(), RwLock::new(conn_manager));
-                }
-                Err(e) => {
-                    warn!(
-                        "Could not create Redis connection manager for actor {}, keyvalue operations will fail: {}",
-                        ld.actor_id, e
-                    );
-                }
-            }
-        } else {
-            warn!(
-                "Could not create Redis client for actor {}, keyvalue operations will fail",
-                ld.actor_id
-            )
+        let topology = Topology::from_link(&ld.values, redis_url)?;
+        topology.validate()?;
+        let options = self
+            .default_connection
+            .with_link_values(&ld.values)?
+            .load(&topology)?;
+        let pool_size = get_pool_size(&ld.values, self.default_pool_size)?;
+        let key_prefix = get_key_prefix(&ld.values);
+        let notify = NotifyConfig::from_link(&ld.values, &topology)?;
+
+        let link = Arc::new(ActorLink::new(
+            &ld.actor_id,
+            topology.clone(),
+            options.clone(),
+            pool_size,
+            key_prefix,
+        ));
+        if let Some(config) = notify {
+            link.set_notifier(notify::spawn(ld.clone(), topology, options, &link, config));
         }
+        let mut update_map = self.actors.write().await;
+        update_map.insert(ld.actor_id.to_string(), link.clone());
+
+        // start connecting now, so the connection is likely to be ready for the first request.
+        // If it fails, the error is logged, reported by health checks, and returned to the actor
+        tokio::spawn(async move {
+            let _ = link.connect().await;
+        });
 
         Ok(true)
     }
 
+    /// Reports the connection state of all links. The provider is healthy if
+    /// no link has failed to connect
+    async fn health_request(&self, _arg: &HealthCheckRequest) -> RpcResult<HealthCheckResponse> {
+        let rd = self.actors.read().await;
+        let mut connecting = 0;
+        let mut failed = Vec::new();
+        for (actor_id, link) in rd.iter() {
+            match link.health() {
+                LinkHealth::Connected => {}
+                LinkHealth::Connecting => connecting += 1,
+                LinkHealth::Failed(error) => failed.push(format!("{}: {}", actor_id, error)),
+            }
+        }
+        let message = format!(
+            "links={} connecting={} failed={}",
+            rd.len(),
+            connecting,
+            failed.len()
+        );
+        Ok(HealthCheckResponse {
+            healthy: failed.is_empty(),
+            message: Some(if failed.is_empty() {
+                message
+            } else {
+                format!("{} ({})", message, failed.join("; "))
+            }),
+        })
+    }
+
     /// Handle notification that a link is dropped - close the connection
     #[instrument(level = "info", skip(self))]
     async fn delete_link(&self, actor_id: &str) {
@@ -128,24 +227,23 @@ impl ProviderHandler for KvRedisProvider {
     }
 }
 
-fn to_rpc_err(e: RedisError) -> RpcError {
-    RpcError::Other(format!("redis error: {}", e))
-}
-
 // There are two api styles you can use for invoking redis. You can build any raw command
 // as a string command and a sequence of args:
 // ```
-//     let mut cmd = redis::cmd("SREM");
-//     let value: u32 = self.exec(ctx, &mut cmd.arg(&arg.set_name).arg(&arg.value)).await?;
+//     let cmd = redis::cmd("SREM").arg(link.key(&arg.set_name)).arg(&arg.value).to_owned();
+//     let value: u32 = link.exec(&cmd).await?;
 // ```
 // or you can call a method on Cmd, as in
 // ```
-//     let mut cmd = redis::Cmd::srem(&arg.set_name, &arg.value);
-//     let value: u32 = self.exec(ctx, &mut cmd).await?;
+//     let cmd = redis::Cmd::srem(link.key(&arg.set_name), &arg.value);
+//     let value: u32 = link.exec(&cmd).await?;
 //```
 // The latter api style has better rust compile-time type checking for args.
 // The rust docs for cmd and Cmd don't document arg types or return types.
 // For that, you need to look at https://redis.io/commands#
+//
+// Keys from the actor must be passed through `link.key()` or `link.keys()`
+// to add the link's key prefix.
 
 /// Handle KeyValue methods that interact with redis
 #[async_trait]
@@ -153,8 +251,9 @@ impl KeyValue for KvRedisProvider {
     /// Increments a numeric value, returning the new value
     #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.key))]
     async fn increment(&self, ctx: &Context, arg: &IncrementRequest) -> RpcResult<i32> {
-        let mut cmd = redis::Cmd::incr(&arg.key, &arg.value);
-        let val: i32 = self.exec(ctx, &mut cmd).await?;
+        let link = self.link(ctx).await?;
+        let cmd = redis::Cmd::incr(link.key(&arg.key), &arg.value);
+        let val: i32 = link.exec(&cmd).await?;
         Ok(val)
     }
 
@@ -165,16 +264,18 @@ impl KeyValue for KvRedisProvider {
         ctx: &Context,
         arg: &TS,
     ) -> RpcResult<bool> {
-        let mut cmd = redis::Cmd::exists(arg.to_string());
-        let val: bool = self.exec(ctx, &mut cmd).await?;
+        let link = self.link(ctx).await?;
+        let cmd = redis::Cmd::exists(link.key(&arg.to_string()));
+        let val: bool = link.exec(&cmd).await?;
         Ok(val)
     }
 
     /// Deletes a key, returning true if the key was deleted
     #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.to_string()))]
     async fn del<TS: ToString + ?Sized + Sync>(&self, ctx: &Context, arg: &TS) -> RpcResult<bool> {
-        let mut cmd = redis::Cmd::del(arg.to_string());
-        let val: i32 = self.exec(ctx, &mut cmd).await?;
+        let link = self.link(ctx).await?;
+        let cmd = redis::Cmd::del(link.key(&arg.to_string()));
+        let val: i32 = link.exec(&cmd).await?;
         Ok(val > 0)
     }
 
@@ -187,8 +288,9 @@ impl KeyValue for KvRedisProvider {
         ctx: &Context,
         arg: &TS,
     ) -> RpcResult<GetResponse> {
-        let mut cmd = redis::Cmd::get(arg.to_string());
-        let val: Option<String> = self.exec(ctx, &mut cmd).await?;
+        let link = self.link(ctx).await?;
+        let cmd = redis::Cmd::get(link.key(&arg.to_string()));
+        let val: Option<String> = link.exec(&cmd).await?;
         let resp = match val {
             Some(s) => GetResponse {
                 exists: true,
@@ -205,8 +307,9 @@ impl KeyValue for KvRedisProvider {
     /// Append a value onto the end of a list. Returns the new list size
     #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.list_name))]
     async fn list_add(&self, ctx: &Context, arg: &ListAddRequest) -> RpcResult<u32> {
-        let mut cmd = redis::Cmd::rpush(&arg.list_name, &arg.value);
-        let val: u32 = self.exec(ctx, &mut cmd).await?;
+        let link = self.link(ctx).await?;
+        let cmd = redis::Cmd::rpush(link.key(&arg.list_name), &arg.value);
+        let val: u32 = link.exec(&cmd).await?;
         Ok(val)
     }
 
@@ -225,8 +328,9 @@ impl KeyValue for KvRedisProvider {
     /// Deletes an item from a list. Returns true if the item was removed.
     #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.list_name))]
     async fn list_del(&self, ctx: &Context, arg: &ListDelRequest) -> RpcResult<bool> {
-        let mut cmd = redis::Cmd::lrem(&arg.list_name, 1, &arg.value);
-        let val: u32 = self.exec(ctx, &mut cmd).await?;
+        let link = self.link(ctx).await?;
+        let cmd = redis::Cmd::lrem(link.key(&arg.list_name), 1, &arg.value);
+        let val: u32 = link.exec(&cmd).await?;
         Ok(val > 0)
     }
 
@@ -236,8 +340,13 @@ impl KeyValue for KvRedisProvider {
     /// is beyond the end of the list, it is treated as the end of the list.
     #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.list_name))]
     async fn list_range(&self, ctx: &Context, arg: &ListRangeRequest) -> RpcResult<StringList> {
-        let mut cmd = redis::Cmd::lrange(&arg.list_name, arg.start as isize, arg.stop as isize);
-        let val: StringList = self.exec(ctx, &mut cmd).await?;
+        let link = self.link(ctx).await?;
+        let cmd = redis::Cmd::lrange(
+            link.key(&arg.list_name),
+            arg.start as isize,
+            arg.stop as isize,
+        );
+        let val: StringList = link.exec(&cmd).await?;
         Ok(val)
     }
 
@@ -246,27 +355,30 @@ impl KeyValue for KvRedisProvider {
     /// or 0 for no expiration.
     #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.key))]
     async fn set(&self, ctx: &Context, arg: &SetRequest) -> RpcResult<()> {
-        let mut cmd = match arg.expires {
-            0 => redis::Cmd::set(&arg.key, &arg.value),
-            _ => redis::Cmd::set_ex(&arg.key, &arg.value, arg.expires as u64),
+        let link = self.link(ctx).await?;
+        let cmd = match arg.expires {
+            0 => redis::Cmd::set(link.key(&arg.key), &arg.value),
+            _ => redis::Cmd::set_ex(link.key(&arg.key), &arg.value, arg.expires as u64),
         };
-        let _value: Option<String> = self.exec(ctx, &mut cmd).await?;
+        let _value: Option<String> = link.exec(&cmd).await?;
         Ok(())
     }
 
     /// Add an item into a set. Returns number of items added
     #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.set_name))]
     async fn set_add(&self, ctx: &Context, arg: &SetAddRequest) -> RpcResult<u32> {
-        let mut cmd = redis::Cmd::sadd(&arg.set_name, &arg.value);
-        let value: u32 = self.exec(ctx, &mut cmd).await?;
+        let link = self.link(ctx).await?;
+        let cmd = redis::Cmd::sadd(link.key(&arg.set_name), &arg.value);
+        let value: u32 = link.exec(&cmd).await?;
         Ok(value)
     }
 
     /// Remove a item from the set. Returns
     #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.set_name))]
     async fn set_del(&self, ctx: &Context, arg: &SetDelRequest) -> RpcResult<u32> {
-        let mut cmd = redis::Cmd::srem(&arg.set_name, &arg.value);
-        let value: u32 = self.exec(ctx, &mut cmd).await?;
+        let link = self.link(ctx).await?;
+        let cmd = redis::Cmd::srem(link.key(&arg.set_name), &arg.value);
+        let value: u32 = link.exec(&cmd).await?;
         Ok(value)
     }
 
@@ -288,8 +400,13 @@ impl KeyValue for KvRedisProvider {
         ctx: &Context,
         arg: &StringList,
     ) -> Result<StringList, RpcError> {
-        let mut cmd = redis::Cmd::sinter(arg);
-        let value: Vec<String> = self.exec(ctx, &mut cmd).await?;
+        let link = self.link(ctx).await?;
+        let keys = link.keys(arg);
+        if link.is_cluster() && !same_slot(&keys) {
+            return Ok(intersection(query_sets(&link, &keys).await?));
+        }
+        let cmd = redis::Cmd::sinter(keys);
+        let value: Vec<String> = link.exec(&cmd).await?;
         Ok(value)
     }
 
@@ -299,114 +416,441 @@ impl KeyValue for KvRedisProvider {
         ctx: &Context,
         arg: &TS,
     ) -> RpcResult<StringList> {
-        let mut cmd = redis::Cmd::smembers(arg.to_string());
-        let values: Vec<String> = self.exec(ctx, &mut cmd).await?;
+        let link = self.link(ctx).await?;
+        let cmd = redis::Cmd::smembers(link.key(&arg.to_string()));
+        let values: Vec<String> = link.exec(&cmd).await?;
         Ok(values)
     }
 
     #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, keys = ?arg))]
     async fn set_union(&self, ctx: &Context, arg: &StringList) -> RpcResult<StringList> {
-        let mut cmd = redis::Cmd::sunion(arg);
-        let values: Vec<String> = self.exec(ctx, &mut cmd).await?;
+        let link = self.link(ctx).await?;
+        let keys = link.keys(arg);
+        if link.is_cluster() && !same_slot(&keys) {
+            return Ok(union(query_sets(&link, &keys).await?));
+        }
+        let cmd = redis::Cmd::sunion(keys);
+        let values: Vec<String> = link.exec(&cmd).await?;
         Ok(values)
     }
 }
 
+/// Handle redis-specific extensions to the keyvalue contract
+#[async_trait]
+impl KvRedis for KvRedisProvider {
+    /// Append a value onto the end of a list, and set the list's expiration.
+    /// Returns the new list size
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.list_name))]
+    async fn list_add_with_expiry(
+        &self,
+        ctx: &Context,
+        arg: &ListAddWithExpiryRequest,
+    ) -> RpcResult<u32> {
+        let link = self.link(ctx).await?;
+        let key = link.key(&arg.list_name);
+        let mut pipe = redis::pipe();
+        pipe.atomic().rpush(&key, &arg.value);
+        if arg.expires > 0 {
+            pipe.expire(&key, arg.expires as i64).ignore();
+        }
+        let (val,): (u32,) = link.exec_pipeline(&pipe).await?;
+        Ok(val)
+    }
+
+    /// Add an item into a set, and set the set's expiration.
+    /// Returns number of items added
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.set_name))]
+    async fn set_add_with_expiry(
+        &self,
+        ctx: &Context,
+        arg: &SetAddWithExpiryRequest,
+    ) -> RpcResult<u32> {
+        let link = self.link(ctx).await?;
+        let key = link.key(&arg.set_name);
+        let mut pipe = redis::pipe();
+        pipe.atomic().sadd(&key, &arg.value);
+        if arg.expires > 0 {
+            pipe.expire(&key, arg.expires as i64).ignore();
+        }
+        let (val,): (u32,) = link.exec_pipeline(&pipe).await?;
+        Ok(val)
+    }
+
+    /// Returns the remaining time to live of a key
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.to_string()))]
+    async fn get_ttl<TS: ToString + ?Sized + Sync>(
+        &self,
+        ctx: &Context,
+        arg: &TS,
+    ) -> RpcResult<TtlResponse> {
+        let link = self.link(ctx).await?;
+        let cmd = redis::Cmd::ttl(link.key(&arg.to_string()));
+        let val: i64 = link.exec(&cmd).await?;
+        Ok(ttl_response(val))
+    }
+
+    /// Removes the expiration of a key.
+    /// Returns true if the key had an expiration that was removed
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.to_string()))]
+    async fn persist<TS: ToString + ?Sized + Sync>(
+        &self,
+        ctx: &Context,
+        arg: &TS,
+    ) -> RpcResult<bool> {
+        let link = self.link(ctx).await?;
+        let cmd = redis::Cmd::persist(link.key(&arg.to_string()));
+        let val: bool = link.exec(&cmd).await?;
+        Ok(val)
+    }
+
+    /// Sets the expiration of a key, or extends it if `extend` is true.
+    /// Returns true if the key exists
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.key))]
+    async fn expire(&self, ctx: &Context, arg: &ExpireRequest) -> RpcResult<bool> {
+        if arg.seconds == 0 {
+            return Err(RpcError::InvalidParameter(
+                "expire seconds must be greater than zero".to_string(),
+            ));
+        }
+        let link = self.link(ctx).await?;
+        let cmd = if arg.extend.unwrap_or_default() {
+            let mut cmd = redis::cmd("EVAL");
+            cmd.arg(EXTEND_EXPIRY_SCRIPT)
+                .arg(1)
+                .arg(link.key(&arg.key))
+                .arg(arg.seconds);
+            cmd
+        } else {
+            redis::Cmd::expire(link.key(&arg.key), arg.seconds as i64)
+        };
+        let val: bool = link.exec(&cmd).await?;
+        Ok(val)
+    }
+
+    /// Runs the operations in a MULTI/EXEC transaction, and returns their results in order
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, count = arg.operations.len()))]
+    async fn batch(&self, ctx: &Context, arg: &BatchRequest) -> RpcResult<BatchResponse> {
+        let link = self.link(ctx).await?;
+        let (pipe, ops) = batch::transaction(&link, &arg.operations)?;
+        if ops.is_empty() {
+            return Ok(BatchResponse::default());
+        }
+        if link.is_cluster() {
+            let keys: Vec<String> = arg.operations.iter().map(|op| link.key(&op.key)).collect();
+            if !same_slot(&keys) {
+                return Err(RpcError::InvalidParameter(
+                    "keys in a batch must be in the same cluster hash slot. Use a hash tag, \
+                     such as {user1}.name and {user1}.email"
+                        .to_string(),
+                ));
+            }
+        }
+        let replies: Vec<redis::Value> = link.exec_pipeline(&pipe).await?;
+        Ok(BatchResponse {
+            results: batch::results(&ops, replies)?,
+        })
+    }
+
+    /// Sets the key to `value`, or deletes it if `value` is not set, if its current value
+    /// is `expected`, or if it doesn't exist and `expected` is not set.
+    /// Returns true if the key was changed
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.key))]
+    async fn compare_and_set(&self, ctx: &Context, arg: &CompareAndSetRequest) -> RpcResult<bool> {
+        let link = self.link(ctx).await?;
+        let mut cmd = redis::cmd("EVAL");
+        cmd.arg(COMPARE_AND_SET_SCRIPT)
+            .arg(1)
+            .arg(link.key(&arg.key))
+            .arg(if arg.expected.is_some() { "1" } else { "0" })
+            .arg(if arg.value.is_some() { "1" } else { "0" })
+            .arg(arg.value.as_deref().unwrap_or_default())
+            .arg(arg.expires.unwrap_or_default())
+            .arg(arg.expected.as_deref().unwrap_or_default());
+        let val: bool = link.exec(&cmd).await?;
+        Ok(val)
+    }
+
+    /// Returns a page of the keys that match the pattern, and the cursor for the next page
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, pattern = ?arg.pattern, cursor = ?arg.cursor))]
+    async fn list_keys(&self, ctx: &Context, arg: &ListKeysRequest) -> RpcResult<ListKeysResponse> {
+        if arg.count == Some(0) {
+            return Err(RpcError::InvalidParameter(
+                "count must be greater than zero".to_string(),
+            ));
+        }
+        let link = self.link(ctx).await?;
+        let pattern = link.key_pattern(arg.pattern.as_deref().unwrap_or("*"));
+        let (keys, cursor) = scan::scan(&link, &pattern, arg.cursor.as_deref(), arg.count).await?;
+        Ok(ListKeysResponse {
+            keys: keys.iter().map(|key| link.strip_key(key)).collect(),
+            cursor,
+        })
+    }
+
+    /// Deletes all keys that match the pattern. Returns the number of keys deleted
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, pattern = %arg.pattern))]
+    async fn delete_keys(&self, ctx: &Context, arg: &DeleteKeysRequest) -> RpcResult<u32> {
+        if arg.pattern.is_empty() {
+            return Err(RpcError::InvalidParameter(
+                "pattern may not be empty".to_string(),
+            ));
+        }
+        let link = self.link(ctx).await?;
+        scan::delete(&link, &link.key_pattern(&arg.pattern)).await
+    }
+
+    /// Returns the value of a field in a hash
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.key, field = %arg.field))]
+    async fn hash_get(&self, ctx: &Context, arg: &HashGetRequest) -> RpcResult<HashGetResponse> {
+        let link = self.link(ctx).await?;
+        let cmd = redis::Cmd::hget(link.key(&arg.key), &arg.field);
+        let val: Option<String> = link.exec(&cmd).await?;
+        Ok(HashGetResponse {
+            exists: val.is_some(),
+            value: val,
+        })
+    }
+
+    /// Sets fields in a hash. Returns the number of fields that were added
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.key))]
+    async fn hash_set(&self, ctx: &Context, arg: &HashSetRequest) -> RpcResult<u32> {
+        if arg.fields.is_empty() {
+            return Err(RpcError::InvalidParameter(
+                "hash_set requires at least one field".to_string(),
+            ));
+        }
+        let link = self.link(ctx).await?;
+        let fields: Vec<(&String, &String)> = arg.fields.iter().collect();
+        let mut cmd = redis::cmd("HSET");
+        cmd.arg(link.key(&arg.key)).arg(&fields);
+        let val: u32 = link.exec(&cmd).await?;
+        Ok(val)
+    }
+
+    /// Deletes fields from a hash. Returns the number of fields that were removed
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.key))]
+    async fn hash_del(&self, ctx: &Context, arg: &HashDelRequest) -> RpcResult<u32> {
+        if arg.fields.is_empty() {
+            return Err(RpcError::InvalidParameter(
+                "hash_del requires at least one field".to_string(),
+            ));
+        }
+        let link = self.link(ctx).await?;
+        let cmd = redis::Cmd::hdel(link.key(&arg.key), &arg.fields);
+        let val: u32 = link.exec(&cmd).await?;
+        Ok(val)
+    }
+
+    /// Returns all fields and values of a hash
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.to_string()))]
+    async fn hash_get_all<TS: ToString + ?Sized + Sync>(
+        &self,
+        ctx: &Context,
+        arg: &TS,
+    ) -> RpcResult<FieldMap> {
+        let link = self.link(ctx).await?;
+        let cmd = redis::Cmd::hgetall(link.key(&arg.to_string()));
+        let val: FieldMap = link.exec(&cmd).await?;
+        Ok(val)
+    }
+
+    /// Adds members to a sorted set, or updates their scores.
+    /// Returns the number of members that were added
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.key))]
+    async fn sorted_set_add(&self, ctx: &Context, arg: &SortedSetAddRequest) -> RpcResult<u32> {
+        if arg.members.is_empty() {
+            return Err(RpcError::InvalidParameter(
+                "sorted_set_add requires at least one member".to_string(),
+            ));
+        }
+        let link = self.link(ctx).await?;
+        let members: Vec<(f64, &String)> =
+            arg.members.iter().map(|m| (m.score, &m.member)).collect();
+        let cmd = redis::Cmd::zadd_multiple(link.key(&arg.key), &members);
+        let val: u32 = link.exec(&cmd).await?;
+        Ok(val)
+    }
+
+    /// Returns members of a sorted set by rank, with their scores
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.key))]
+    async fn sorted_set_range_by_rank(
+        &self,
+        ctx: &Context,
+        arg: &SortedSetRangeByRankRequest,
+    ) -> RpcResult<ScoredMembers> {
+        let link = self.link(ctx).await?;
+        let key = link.key(&arg.key);
+        let (start, stop) = (arg.start as isize, arg.stop as isize);
+        let cmd = if arg.reverse.unwrap_or_default() {
+            redis::Cmd::zrevrange_withscores(key, start, stop)
+        } else {
+            redis::Cmd::zrange_withscores(key, start, stop)
+        };
+        let val: Vec<(String, f64)> = link.exec(&cmd).await?;
+        Ok(scored_members(val))
+    }
+
+    /// Returns members of a sorted set by score, with their scores
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.key, min = %arg.min, max = %arg.max))]
+    async fn sorted_set_range_by_score(
+        &self,
+        ctx: &Context,
+        arg: &SortedSetRangeByScoreRequest,
+    ) -> RpcResult<ScoredMembers> {
+        let link = self.link(ctx).await?;
+        let key = link.key(&arg.key);
+        let reverse = arg.reverse.unwrap_or_default();
+        let cmd = match (arg.offset, arg.count) {
+            (None, None) if reverse => {
+                redis::Cmd::zrevrangebyscore_withscores(key, &arg.max, &arg.min)
+            }
+            (None, None) => redis::Cmd::zrangebyscore_withscores(key, &arg.min, &arg.max),
+            (offset, count) => {
+                let offset = offset.unwrap_or_default() as isize;
+                // a negative count returns all members after the offset
+                let count = count.map(|c| c as isize).unwrap_or(-1);
+                if reverse {
+                    redis::Cmd::zrevrangebyscore_limit_withscores(
+                        key, &arg.max, &arg.min, offset, count,
+                    )
+                } else {
+                    redis::Cmd::zrangebyscore_limit_withscores(
+                        key, &arg.min, &arg.max, offset, count,
+                    )
+                }
+            }
+        };
+        let val: Vec<(String, f64)> = link.exec(&cmd).await?;
+        Ok(scored_members(val))
+    }
+
+    /// Adds to the score of a member of a sorted set. Returns the new score
+    #[instrument(level = "debug", skip(self, ctx, arg), fields(actor_id = ?ctx.actor, key = %arg.key))]
+    async fn sorted_set_increment(
+        &self,
+        ctx: &Context,
+        arg: &SortedSetIncrementRequest,
+    ) -> RpcResult<f64> {
+        let link = self.link(ctx).await?;
+        let cmd = redis::Cmd::zincr(link.key(&arg.key), &arg.member, arg.delta);
+        let val: f64 = link.exec(&cmd).await?;
+        Ok(val)
+    }
+}
+
+fn scored_members(members: Vec<(String, f64)>) -> ScoredMembers {
+    members
+        .into_iter()
+        .map(|(member, score)| ScoredMember { member, score })
+        .collect()
+}
+
+/// Converts the reply from TTL, which is -2 if the key does not exist,
+/// or -1 if the key exists and has no expiration
+fn ttl_response(ttl: i64) -> TtlResponse {
+    match ttl {
+        -2 => TtlResponse {
+            exists: false,
+            ttl: None,
+        },
+        n if n < 0 => TtlResponse {
+            exists: true,
+            ttl: None,
+        },
+        n => TtlResponse {
+            exists: true,
+            ttl: Some(n as u32),
+        },
+    }
+}
+
 impl KvRedisProvider {
-    /// Helper function to execute redis async command while holding onto a mutable connection.
+    /// Returns the link for the actor in the request.
     ///
     /// This provider is multi-threaded, and requests from different actors use
     /// different connections, and requests can run in parallel.
     ///
-    /// There is a single connection per actor public key, and the write lock on the connection
-    /// effectively serializes redis operations for all instances of the same actor.
-    /// The lock is held only for the duration of a redis command from this provider
-    /// and waiting for its response. The lock duration does not overlap with
-    /// message passing between actors and this provider, including serialization
-    /// of requests and deserialization of responses, which are fully parallelizable.
-    ///
-    /// There is a read lock held on the actors hashtable, which does not interfere
-    /// with redis operations, but any control commands for new actor links
-    /// or removal of actor links may need to wait for in-progress operations to complete.
-    /// That should be rare, because most links are passed to the provider at startup.
-    async fn exec<T: FromRedisValue>(&self, ctx: &Context, cmd: &mut redis::Cmd) -> RpcResult<T> {
+    /// There is a read lock held on the actors hashtable only while the link is
+    /// found, so control commands for new actor links or removal of actor links
+    /// don't wait for in-progress operations to complete.
+    async fn link(&self, ctx: &Context) -> RpcResult<Arc<ActorLink>> {
         let actor_id = ctx
             .actor
             .as_ref()
             .ok_or_else(|| RpcError::InvalidParameter("no actor in request".to_string()))?;
         // get read lock on actor-connections hashmap
         let rd = self.actors.read().await;
-        let rc = rd
-            .get(actor_id)
-            .ok_or_else(|| RpcError::InvalidParameter(format!("No Redis connection found for {}. Please ensure the URL supplied in the link definition is a valid Redis URL", actor_id)))?;
-        // get write lock on this actor's connection
-        let mut con = rc.write().await;
-        cmd.query_async(con.deref_mut()).await.map_err(to_rpc_err)
+        let link = rd.get(actor_id).ok_or_else(|| {
+            RpcError::InvalidParameter(format!(
+                "No Redis link found for {}. Please ensure the actor is linked to this provider",
+                actor_id
+            ))
+        })?;
+        Ok(link.clone())
     }
 }
 
-fn get_redis_url(link_values: &HashMap<String, String>, default_connect_url: &str) -> String {
-    link_values
-        .iter()
-        .find(|(key, _value)| key.eq_ignore_ascii_case(REDIS_URL_KEY))
-        .map(|(_key, url)| url.to_owned())
-        .unwrap_or_else(|| default_connect_url.to_owned())
+/// Returns the members of each set, with a separate request per set. Used instead of
+/// a multi-key command when the sets are in different cluster hash slots
+async fn query_sets(link: &ActorLink, keys: &[String]) -> RpcResult<Vec<Vec<String>>> {
+    futures::future::try_join_all(
+        keys.iter()
+            .map(|key| async move { link.exec(&redis::Cmd::smembers(key)).await }),
+    )
+    .await
 }
 
-#[cfg(test)]
-mod test {
-    use std::collections::HashMap;
+/// Returns the members of all of the sets, without duplicates
+fn union(sets: Vec<Vec<String>>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    sets.into_iter()
+        .flatten()
+        .filter(|member| seen.insert(member.clone()))
+        .collect()
+}
 
-    use crate::{get_redis_url, KvRedisConfig};
+/// Returns the members that are in every one of the sets
+fn intersection(sets: Vec<Vec<String>>) -> Vec<String> {
+    let mut sets = sets.into_iter();
+    let first = match sets.next() {
+        Some(first) => first,
+        None => return Vec::new(),
+    };
+    let others: Vec<HashSet<String>> = sets.map(|set| set.into_iter().collect()).collect();
+    first
+        .into_iter()
+        .filter(|member| others.iter().all(|set| set.contains(member)))
+        .collect()
+}
 
-    const PROPER_URL: &str = "redis://127.0.0.1:6379";
+#[cfg(test)]
+mod test {
+    use crate::{intersection, ttl_response, union};
 
     #[test]
-    fn can_deserialize_config_case_insensitive() {
-        let lowercase_config = format!("{{\"url\": \"{}\"}}", PROPER_URL);
-        let uppercase_config = format!("{{\"URL\": \"{}\"}}", PROPER_URL);
-        let initial_caps_config = format!("{{\"Url\": \"{}\"}}", PROPER_URL);
-
-        assert_eq!(
-            PROPER_URL,
-            serde_json::from_str::<KvRedisConfig>(&lowercase_config)
-                .unwrap()
-                .url
-        );
-        assert_eq!(
-            PROPER_URL,
-            serde_json::from_str::<KvRedisConfig>(&uppercase_config)
-                .unwrap()
-                .url
-        );
-        assert_eq!(
-            PROPER_URL,
-            serde_json::from_str::<KvRedisConfig>(&initial_caps_config)
-                .unwrap()
-                .url
-        );
+    fn client_side_set_operations() {
+        let sets = vec![
+            vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            vec!["b".to_string(), "c".to_string(), "d".to_string()],
+            vec!["c".to_string(), "b".to_string()],
+        ];
+        assert_eq!(union(sets.clone()), vec!["a", "b", "c", "d"]);
+        assert_eq!(intersection(sets), vec!["b", "c"]);
+        assert!(intersection(Vec::new()).is_empty());
     }
 
     #[test]
-    fn can_accept_case_insensitive_url_parameters() {
-        let mut lowercase_map = HashMap::new();
-        lowercase_map.insert("url".to_string(), PROPER_URL.to_string());
-
-        assert_eq!(get_redis_url(&lowercase_map, ""), PROPER_URL);
-
-        let mut uppercase_map = HashMap::new();
-        uppercase_map.insert("URL".to_string(), PROPER_URL.to_string());
-
-        assert_eq!(get_redis_url(&uppercase_map, ""), PROPER_URL);
-
-        let mut spongebob_map_one = HashMap::new();
-        spongebob_map_one.insert("uRl".to_string(), PROPER_URL.to_string());
-
-        assert_eq!(get_redis_url(&spongebob_map_one, ""), PROPER_URL);
-
-        let mut spongebob_map_two = HashMap::new();
-        spongebob_map_two.insert("UrL".to_string(), PROPER_URL.to_string());
-
-        assert_eq!(get_redis_url(&spongebob_map_two, ""), PROPER_URL);
+    fn ttl_replies() {
+        let missing = ttl_response(-2);
+        assert!(!missing.exists);
+        assert_eq!(missing.ttl, None);
+
+        let persistent = ttl_response(-1);
+        assert!(persistent.exists);
+        assert_eq!(persistent.ttl, None);
+
+        let expiring = ttl_response(30);
+        assert!(expiring.exists);
+        assert_eq!(expiring.ttl, Some(30));
     }
 }
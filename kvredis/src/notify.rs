@@ -0,0 +1,383 @@
+//! Key change notifications
+//!
+//! A link can ask to be told when keys change, with `NOTIFY_KEYS`, a glob-style pattern
+//! of keys relative to the link's key prefix, and optionally `NOTIFY_EVENTS`, a
+//! comma-separated list of the keyspace events to send (default `set,del,expired`,
+//! or `*` for all events). The provider subscribes to the keyspace notification channels
+//! of the matching keys, and sends each event to the actor with
+//! `KeyChangeSubscriber.HandleKeyChange`.
+//!
+//! A link can also subscribe to messages published on channels matching `NOTIFY_CHANNELS`,
+//! so that applications can publish their own invalidation messages. Like key patterns,
+//! the channel pattern is relative to the link's key prefix, and channel names are sent
+//! to the actor without the prefix.
+//!
+//! Keyspace notifications must be enabled on the server with the `notify-keyspace-events`
+//! setting, for example `K$gx` for string commands, generic commands such as DEL, and
+//! expirations. Redis does not queue notifications: events that happen while the
+//! subscription is reconnecting are lost.
+
+use std::collections::HashMap;
+
+use futures::StreamExt;
+use redis::{aio::PubSub, Msg, RedisResult};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use wasmbus_rpc::{
+    core::LinkDefinition,
+    error::{RpcError, RpcResult},
+    provider::prelude::Context,
+};
+
+use crate::wasmcloud_interface_kvredis::{
+    KeyChange, KeyChangeSubscriber, KeyChangeSubscriberSender,
+};
+use wasmcloud_provider_kvredis::link::{retry_delay, ActorLink};
+use wasmcloud_provider_kvredis::options::ConnectionOptions;
+use wasmcloud_provider_kvredis::pool::{primary_client, Topology};
+
+const NOTIFY_KEYS_KEY: &str = "NOTIFY_KEYS";
+const NOTIFY_EVENTS_KEY: &str = "NOTIFY_EVENTS";
+const NOTIFY_CHANNELS_KEY: &str = "NOTIFY_CHANNELS";
+
+/// Events sent when the link has no `NOTIFY_EVENTS` value
+const DEFAULT_EVENTS: &[&str] = &["set", "del", "expired"];
+
+/// Event name for messages published on a subscribed channel
+const MESSAGE_EVENT: &str = "message";
+
+/// Notification settings from link values
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct NotifyConfig {
+    /// pattern of keys to watch, without the key prefix
+    keys: Option<String>,
+    /// keyspace events to send, or None for all events
+    events: Option<Vec<String>>,
+    /// pattern of channels to subscribe to, without the key prefix
+    channels: Option<String>,
+}
+
+impl NotifyConfig {
+    /// Reads notification settings from link values (case-insensitive).
+    /// Returns None if the link doesn't ask for notifications
+    pub(crate) fn from_link(
+        values: &HashMap<String, String>,
+        topology: &Topology,
+    ) -> RpcResult<Option<Self>> {
+        let find = |name: &str| {
+            values
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        let keys = find(NOTIFY_KEYS_KEY);
+        let channels = find(NOTIFY_CHANNELS_KEY);
+        let events = find(NOTIFY_EVENTS_KEY);
+        if keys.is_none() && channels.is_none() {
+            if events.is_some() {
+                return Err(RpcError::InvalidParameter(format!(
+                    "{} requires {}",
+                    NOTIFY_EVENTS_KEY, NOTIFY_KEYS_KEY
+                )));
+            }
+            return Ok(None);
+        }
+        if matches!(topology, Topology::Cluster(_)) {
+            return Err(RpcError::InvalidParameter(format!(
+                "{} and {} are not supported with a Redis Cluster",
+                NOTIFY_KEYS_KEY, NOTIFY_CHANNELS_KEY
+            )));
+        }
+        let events = match events.as_deref() {
+            Some("*") => None,
+            Some(list) => Some(
+                list.split(',')
+                    .map(|e| e.trim().to_ascii_lowercase())
+                    .filter(|e| !e.is_empty())
+                    .collect(),
+            ),
+            None => Some(DEFAULT_EVENTS.iter().map(|e| e.to_string()).collect()),
+        };
+        Ok(Some(NotifyConfig {
+            keys,
+            events,
+            channels,
+        }))
+    }
+}
+
+/// Starts a task that subscribes to the link's notifications and sends them to the actor.
+/// The subscription is reopened, with backoff, if it fails or the connection is lost
+pub(crate) fn spawn(
+    ld: LinkDefinition,
+    topology: Topology,
+    options: ConnectionOptions,
+    link: &ActorLink,
+    config: NotifyConfig,
+) -> JoinHandle<()> {
+    let notifier = Notifier {
+        ld,
+        topology,
+        options,
+        key_prefix: link.key_prefix().to_string(),
+        keys: config.keys.as_deref().map(|keys| link.key_pattern(keys)),
+        channels: config
+            .channels
+            .as_deref()
+            .map(|channels| link.key_pattern(channels)),
+        events: config.events,
+    };
+    tokio::spawn(notifier.run())
+}
+
+struct Notifier {
+    ld: LinkDefinition,
+    topology: Topology,
+    options: ConnectionOptions,
+    key_prefix: String,
+    /// pattern of keys to watch, with the key prefix
+    keys: Option<String>,
+    /// pattern of channels to subscribe to, with the key prefix
+    channels: Option<String>,
+    events: Option<Vec<String>>,
+}
+
+impl Notifier {
+    async fn run(self) {
+        let mut failures = 0;
+        loop {
+            match self.subscribe().await {
+                Ok((pubsub, keyspace)) => {
+                    failures = 0;
+                    info!(actor_id = %self.ld.actor_id, "subscribed to redis notifications");
+                    let mut messages = pubsub.into_on_message();
+                    while let Some(msg) = messages.next().await {
+                        self.dispatch(&keyspace, msg).await;
+                    }
+                    warn!(actor_id = %self.ld.actor_id, "redis notification subscription closed");
+                }
+                Err(e) => {
+                    failures += 1;
+                    warn!(
+                        actor_id = %self.ld.actor_id,
+                        error = %e,
+                        attempts = failures,
+                        "unable to subscribe to redis notifications"
+                    );
+                }
+            }
+            tokio::time::sleep(retry_delay(failures.max(1))).await;
+        }
+    }
+
+    /// Opens a connection and subscribes. Returns the subscription, and the
+    /// prefix of the keyspace channels of the link's keys
+    async fn subscribe(&self) -> RedisResult<(PubSub, String)> {
+        let client = primary_client(&self.topology, &self.options).await?;
+        let db = client.get_connection_info().redis.db;
+        if self.keys.is_some() {
+            self.check_server_config(&client).await;
+        }
+        let mut pubsub = client.get_async_connection().await?.into_pubsub();
+        if let Some(keys) = &self.keys {
+            pubsub
+                .psubscribe(format!("__keyspace@{}__:{}", db, keys))
+                .await?;
+        }
+        if let Some(channels) = &self.channels {
+            pubsub.psubscribe(channels).await?;
+        }
+        let keyspace = format!("__keyspace@{}__:{}", db, self.key_prefix);
+        Ok((pubsub, keyspace))
+    }
+
+    /// Logs a warning if keyspace notifications are disabled on the server.
+    /// Servers that don't allow CONFIG are not checked
+    async fn check_server_config(&self, client: &redis::Client) {
+        let setting: RedisResult<(String, String)> = async {
+            let mut con = client.get_async_connection().await?;
+            redis::cmd("CONFIG")
+                .arg("GET")
+                .arg("notify-keyspace-events")
+                .query_async(&mut con)
+                .await
+        }
+        .await;
+        if let Ok((_, flags)) = setting {
+            if !flags.contains('K') {
+                warn!(
+                    actor_id = %self.ld.actor_id,
+                    notify_keyspace_events = %flags,
+                    "keyspace notifications are not enabled on the redis server. \
+                     Set notify-keyspace-events, for example to K$gx"
+                );
+            }
+        }
+    }
+
+    async fn dispatch(&self, keyspace: &str, msg: Msg) {
+        let payload = match msg.get_payload::<String>() {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!(actor_id = %self.ld.actor_id, error = %e, "invalid notification payload");
+                return;
+            }
+        };
+        let change = match key_change(
+            keyspace,
+            &self.key_prefix,
+            self.events.as_deref(),
+            msg.get_channel_name(),
+            payload,
+        ) {
+            Some(change) => change,
+            None => return,
+        };
+        debug!(actor_id = %self.ld.actor_id, event = %change.event, key = %change.key, "key change");
+        let actor = KeyChangeSubscriberSender::for_actor(&self.ld);
+        if let Err(e) = actor.handle_key_change(&Context::default(), &change).await {
+            error!(actor_id = %self.ld.actor_id, error = %e, "unable to send key change to actor");
+        }
+    }
+}
+
+/// Converts a message into the change sent to the actor. `keyspace` is the prefix of
+/// keyspace channels for the link's keys, and `key_prefix` the prefix of the link's
+/// channels. Returns None for events the link doesn't want
+fn key_change(
+    keyspace: &str,
+    key_prefix: &str,
+    events: Option<&[String]>,
+    channel: &str,
+    payload: String,
+) -> Option<KeyChange> {
+    match channel.strip_prefix(keyspace) {
+        Some(key) => {
+            if let Some(events) = events {
+                if !events.contains(&payload) {
+                    return None;
+                }
+            }
+            Some(KeyChange {
+                event: payload,
+                key: key.to_string(),
+                message: None,
+            })
+        }
+        None => Some(KeyChange {
+            event: MESSAGE_EVENT.to_string(),
+            key: channel.strip_prefix(key_prefix)?.to_string(),
+            message: Some(payload),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn single() -> Topology {
+        Topology::Single("redis://127.0.0.1:6379/".to_string())
+    }
+
+    #[test]
+    fn config_from_link() {
+        assert_eq!(
+            NotifyConfig::from_link(&values(&[]), &single()).unwrap(),
+            None
+        );
+
+        let config = NotifyConfig::from_link(&values(&[("notify_keys", "cache:*")]), &single())
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.keys.as_deref(), Some("cache:*"));
+        assert_eq!(
+            config.events,
+            Some(vec!["set".into(), "del".into(), "expired".into()])
+        );
+
+        let config = NotifyConfig::from_link(
+            &values(&[("NOTIFY_KEYS", "*"), ("NOTIFY_EVENTS", "*")]),
+            &single(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(config.events, None);
+
+        // events without keys
+        assert!(NotifyConfig::from_link(&values(&[("NOTIFY_EVENTS", "set")]), &single()).is_err());
+        // cluster
+        assert!(NotifyConfig::from_link(
+            &values(&[("NOTIFY_CHANNELS", "invalidate.*")]),
+            &Topology::Cluster(vec!["redis://a:7000".into()])
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn convert_messages() {
+        let keyspace = "__keyspace@0__:team1:";
+        let prefix = "team1:";
+        let events = vec!["set".to_string(), "del".to_string()];
+
+        let change = key_change(
+            keyspace,
+            prefix,
+            Some(&events),
+            "__keyspace@0__:team1:user.1",
+            "set".into(),
+        )
+        .unwrap();
+        assert_eq!(change.event, "set");
+        assert_eq!(change.key, "user.1");
+        assert_eq!(change.message, None);
+
+        // filtered out
+        assert!(key_change(
+            keyspace,
+            prefix,
+            Some(&events),
+            "__keyspace@0__:team1:user.1",
+            "expire".into()
+        )
+        .is_none());
+        assert!(key_change(
+            keyspace,
+            prefix,
+            None,
+            "__keyspace@0__:team1:x",
+            "expire".into()
+        )
+        .is_some());
+
+        let change = key_change(
+            keyspace,
+            prefix,
+            Some(&events),
+            "team1:invalidate.users",
+            "1".into(),
+        )
+        .unwrap();
+        assert_eq!(change.event, "message");
+        assert_eq!(change.key, "invalidate.users");
+        assert_eq!(change.message.as_deref(), Some("1"));
+
+        // channels of other prefixes are not sent
+        assert!(key_change(
+            keyspace,
+            prefix,
+            Some(&events),
+            "team2:invalidate.users",
+            "1".into()
+        )
+        .is_none());
+    }
+}
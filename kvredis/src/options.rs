@@ -0,0 +1,403 @@
+//! TLS and authentication settings for redis connections
+//!
+//! Settings may be provided in the provider's config_json, as defaults for all links,
+//! and in link values, which override the defaults for one link:
+//!
+//! | link value | config_json | |
+//! | :--- | :--- | :--- |
+//! | `TLS_CA`   | `tls_ca`   | CA certificates (PEM) used to verify the server, instead of the built-in roots |
+//! | `TLS_CERT` | `tls_cert` | client certificate chain (PEM), for servers that require client authentication |
+//! | `TLS_KEY`  | `tls_key`  | private key (PEM) of the client certificate |
+//! | `USERNAME` | `username` | ACL username |
+//! | `PASSWORD` | `password` | password for the ACL user, or the `requirepass` password |
+//! | `DB`       | `db`       | database index |
+//!
+//! Certificate and key values are either a path to a PEM file, or the PEM text itself.
+//! Files are read, and all settings are checked, when the link is put, so that
+//! a misconfigured link is rejected instead of failing later on its first request.
+
+use std::collections::HashMap;
+
+use redis::{
+    cluster::{ClusterClient, ClusterClientBuilder},
+    sentinel::{Sentinel, SentinelNodeConnectionInfo},
+    Client, ClientTlsConfig, ConnectionAddr, ConnectionInfo, IntoConnectionInfo,
+    RedisConnectionInfo, RedisResult, TlsCertificates, TlsMode,
+};
+use serde::Deserialize;
+use wasmbus_rpc::error::{RpcError, RpcResult};
+
+use crate::pool::Topology;
+
+const TLS_CA_KEY: &str = "TLS_CA";
+const TLS_CERT_KEY: &str = "TLS_CERT";
+const TLS_KEY_KEY: &str = "TLS_KEY";
+const USERNAME_KEY: &str = "USERNAME";
+const PASSWORD_KEY: &str = "PASSWORD";
+const DB_KEY: &str = "DB";
+
+/// TLS and authentication settings, as configured
+#[derive(Clone, Default, Deserialize)]
+pub struct ConnectionConfig {
+    #[serde(default, alias = "TLS_CA")]
+    pub tls_ca: Option<String>,
+    #[serde(default, alias = "TLS_CERT")]
+    pub tls_cert: Option<String>,
+    #[serde(default, alias = "TLS_KEY")]
+    pub tls_key: Option<String>,
+    #[serde(default, alias = "USERNAME")]
+    pub username: Option<String>,
+    #[serde(default, alias = "PASSWORD")]
+    pub password: Option<String>,
+    #[serde(default, alias = "DB")]
+    pub db: Option<i64>,
+}
+
+impl ConnectionConfig {
+    /// Returns these settings, overridden by any settings in the link values (case-insensitive)
+    pub fn with_link_values(&self, values: &HashMap<String, String>) -> RpcResult<Self> {
+        let mut config = self.clone();
+        for (key, value) in values.iter() {
+            match key.to_ascii_uppercase().as_str() {
+                TLS_CA_KEY => config.tls_ca = Some(value.clone()),
+                TLS_CERT_KEY => config.tls_cert = Some(value.clone()),
+                TLS_KEY_KEY => config.tls_key = Some(value.clone()),
+                USERNAME_KEY => config.username = Some(value.clone()),
+                PASSWORD_KEY => config.password = Some(value.clone()),
+                DB_KEY => config.db = Some(parse_db(value)?),
+                _ => {}
+            }
+        }
+        Ok(config)
+    }
+
+    /// Reads certificates, and checks that the settings can be used with the topology
+    pub fn load(&self, topology: &Topology) -> RpcResult<ConnectionOptions> {
+        let tls = match (&self.tls_ca, &self.tls_cert, &self.tls_key) {
+            (None, None, None) => None,
+            (ca, cert, key) => {
+                let client_tls = match (cert, key) {
+                    (Some(cert), Some(key)) => Some(ClientTlsConfig {
+                        client_cert: read_pem(TLS_CERT_KEY, cert)?,
+                        client_key: read_pem(TLS_KEY_KEY, key)?,
+                    }),
+                    (None, None) => None,
+                    _ => {
+                        return Err(invalid(format!(
+                            "{} and {} must be used together",
+                            TLS_CERT_KEY, TLS_KEY_KEY
+                        )))
+                    }
+                };
+                let root_cert = match ca {
+                    Some(ca) => Some(read_pem(TLS_CA_KEY, ca)?),
+                    None => None,
+                };
+                Some(TlsCertificates {
+                    client_tls,
+                    root_cert,
+                })
+            }
+        };
+        if let Some(db) = self.db {
+            if db < 0 {
+                return Err(invalid(format!("{} must not be negative", DB_KEY)));
+            }
+            if db != 0 && matches!(topology, Topology::Cluster(_)) {
+                return Err(invalid(format!("{} must be 0 for a Redis Cluster", DB_KEY)));
+            }
+        }
+        if tls.is_some() && matches!(topology, Topology::Sentinel { .. }) {
+            return Err(invalid(format!(
+                "{}, {} and {} are not supported with sentinels",
+                TLS_CA_KEY, TLS_CERT_KEY, TLS_KEY_KEY
+            )));
+        }
+        let options = ConnectionOptions {
+            username: self.username.clone(),
+            password: self.password.clone(),
+            db: self.db,
+            tls,
+        };
+        // build clients without connecting, to check urls and certificates
+        let checked = match topology {
+            Topology::Single(url) => options.client(url).map(|_| ()),
+            Topology::Cluster(urls) => options.cluster_client(urls).map(|_| ()),
+            Topology::Sentinel { urls, .. } => options.sentinel(urls).map(|_| ()),
+        };
+        checked.map_err(|e| invalid(e.to_string()))?;
+        Ok(options)
+    }
+}
+
+/// TLS and authentication settings, checked and ready to use
+#[derive(Clone, Default)]
+pub struct ConnectionOptions {
+    username: Option<String>,
+    password: Option<String>,
+    db: Option<i64>,
+    tls: Option<TlsCertificates>,
+}
+
+impl ConnectionOptions {
+    /// Returns a client for a single server
+    pub fn client(&self, url: &str) -> RedisResult<Client> {
+        let mut info = url.into_connection_info()?;
+        self.apply(&mut info.redis);
+        match &self.tls {
+            Some(certs) => Client::build_with_tls(info, certs.clone()),
+            None => Client::open(info),
+        }
+    }
+
+    /// Returns a client for a Redis Cluster
+    pub fn cluster_client(&self, urls: &[String]) -> RedisResult<ClusterClient> {
+        let mut builder = ClusterClientBuilder::new(urls.iter().map(String::as_str));
+        if let Some(username) = &self.username {
+            builder = builder.username(username.clone());
+        }
+        if let Some(password) = &self.password {
+            builder = builder.password(password.clone());
+        }
+        if let Some(certs) = &self.tls {
+            builder = builder.certs(certs.clone());
+        }
+        builder.build()
+    }
+
+    /// Returns a sentinel client
+    pub fn sentinel(&self, urls: &[String]) -> RedisResult<Sentinel> {
+        Sentinel::build(urls.iter().map(String::as_str).collect())
+    }
+
+    /// Connection settings for the primary found by the sentinels. The primary uses
+    /// TLS if the sentinels do
+    pub fn sentinel_node_info(&self, urls: &[String]) -> SentinelNodeConnectionInfo {
+        let tls_mode = urls
+            .first()
+            .and_then(|url| url.as_str().into_connection_info().ok())
+            .and_then(|info: ConnectionInfo| match info.addr {
+                ConnectionAddr::TcpTls { insecure, .. } => Some(if insecure {
+                    TlsMode::Insecure
+                } else {
+                    TlsMode::Secure
+                }),
+                _ => None,
+            });
+        let mut redis = RedisConnectionInfo::default();
+        self.apply(&mut redis);
+        SentinelNodeConnectionInfo {
+            tls_mode,
+            redis_connection_info: Some(redis),
+        }
+    }
+
+    /// Overrides the settings from the url with any configured settings
+    fn apply(&self, info: &mut RedisConnectionInfo) {
+        if let Some(username) = &self.username {
+            info.username = Some(username.clone());
+        }
+        if let Some(password) = &self.password {
+            info.password = Some(password.clone());
+        }
+        if let Some(db) = self.db {
+            info.db = db;
+        }
+    }
+}
+
+/// Returns the PEM text of a setting, which is either the PEM text, or a path to a PEM file
+fn read_pem(name: &str, value: &str) -> RpcResult<Vec<u8>> {
+    let pem = if value.trim_start().starts_with("-----BEGIN") {
+        value.as_bytes().to_vec()
+    } else {
+        std::fs::read(value)
+            .map_err(|e| invalid(format!("unable to read {} file '{}': {}", name, value, e)))?
+    };
+    if !String::from_utf8_lossy(&pem).contains("-----BEGIN") {
+        return Err(invalid(format!("{} is not in PEM format", name)));
+    }
+    Ok(pem)
+}
+
+fn parse_db(value: &str) -> RpcResult<i64> {
+    value
+        .trim()
+        .parse::<i64>()
+        .map_err(|_| invalid(format!("invalid {} '{}'", DB_KEY, value)))
+}
+
+fn invalid(msg: String) -> RpcError {
+    RpcError::InvalidParameter(msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const URL: &str = "redis://127.0.0.1:6379";
+    const TLS_URL: &str = "rediss://127.0.0.1:6380";
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn link_values_override_config() {
+        let config = ConnectionConfig {
+            username: Some("default-user".into()),
+            db: Some(1),
+            ..Default::default()
+        };
+        let link = config
+            .with_link_values(&values(&[("username", "app"), ("Password", "secret")]))
+            .unwrap();
+        assert_eq!(link.username.as_deref(), Some("app"));
+        assert_eq!(link.password.as_deref(), Some("secret"));
+        assert_eq!(link.db, Some(1));
+
+        assert!(config.with_link_values(&values(&[("DB", "one")])).is_err());
+    }
+
+    #[test]
+    fn config_from_json() {
+        let config: ConnectionConfig =
+            serde_json::from_str(r#"{"username": "app", "db": 2, "TLS_CA": "/etc/ca.pem"}"#)
+                .unwrap();
+        assert_eq!(config.username.as_deref(), Some("app"));
+        assert_eq!(config.db, Some(2));
+        assert_eq!(config.tls_ca.as_deref(), Some("/etc/ca.pem"));
+    }
+
+    #[test]
+    fn invalid_settings() {
+        let single = Topology::Single(URL.to_string());
+        let tls = Topology::Single(TLS_URL.to_string());
+        let cluster = Topology::Cluster(vec![URL.to_string()]);
+
+        // no settings
+        assert!(ConnectionConfig::default().load(&single).is_ok());
+
+        // negative db, or db on a cluster
+        let config = ConnectionConfig {
+            db: Some(-1),
+            ..Default::default()
+        };
+        assert!(config.load(&single).is_err());
+        let config = ConnectionConfig {
+            db: Some(3),
+            ..Default::default()
+        };
+        assert!(config.load(&single).is_ok());
+        assert!(config.load(&cluster).is_err());
+
+        // certificate without key
+        let config = ConnectionConfig {
+            tls_cert: Some("-----BEGIN CERTIFICATE-----".into()),
+            ..Default::default()
+        };
+        assert!(config.load(&tls).is_err());
+
+        // missing file, or not PEM
+        let config = ConnectionConfig {
+            tls_ca: Some("/nonexistent/ca.pem".into()),
+            ..Default::default()
+        };
+        let err = config.load(&tls).err().unwrap();
+        assert!(err.to_string().contains("/nonexistent/ca.pem"), "{}", err);
+        let config = ConnectionConfig {
+            tls_ca: Some("Cargo.toml".into()),
+            ..Default::default()
+        };
+        assert!(config.load(&tls).is_err());
+
+        // certificates with a url that doesn't use tls
+        let config = ConnectionConfig {
+            tls_ca: Some("-----BEGIN CERTIFICATE-----\n-----END CERTIFICATE-----\n".into()),
+            ..Default::default()
+        };
+        assert!(config.load(&single).is_err());
+    }
+
+    /// Connects to the TLS-enabled redis-server started by `make test-tls`, which requires
+    /// client certificates and has an ACL user. Skipped unless KVREDIS_TLS_DIR is set
+    #[tokio::test]
+    async fn tls_server() {
+        let dir = match std::env::var("KVREDIS_TLS_DIR") {
+            Ok(dir) => dir,
+            Err(_) => {
+                eprintln!("skipping tls_server test: KVREDIS_TLS_DIR is not set");
+                return;
+            }
+        };
+        let url = std::env::var("KVREDIS_TLS_URL")
+            .unwrap_or_else(|_| "rediss://localhost:6380".to_string());
+        let topology = Topology::Single(url);
+        let config = ConnectionConfig {
+            tls_ca: Some(format!("{}/ca.crt", dir)),
+            tls_cert: Some(format!("{}/client.crt", dir)),
+            tls_key: Some(format!("{}/client.key", dir)),
+            username: Some("kvtest".to_string()),
+            password: Some("kvtest-password".to_string()),
+            db: Some(2),
+        };
+        let connect = |config: &ConnectionConfig| {
+            crate::link::ActorLink::new(
+                "tls_test",
+                topology.clone(),
+                config.load(&topology).unwrap(),
+                1,
+                String::new(),
+            )
+        };
+
+        let link = connect(&config);
+        let user: String = link
+            .exec(&redis::cmd("ACL").arg("WHOAMI").to_owned())
+            .await
+            .unwrap();
+        assert_eq!(user, "kvtest");
+        let info: String = link
+            .exec(&redis::cmd("CLIENT").arg("INFO").to_owned())
+            .await
+            .unwrap();
+        assert!(info.contains(" db=2 "), "{}", info);
+        let _: () = link
+            .exec(&redis::Cmd::set("tls_test", "secure"))
+            .await
+            .unwrap();
+        let value: String = link.exec(&redis::Cmd::get("tls_test")).await.unwrap();
+        assert_eq!(value, "secure");
+        let _: () = link.exec(&redis::Cmd::del("tls_test")).await.unwrap();
+
+        // inline PEM works the same as files
+        let inline = ConnectionConfig {
+            tls_ca: Some(std::fs::read_to_string(format!("{}/ca.crt", dir)).unwrap()),
+            ..config.clone()
+        };
+        let link = connect(&inline);
+        let pong: String = link.exec(&redis::cmd("PING")).await.unwrap();
+        assert_eq!(pong, "PONG");
+
+        // the server requires a client certificate
+        let no_client_cert = ConnectionConfig {
+            tls_cert: None,
+            tls_key: None,
+            ..config.clone()
+        };
+        let link = connect(&no_client_cert);
+        assert!(link.exec::<String>(&redis::cmd("PING")).await.is_err());
+
+        // wrong password
+        let wrong_password = ConnectionConfig {
+            password: Some("wrong".to_string()),
+            ..config.clone()
+        };
+        let link = connect(&wrong_password);
+        let err = link.exec::<String>(&redis::cmd("PING")).await.unwrap_err();
+        assert!(err.to_string().contains("unable to connect"), "{}", err);
+    }
+}
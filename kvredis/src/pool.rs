@@ -0,0 +1,408 @@
+//! Pool of multiplexed redis connections for one link
+//!
+//! Each connection is a [ConnectionManager] for a single server, or a cluster connection,
+//! which multiplexes requests from any number of callers and reconnects if the connection
+//! is lost. Cloning a connection is cheap, so callers take a clone of the next connection in
+//! the pool and don't hold any lock while a command is in progress. Requests are spread
+//! round-robin over the connections in the pool, so a busy actor is not limited to the
+//! throughput of a single socket.
+//!
+//! A link connects to one of:
+//! - a single server, with the `URL` link value
+//! - a Redis Cluster, with `CLUSTER_URLS`, a comma-separated list of one or more cluster nodes.
+//!   Commands are routed to the node that owns the hash slot of their key
+//! - the primary of a Sentinel-managed deployment, with `SENTINEL_URLS`, a comma-separated list
+//!   of sentinels, and `SENTINEL_MASTER`, the name of the monitored primary. The primary is
+//!   resolved again if it stops accepting requests, for example after a failover. Only one
+//!   reconnect runs at a time, and requests that fail while it runs wait for it instead of
+//!   starting another
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use redis::{
+    aio::{ConnectionLike, ConnectionManager},
+    cluster_async::ClusterConnection,
+    cluster_routing::{get_slot, Route, RoutingInfo, SingleNodeRoutingInfo, SlotAddr},
+    from_redis_value, Client, Cmd, ErrorKind, FromRedisValue, IntoConnectionInfo, Pipeline,
+    RedisError, RedisFuture, RedisResult, Value,
+};
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+use wasmbus_rpc::error::{RpcError, RpcResult};
+
+use crate::options::ConnectionOptions;
+
+/// Number of connections opened for each link when no pool size is configured
+pub const DEFAULT_POOL_SIZE: usize = 4;
+
+/// Largest pool size accepted in link values or config
+pub const MAX_POOL_SIZE: usize = 64;
+
+const CLUSTER_URLS_KEY: &str = "CLUSTER_URLS";
+const SENTINEL_URLS_KEY: &str = "SENTINEL_URLS";
+const SENTINEL_MASTER_KEY: &str = "SENTINEL_MASTER";
+
+/// Shortest time between attempts to resolve the primary again after a failure
+const MIN_RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The redis deployment a link connects to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Topology {
+    /// a single server
+    Single(String),
+    /// a Redis Cluster, with the urls of one or more of its nodes
+    Cluster(Vec<String>),
+    /// the primary named `master`, discovered from the sentinels at `urls`
+    Sentinel { urls: Vec<String>, master: String },
+}
+
+impl Topology {
+    /// Reads the topology from link values (case-insensitive). `CLUSTER_URLS` and
+    /// `SENTINEL_URLS` take priority over `url`, which is the link's `URL` or the default url
+    pub fn from_link(values: &HashMap<String, String>, url: String) -> RpcResult<Self> {
+        let cluster = find_value(values, CLUSTER_URLS_KEY).map(split_urls);
+        let sentinel = find_value(values, SENTINEL_URLS_KEY).map(split_urls);
+        match (cluster, sentinel) {
+            (Some(_), Some(_)) => Err(RpcError::InvalidParameter(format!(
+                "link may not have both {} and {}",
+                CLUSTER_URLS_KEY, SENTINEL_URLS_KEY
+            ))),
+            (Some(urls), None) if urls.is_empty() => Err(RpcError::InvalidParameter(format!(
+                "{} must contain at least one url",
+                CLUSTER_URLS_KEY
+            ))),
+            (Some(urls), None) => Ok(Topology::Cluster(urls)),
+            (None, Some(urls)) if urls.is_empty() => Err(RpcError::InvalidParameter(format!(
+                "{} must contain at least one url",
+                SENTINEL_URLS_KEY
+            ))),
+            (None, Some(urls)) => {
+                let master = find_value(values, SENTINEL_MASTER_KEY)
+                    .map(|name| name.trim().to_string())
+                    .filter(|name| !name.is_empty())
+                    .ok_or_else(|| {
+                        RpcError::InvalidParameter(format!(
+                            "{} is required with {}",
+                            SENTINEL_MASTER_KEY, SENTINEL_URLS_KEY
+                        ))
+                    })?;
+                Ok(Topology::Sentinel { urls, master })
+            }
+            (None, None) => Ok(Topology::Single(url)),
+        }
+    }
+
+    /// Checks that all urls can be parsed, without connecting
+    pub fn validate(&self) -> RpcResult<()> {
+        let urls = match self {
+            Topology::Single(url) => std::slice::from_ref(url),
+            Topology::Cluster(urls) | Topology::Sentinel { urls, .. } => urls.as_slice(),
+        };
+        for url in urls {
+            if let Err(e) = url.as_str().into_connection_info() {
+                return Err(RpcError::InvalidParameter(format!(
+                    "invalid redis url '{}': {}",
+                    url, e
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A connection to a single server or a cluster
+// not boxed, because a connection is cloned for every request
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+pub enum Connection {
+    Single(ConnectionManager),
+    Cluster(ClusterConnection),
+}
+
+impl ConnectionLike for Connection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        match self {
+            Connection::Single(con) => con.req_packed_command(cmd),
+            Connection::Cluster(con) => con.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match self {
+            Connection::Single(con) => con.req_packed_commands(cmd, offset, count),
+            Connection::Cluster(con) => con.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            Connection::Single(con) => con.get_db(),
+            Connection::Cluster(con) => con.get_db(),
+        }
+    }
+}
+
+impl Connection {
+    /// Sends a command to the cluster node that owns the hash slot.
+    /// A connection to a single server ignores the slot
+    pub async fn query_on_slot<T: FromRedisValue>(
+        &mut self,
+        cmd: &Cmd,
+        slot: u16,
+    ) -> RedisResult<T> {
+        match self {
+            Connection::Single(con) => cmd.query_async(con).await,
+            Connection::Cluster(con) => {
+                let route = Route::new(slot, SlotAddr::Master);
+                let routing = RoutingInfo::SingleNode(SingleNodeRoutingInfo::SpecificNode(route));
+                from_redis_value(&con.route_command(cmd, routing).await?)
+            }
+        }
+    }
+}
+
+pub struct ConnectionPool {
+    topology: Topology,
+    options: ConnectionOptions,
+    connections: RwLock<Vec<Connection>>,
+    next: AtomicUsize,
+    /// incremented after each attempt to reconnect
+    generation: AtomicU64,
+    /// held while reconnecting, with the time of the last attempt
+    reconnect: Mutex<Option<Instant>>,
+}
+
+impl ConnectionPool {
+    /// Opens `size` connections. Fails if any connection can't be opened
+    pub async fn connect(
+        topology: Topology,
+        options: ConnectionOptions,
+        size: usize,
+    ) -> RedisResult<Self> {
+        let connections = open(&topology, &options, size.max(1)).await?;
+        Ok(ConnectionPool {
+            topology,
+            options,
+            connections: RwLock::new(connections),
+            next: AtomicUsize::new(0),
+            generation: AtomicU64::new(0),
+            reconnect: Mutex::new(None),
+        })
+    }
+
+    /// Returns the next connection in the pool
+    pub fn get(&self) -> Connection {
+        let n = self.next.fetch_add(1, Ordering::Relaxed);
+        let connections = self.connections.read().unwrap();
+        connections[n % connections.len()].clone()
+    }
+
+    /// Number of connections in the pool
+    pub fn size(&self) -> usize {
+        self.connections.read().unwrap().len()
+    }
+
+    /// Number of reconnect attempts so far. Callers read it before sending a request,
+    /// and pass it to [ConnectionPool::check_failover] if the request fails
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Called after a request fails. If the link uses sentinels and the error shows that
+    /// the primary is unreachable or has become a replica, the primary is resolved again
+    /// and the pool reconnects to it, so later requests go to the new primary.
+    /// `generation` is the value of [ConnectionPool::generation] when the request was sent:
+    /// if the pool has reconnected since then, or is reconnecting now, the caller waits for
+    /// that attempt instead of starting another. Attempts are at least
+    /// `MIN_RECONNECT_INTERVAL` apart.
+    pub async fn check_failover(&self, error: &RedisError, generation: u64) {
+        if !matches!(self.topology, Topology::Sentinel { .. })
+            || !(error.is_io_error()
+                || error.is_connection_dropped()
+                || error.kind() == ErrorKind::ReadOnly)
+        {
+            return;
+        }
+        let mut last_attempt = self.reconnect.lock().await;
+        if self.generation() != generation {
+            debug!("redis primary already resolved again after the request failed");
+            return;
+        }
+        if matches!(*last_attempt, Some(last) if last.elapsed() < MIN_RECONNECT_INTERVAL) {
+            return;
+        }
+        let size = self.size();
+        match open(&self.topology, &self.options, size).await {
+            Ok(connections) => {
+                info!("reconnected to redis primary after failover");
+                *self.connections.write().unwrap() = connections;
+            }
+            Err(e) => warn!(error = %e, "unable to resolve redis primary from sentinels"),
+        }
+        *last_attempt = Some(Instant::now());
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+/// Returns true if all keys are in the same cluster hash slot,
+/// so they can be used together in one multi-key command
+pub fn same_slot(keys: &[String]) -> bool {
+    let mut slots = keys.iter().map(|k| get_slot(k.as_bytes()));
+    match slots.next() {
+        Some(first) => slots.all(|slot| slot == first),
+        None => true,
+    }
+}
+
+async fn open(
+    topology: &Topology,
+    options: &ConnectionOptions,
+    size: usize,
+) -> RedisResult<Vec<Connection>> {
+    let mut connections = Vec::with_capacity(size);
+    match topology {
+        Topology::Cluster(urls) => {
+            let client = options.cluster_client(urls)?;
+            for _ in 0..size {
+                connections.push(Connection::Cluster(client.get_async_connection().await?));
+            }
+        }
+        Topology::Single(_) | Topology::Sentinel { .. } => {
+            let client = primary_client(topology, options).await?;
+            for _ in 0..size {
+                connections.push(Connection::Single(client.get_connection_manager().await?));
+            }
+        }
+    }
+    Ok(connections)
+}
+
+/// Returns a client for the single server, or for the current primary found by the sentinels.
+/// Fails for a Redis Cluster, which has no single server
+pub async fn primary_client(
+    topology: &Topology,
+    options: &ConnectionOptions,
+) -> RedisResult<Client> {
+    match topology {
+        Topology::Single(url) => options.client(url),
+        Topology::Sentinel { urls, master } => {
+            let mut sentinel = options.sentinel(urls)?;
+            sentinel
+                .async_master_for(master, Some(&options.sentinel_node_info(urls)))
+                .await
+        }
+        Topology::Cluster(_) => Err((
+            ErrorKind::InvalidClientConfig,
+            "a Redis Cluster has no single server",
+        )
+            .into()),
+    }
+}
+
+fn find_value<'a>(values: &'a HashMap<String, String>, key: &str) -> Option<&'a str> {
+    values
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, v)| v.as_str())
+}
+
+fn split_urls(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const URL: &str = "redis://127.0.0.1:6379";
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn topology_from_link() {
+        assert_eq!(
+            Topology::from_link(&values(&[]), URL.to_string()).unwrap(),
+            Topology::Single(URL.to_string())
+        );
+        assert_eq!(
+            Topology::from_link(
+                &values(&[("cluster_urls", "redis://a:7000, redis://b:7001,")]),
+                URL.to_string()
+            )
+            .unwrap(),
+            Topology::Cluster(vec!["redis://a:7000".into(), "redis://b:7001".into()])
+        );
+        assert_eq!(
+            Topology::from_link(
+                &values(&[
+                    ("SENTINEL_URLS", "redis://s1:26379,redis://s2:26379"),
+                    ("SENTINEL_MASTER", "mymaster")
+                ]),
+                URL.to_string()
+            )
+            .unwrap(),
+            Topology::Sentinel {
+                urls: vec!["redis://s1:26379".into(), "redis://s2:26379".into()],
+                master: "mymaster".into()
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_topology() {
+        // sentinel without master name
+        assert!(Topology::from_link(
+            &values(&[("SENTINEL_URLS", "redis://s1:26379")]),
+            URL.to_string()
+        )
+        .is_err());
+        // empty list
+        assert!(Topology::from_link(&values(&[("CLUSTER_URLS", " , ")]), URL.to_string()).is_err());
+        // both
+        assert!(Topology::from_link(
+            &values(&[
+                ("CLUSTER_URLS", "redis://a:7000"),
+                ("SENTINEL_URLS", "redis://s1:26379"),
+                ("SENTINEL_MASTER", "mymaster")
+            ]),
+            URL.to_string()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn validate_urls() {
+        assert!(Topology::Single(URL.to_string()).validate().is_ok());
+        assert!(Topology::Single("localhost:6379".to_string())
+            .validate()
+            .is_err());
+        assert!(
+            Topology::Cluster(vec![URL.to_string(), "http://a:7000".to_string()])
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn hash_slots() {
+        assert!(same_slot(&[]));
+        assert!(same_slot(&["{user1}.a".into(), "{user1}.b".into()]));
+        assert!(!same_slot(&["a".into(), "b".into()]));
+    }
+}
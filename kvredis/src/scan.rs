@@ -0,0 +1,209 @@
+//! Listing and deleting keys with SCAN
+//!
+//! Keys are listed a page at a time with SCAN and a cursor. On a Redis Cluster each
+//! primary has its own keys and cursor, so the primaries are scanned one after another,
+//! and the cursor returned to the actor is `slot:position`, where `slot` is a hash slot
+//! of the primary being scanned. Actors should treat cursors as opaque.
+//!
+//! SCAN returns every key that exists for the whole scan, but may return a key more than
+//! once, and keys that are added or removed during the scan may or may not be returned.
+
+use std::collections::HashMap;
+
+use redis::{from_redis_value, Value};
+use wasmbus_rpc::error::{RpcError, RpcResult};
+
+use wasmcloud_provider_kvredis::link::ActorLink;
+
+/// COUNT hint used for each page when deleting keys
+const DELETE_PAGE_COUNT: u32 = 1000;
+
+/// Position in a scan
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Cursor {
+    /// a hash slot of the cluster node being scanned
+    slot: Option<u16>,
+    /// cursor on the server
+    position: u64,
+}
+
+impl Cursor {
+    /// Parses a cursor from a previous page. No cursor starts a new scan
+    fn parse(cursor: Option<&str>) -> RpcResult<Self> {
+        let cursor = match cursor.map(str::trim) {
+            None | Some("") => return Ok(Cursor::default()),
+            Some(cursor) => cursor,
+        };
+        let invalid = || RpcError::InvalidParameter(format!("invalid cursor '{}'", cursor));
+        match cursor.split_once(':') {
+            Some((slot, position)) => Ok(Cursor {
+                slot: Some(slot.parse().map_err(|_| invalid())?),
+                position: position.parse().map_err(|_| invalid())?,
+            }),
+            None => Ok(Cursor {
+                slot: None,
+                position: cursor.parse().map_err(|_| invalid())?,
+            }),
+        }
+    }
+}
+
+/// Returns one page of redis keys matching the redis pattern,
+/// and the cursor for the next page, or None if the scan is complete
+pub(crate) async fn scan(
+    link: &ActorLink,
+    pattern: &str,
+    cursor: Option<&str>,
+    count: Option<u32>,
+) -> RpcResult<(Vec<String>, Option<String>)> {
+    let cursor = Cursor::parse(cursor)?;
+    let mut cmd = redis::cmd("SCAN");
+    cmd.arg(cursor.position).arg("MATCH").arg(pattern);
+    if let Some(count) = count {
+        cmd.arg("COUNT").arg(count);
+    }
+
+    if !link.is_cluster() {
+        if cursor.slot.is_some() {
+            return Err(RpcError::InvalidParameter(
+                "cursor is from a Redis Cluster".to_string(),
+            ));
+        }
+        let (next, keys): (u64, Vec<String>) = link.exec(&cmd).await?;
+        return Ok((keys, (next != 0).then(|| next.to_string())));
+    }
+
+    let mut slots_cmd = redis::cmd("CLUSTER");
+    slots_cmd.arg("SLOTS");
+    let nodes = primary_slots(link.exec(&slots_cmd).await?)?;
+    let slot = match (cursor.slot, nodes.first()) {
+        (Some(slot), _) => slot,
+        (None, Some(first)) if cursor.position == 0 => *first,
+        (None, Some(_)) => {
+            return Err(RpcError::InvalidParameter(
+                "cursor is not from a Redis Cluster".to_string(),
+            ))
+        }
+        (None, None) => return Ok((Vec::new(), None)),
+    };
+    let (next, keys): (u64, Vec<String>) = link.exec_on_slot(&cmd, slot).await?;
+    let next = if next != 0 {
+        Some(format!("{}:{}", slot, next))
+    } else {
+        // this node is done, continue with the next one
+        nodes
+            .iter()
+            .find(|s| **s > slot)
+            .map(|s| format!("{}:0", s))
+    };
+    Ok((keys, next))
+}
+
+/// Deletes all redis keys matching the redis pattern. Returns the number of keys deleted
+pub(crate) async fn delete(link: &ActorLink, pattern: &str) -> RpcResult<u32> {
+    let mut deleted = 0;
+    let mut cursor = None;
+    loop {
+        let (keys, next) = scan(link, pattern, cursor.as_deref(), Some(DELETE_PAGE_COUNT)).await?;
+        if !keys.is_empty() {
+            deleted += if link.is_cluster() {
+                // keys may be in different slots, so delete them one at a time
+                futures::future::try_join_all(
+                    keys.iter()
+                        .map(|key| async move { link.exec::<u32>(&redis::Cmd::unlink(key)).await }),
+                )
+                .await?
+                .into_iter()
+                .sum()
+            } else {
+                link.exec::<u32>(&redis::Cmd::unlink(keys)).await?
+            };
+        }
+        match next {
+            Some(next) => cursor = Some(next),
+            None => return Ok(deleted),
+        }
+    }
+}
+
+/// Returns one hash slot of each primary from the reply to CLUSTER SLOTS, in order.
+/// Each entry of the reply is the first and last slot of a range, then the primary's
+/// address, then its replicas
+fn primary_slots(ranges: Vec<Value>) -> RpcResult<Vec<u16>> {
+    let invalid = || RpcError::Other("redis error: invalid reply to CLUSTER SLOTS".to_string());
+    let mut primaries: HashMap<(String, u16), u16> = HashMap::new();
+    for range in ranges {
+        let range: Vec<Value> = from_redis_value(&range).map_err(|_| invalid())?;
+        let (start, primary) = match range.as_slice() {
+            [start, _end, primary, ..] => (start, primary),
+            _ => return Err(invalid()),
+        };
+        let start: u16 = from_redis_value(start).map_err(|_| invalid())?;
+        let address: Vec<Value> = from_redis_value(primary).map_err(|_| invalid())?;
+        let address = match address.as_slice() {
+            [host, port, ..] => (
+                from_redis_value(host).map_err(|_| invalid())?,
+                from_redis_value(port).map_err(|_| invalid())?,
+            ),
+            _ => return Err(invalid()),
+        };
+        let first = primaries.entry(address).or_insert(start);
+        *first = start.min(*first);
+    }
+    let mut slots: Vec<u16> = primaries.into_values().collect();
+    slots.sort_unstable();
+    Ok(slots)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_cursor() {
+        assert_eq!(Cursor::parse(None).unwrap(), Cursor::default());
+        assert_eq!(Cursor::parse(Some("")).unwrap(), Cursor::default());
+        assert_eq!(
+            Cursor::parse(Some("1792")).unwrap(),
+            Cursor {
+                slot: None,
+                position: 1792
+            }
+        );
+        assert_eq!(
+            Cursor::parse(Some("5461:88")).unwrap(),
+            Cursor {
+                slot: Some(5461),
+                position: 88
+            }
+        );
+        assert!(Cursor::parse(Some("next")).is_err());
+        assert!(Cursor::parse(Some("99999:1")).is_err());
+    }
+
+    fn range(start: i64, end: i64, host: &str, port: i64) -> Value {
+        Value::Bulk(vec![
+            Value::Int(start),
+            Value::Int(end),
+            Value::Bulk(vec![
+                Value::Data(host.as_bytes().to_vec()),
+                Value::Int(port),
+                Value::Data(b"node-id".to_vec()),
+            ]),
+        ])
+    }
+
+    #[test]
+    fn slots_of_primaries() {
+        let reply = vec![
+            range(10923, 16383, "10.0.0.3", 7000),
+            range(0, 5460, "10.0.0.1", 7000),
+            range(5461, 10000, "10.0.0.2", 7000),
+            // a second range of the first primary
+            range(10001, 10922, "10.0.0.1", 7000),
+        ];
+        assert_eq!(primary_slots(reply).unwrap(), vec![0, 5461, 10923]);
+
+        assert!(primary_slots(vec![Value::Int(1)]).is_err());
+    }
+}
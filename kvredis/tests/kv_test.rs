@@ -5,6 +5,7 @@ use wasmbus_rpc::{
     provider::prelude::Context,
 };
 use wasmcloud_interface_keyvalue::*;
+use wasmcloud_interface_kvredis::*;
 use wasmcloud_test_util::{
     check, check_eq,
     cli::print_test_results,
@@ -13,10 +14,31 @@ use wasmcloud_test_util::{
     testing::{TestOptions, TestResult},
 };
 
+// generated from the provider's kvredis.smithy by build.rs
+#[allow(dead_code)]
+mod wasmcloud_interface_kvredis {
+    include!(concat!(env!("OUT_DIR"), "/gen/kvredis.rs"));
+}
+
 #[tokio::test]
 async fn run_all() {
     let opts = TestOptions::default();
-    let res = run_selected_spawn!(opts, health_check, get_set, contains_del, incr, lists, sets);
+    let res = run_selected_spawn!(
+        opts,
+        health_check,
+        get_set,
+        contains_del,
+        incr,
+        lists,
+        sets,
+        expiry,
+        concurrent,
+        batch,
+        compare_and_set,
+        list_keys,
+        hashes,
+        sorted_sets
+    );
     print_test_results(&res);
 
     let passed = res.iter().filter(|tr| tr.passed).count();
@@ -394,3 +416,517 @@ async fn sets(_opt: &TestOptions) -> RpcResult<()> {
     let _ = kv.set_clear(&ctx, &key2).await?;
     Ok(())
 }
+
+/// expiry : list_add_with_expiry, set_add_with_expiry, get_ttl, persist, expire
+async fn expiry(_opt: &TestOptions) -> RpcResult<()> {
+    let kv = KeyValueSender::via(test_provider().await);
+    let kvr = KvRedisSender::via(test_provider().await);
+    let ctx = Context::default();
+
+    let list = new_key("explist");
+    let set_name = new_key("expset");
+    let key = new_key("expkey");
+
+    let ttl = kvr.get_ttl(&ctx, &list).await?;
+    check_eq!(ttl.exists, false)?;
+    check!(ttl.ttl.is_none())?;
+
+    // the expiration is set on each write
+    for (i, name) in ["apple", "banana"].iter().enumerate() {
+        let n = kvr
+            .list_add_with_expiry(
+                &ctx,
+                &ListAddWithExpiryRequest {
+                    list_name: list.clone(),
+                    value: name.to_string(),
+                    expires: 3,
+                },
+            )
+            .await?;
+        check_eq!(n, i as u32 + 1)?;
+    }
+    let ttl = kvr.get_ttl(&ctx, &list).await?;
+    check!(ttl.exists)?;
+    check!(matches!(ttl.ttl, Some(1..=3)))?;
+
+    let n = kvr
+        .set_add_with_expiry(
+            &ctx,
+            &SetAddWithExpiryRequest {
+                set_name: set_name.clone(),
+                value: "Alice".to_string(),
+                expires: 3,
+            },
+        )
+        .await?;
+    check_eq!(n, 1)?;
+
+    // a persistent key has no ttl
+    set(&kv, &ctx, &key, "value", 0).await?;
+    let ttl = kvr.get_ttl(&ctx, &key).await?;
+    check!(ttl.exists)?;
+    check!(ttl.ttl.is_none())?;
+
+    // extending a persistent key leaves it persistent
+    let exists = kvr
+        .expire(
+            &ctx,
+            &ExpireRequest {
+                key: key.clone(),
+                seconds: 10,
+                extend: Some(true),
+            },
+        )
+        .await?;
+    check!(exists)?;
+    check!(kvr.get_ttl(&ctx, &key).await?.ttl.is_none())?;
+
+    // touch, then extend
+    kvr.expire(
+        &ctx,
+        &ExpireRequest {
+            key: key.clone(),
+            seconds: 10,
+            extend: None,
+        },
+    )
+    .await?;
+    kvr.expire(
+        &ctx,
+        &ExpireRequest {
+            key: key.clone(),
+            seconds: 100,
+            extend: Some(true),
+        },
+    )
+    .await?;
+    check!(matches!(
+        kvr.get_ttl(&ctx, &key).await?.ttl,
+        Some(101..=110)
+    ))?;
+
+    // expire on a missing key returns false
+    let exists = kvr
+        .expire(
+            &ctx,
+            &ExpireRequest {
+                key: new_key("missing"),
+                seconds: 10,
+                extend: Some(true),
+            },
+        )
+        .await?;
+    check_eq!(exists, false)?;
+
+    // persist the set, so only the list expires
+    check!(kvr.persist(&ctx, &set_name).await?)?;
+    check_eq!(kvr.persist(&ctx, &set_name).await?, false)?;
+
+    sleep(Duration::from_secs(5)).await;
+
+    check_eq!(kv.contains(&ctx, &list).await?, false)?;
+    check_eq!(kv.set_query(&ctx, &set_name).await?.len(), 1)?;
+
+    // clean up
+    let _ = kv.set_clear(&ctx, &set_name).await?;
+    let _ = kv.del(&ctx, &key).await?;
+    Ok(())
+}
+
+/// many concurrent requests from the same actor share the pooled connections
+async fn concurrent(_opt: &TestOptions) -> RpcResult<()> {
+    let kv = KeyValueSender::via(test_provider().await);
+    let ctx = Context::default();
+    let key = new_key("concurrent");
+
+    let requests = (0..100).map(|_| {
+        let (kv, ctx, key) = (&kv, &ctx, key.clone());
+        async move { kv.increment(ctx, &IncrementRequest { key, value: 1 }).await }
+    });
+    let results = futures::future::join_all(requests).await;
+    check!(results.iter().all(|r| r.is_ok()))?;
+
+    let get_resp = kv.get(&ctx, &key).await?;
+    check_eq!(get_resp.value.as_str(), "100")?;
+
+    // clean up
+    let _ = kv.del(&ctx, &key).await?;
+    Ok(())
+}
+
+fn batch_op(op: &str, key: &str, value: Option<&str>) -> BatchOperation {
+    BatchOperation {
+        op: op.to_string(),
+        key: key.to_string(),
+        value: value.map(String::from),
+        ..Default::default()
+    }
+}
+
+/// batch: operations in one transaction, results in order
+async fn batch(_opt: &TestOptions) -> RpcResult<()> {
+    let kv = KeyValueSender::via(test_provider().await);
+    let kvr = KvRedisSender::via(test_provider().await);
+    let ctx = Context::default();
+    let key = new_key("batch");
+    let counter = new_key("batchcount");
+    let list = new_key("batchlist");
+
+    let resp = kvr
+        .batch(
+            &ctx,
+            &BatchRequest {
+                operations: vec![
+                    batch_op("Set", &key, Some("one")),
+                    BatchOperation {
+                        delta: Some(5),
+                        ..batch_op("Increment", &counter, None)
+                    },
+                    batch_op("ListAdd", &list, Some("a")),
+                    batch_op("ListAdd", &list, Some("b")),
+                    batch_op("ListRange", &list, None),
+                    batch_op("Get", &key, None),
+                    batch_op("Del", &key, None),
+                    batch_op("Contains", &key, None),
+                ],
+            },
+        )
+        .await?;
+    let results = resp.results;
+    check_eq!(results.len(), 8)?;
+    check!(results[0].exists)?;
+    check_eq!(results[1].number, Some(5))?;
+    check_eq!(results[3].number, Some(2))?;
+    check_eq!(
+        results[4].values,
+        Some(vec!["a".to_string(), "b".to_string()])
+    )?;
+    check_eq!(results[5].value.as_deref(), Some("one"))?;
+    check!(results[6].exists)?;
+    check_eq!(results[7].exists, false)?;
+
+    // invalid operations are rejected before anything runs
+    let err = kvr
+        .batch(
+            &ctx,
+            &BatchRequest {
+                operations: vec![
+                    batch_op("Set", &key, Some("two")),
+                    batch_op("SetAdd", &list, None),
+                ],
+            },
+        )
+        .await;
+    check!(err.is_err())?;
+    check_eq!(kv.contains(&ctx, &key).await?, false)?;
+
+    // clean up
+    let _ = kv.del(&ctx, &counter).await?;
+    let _ = kv.list_clear(&ctx, &list).await?;
+    Ok(())
+}
+
+/// compare_and_set: locks, and updates from concurrent writers
+async fn compare_and_set(_opt: &TestOptions) -> RpcResult<()> {
+    let kv = KeyValueSender::via(test_provider().await);
+    let kvr = KvRedisSender::via(test_provider().await);
+    let ctx = Context::default();
+    let lock = new_key("lock");
+
+    // acquire a lock: set only if missing
+    let acquire = |owner: &str| CompareAndSetRequest {
+        key: lock.clone(),
+        expected: None,
+        value: Some(owner.to_string()),
+        expires: Some(30),
+    };
+    check!(kvr.compare_and_set(&ctx, &acquire("alice")).await?)?;
+    check_eq!(kvr.compare_and_set(&ctx, &acquire("bob")).await?, false)?;
+    check!(matches!(kvr.get_ttl(&ctx, &lock).await?.ttl, Some(1..=30)))?;
+
+    // only the owner can release it
+    let release = |owner: &str| CompareAndSetRequest {
+        key: lock.clone(),
+        expected: Some(owner.to_string()),
+        value: None,
+        expires: None,
+    };
+    check_eq!(kvr.compare_and_set(&ctx, &release("bob")).await?, false)?;
+    check!(kvr.compare_and_set(&ctx, &release("alice")).await?)?;
+    check_eq!(kv.contains(&ctx, &lock).await?, false)?;
+
+    // an empty string is a value, not a missing key
+    set(&kv, &ctx, &lock, "", 0).await?;
+    check_eq!(kvr.compare_and_set(&ctx, &acquire("carol")).await?, false)?;
+    let empty = CompareAndSetRequest {
+        expected: Some(String::new()),
+        ..acquire("carol")
+    };
+    check!(kvr.compare_and_set(&ctx, &empty).await?)?;
+    let _ = kv.del(&ctx, &lock).await?;
+
+    // concurrent read-modify-write with retries loses no updates
+    let counter = new_key("cas");
+    set(&kv, &ctx, &counter, "0", 0).await?;
+    let writers = (0..10).map(|_| async {
+        loop {
+            let current = kv.get(&ctx, &counter).await?.value;
+            let next = current.parse::<u32>().unwrap_or_default() + 1;
+            let req = CompareAndSetRequest {
+                key: counter.clone(),
+                expected: Some(current),
+                value: Some(next.to_string()),
+                expires: None,
+            };
+            if kvr.compare_and_set(&ctx, &req).await? {
+                return Ok::<(), RpcError>(());
+            }
+        }
+    });
+    let results = futures::future::join_all(writers).await;
+    check!(results.iter().all(|r| r.is_ok()))?;
+    check_eq!(kv.get(&ctx, &counter).await?.value.as_str(), "10")?;
+
+    // clean up
+    let _ = kv.del(&ctx, &counter).await?;
+    Ok(())
+}
+
+/// list_keys, delete_keys
+async fn list_keys(_opt: &TestOptions) -> RpcResult<()> {
+    let kv = KeyValueSender::via(test_provider().await);
+    let kvr = KvRedisSender::via(test_provider().await);
+    let ctx = Context::default();
+    let prefix = new_key("scan");
+
+    for i in 0..25 {
+        set(&kv, &ctx, format!("{}:{}", prefix, i), i, 0).await?;
+    }
+
+    // read all pages
+    let mut keys = std::collections::HashSet::new();
+    let mut cursor = None;
+    loop {
+        let page = kvr
+            .list_keys(
+                &ctx,
+                &ListKeysRequest {
+                    pattern: Some(format!("{}:*", prefix)),
+                    cursor,
+                    count: Some(5),
+                },
+            )
+            .await?;
+        keys.extend(page.keys);
+        cursor = page.cursor;
+        if cursor.is_none() {
+            break;
+        }
+    }
+    check_eq!(keys.len(), 25)?;
+    check!(keys.contains(&format!("{}:7", prefix)))?;
+
+    // bad cursor
+    let resp = kvr
+        .list_keys(
+            &ctx,
+            &ListKeysRequest {
+                cursor: Some("not-a-cursor".to_string()),
+                ..Default::default()
+            },
+        )
+        .await;
+    check!(resp.is_err())?;
+
+    let deleted = kvr
+        .delete_keys(
+            &ctx,
+            &DeleteKeysRequest {
+                pattern: format!("{}:1*", prefix),
+            },
+        )
+        .await?;
+    // 1, 10..19
+    check_eq!(deleted, 11)?;
+    check_eq!(kv.contains(&ctx, &format!("{}:12", prefix)).await?, false)?;
+    check!(kv.contains(&ctx, &format!("{}:2", prefix)).await?)?;
+
+    let deleted = kvr
+        .delete_keys(
+            &ctx,
+            &DeleteKeysRequest {
+                pattern: format!("{}:*", prefix),
+            },
+        )
+        .await?;
+    check_eq!(deleted, 14)?;
+    Ok(())
+}
+
+/// hash_get, hash_set, hash_del, hash_get_all
+async fn hashes(_opt: &TestOptions) -> RpcResult<()> {
+    let kv = KeyValueSender::via(test_provider().await);
+    let kvr = KvRedisSender::via(test_provider().await);
+    let ctx = Context::default();
+    let key = new_key("hash");
+
+    check!(kvr.hash_get_all(&ctx, &key).await?.is_empty())?;
+
+    let fields: FieldMap = [("name", "Alice"), ("city", "Paris")]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    let added = kvr
+        .hash_set(
+            &ctx,
+            &HashSetRequest {
+                key: key.clone(),
+                fields,
+            },
+        )
+        .await?;
+    check_eq!(added, 2)?;
+
+    // update one field, add another
+    let added = kvr
+        .hash_set(
+            &ctx,
+            &HashSetRequest {
+                key: key.clone(),
+                fields: [("city", "Lyon"), ("age", "30")]
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            },
+        )
+        .await?;
+    check_eq!(added, 1)?;
+
+    let resp = kvr
+        .hash_get(
+            &ctx,
+            &HashGetRequest {
+                key: key.clone(),
+                field: "city".to_string(),
+            },
+        )
+        .await?;
+    check!(resp.exists)?;
+    check_eq!(resp.value.as_deref(), Some("Lyon"))?;
+
+    let removed = kvr
+        .hash_del(
+            &ctx,
+            &HashDelRequest {
+                key: key.clone(),
+                fields: vec!["age".to_string(), "missing".to_string()],
+            },
+        )
+        .await?;
+    check_eq!(removed, 1)?;
+
+    let resp = kvr
+        .hash_get(
+            &ctx,
+            &HashGetRequest {
+                key: key.clone(),
+                field: "age".to_string(),
+            },
+        )
+        .await?;
+    check_eq!(resp.exists, false)?;
+    check!(resp.value.is_none())?;
+
+    let all = kvr.hash_get_all(&ctx, &key).await?;
+    check_eq!(all.len(), 2)?;
+    check_eq!(all.get("name").map(String::as_str), Some("Alice"))?;
+
+    // clean up
+    let _ = kv.del(&ctx, &key).await?;
+    Ok(())
+}
+
+/// sorted_set_add, sorted_set_range_by_rank, sorted_set_range_by_score, sorted_set_increment
+async fn sorted_sets(_opt: &TestOptions) -> RpcResult<()> {
+    let kv = KeyValueSender::via(test_provider().await);
+    let kvr = KvRedisSender::via(test_provider().await);
+    let ctx = Context::default();
+    let key = new_key("zset");
+
+    let member = |member: &str, score: f64| ScoredMember {
+        member: member.to_string(),
+        score,
+    };
+    let added = kvr
+        .sorted_set_add(
+            &ctx,
+            &SortedSetAddRequest {
+                key: key.clone(),
+                members: vec![
+                    member("alice", 10.0),
+                    member("bob", 25.0),
+                    member("carol", 17.5),
+                ],
+            },
+        )
+        .await?;
+    check_eq!(added, 3)?;
+
+    let score = kvr
+        .sorted_set_increment(
+            &ctx,
+            &SortedSetIncrementRequest {
+                key: key.clone(),
+                member: "alice".to_string(),
+                delta: 20.0,
+            },
+        )
+        .await?;
+    check_eq!(score, 30.0)?;
+
+    // top two
+    let top = kvr
+        .sorted_set_range_by_rank(
+            &ctx,
+            &SortedSetRangeByRankRequest {
+                key: key.clone(),
+                start: 0,
+                stop: 1,
+                reverse: Some(true),
+            },
+        )
+        .await?;
+    check_eq!(top, vec![member("alice", 30.0), member("bob", 25.0)])?;
+
+    let range = kvr
+        .sorted_set_range_by_score(
+            &ctx,
+            &SortedSetRangeByScoreRequest {
+                key: key.clone(),
+                min: "(17.5".to_string(),
+                max: "+inf".to_string(),
+                ..Default::default()
+            },
+        )
+        .await?;
+    check_eq!(range, vec![member("bob", 25.0), member("alice", 30.0)])?;
+
+    let range = kvr
+        .sorted_set_range_by_score(
+            &ctx,
+            &SortedSetRangeByScoreRequest {
+                key: key.clone(),
+                min: "-inf".to_string(),
+                max: "+inf".to_string(),
+                offset: Some(1),
+                count: Some(1),
+                reverse: Some(true),
+            },
+        )
+        .await?;
+    check_eq!(range, vec![member("bob", 25.0)])?;
+
+    // clean up
+    let _ = kv.del(&ctx, &key).await?;
+    Ok(())
+}
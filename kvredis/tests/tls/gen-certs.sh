@@ -0,0 +1,22 @@
+#!/bin/sh
+# Generates a CA, a server certificate for localhost, and a client certificate,
+# for tests against a TLS-enabled redis-server. See `make test-tls`
+set -e
+cd "$(dirname "$0")"
+
+openssl req -x509 -new -nodes -newkey rsa:2048 -days 30 \
+    -keyout ca.key -out ca.crt -subj "/CN=kvredis-test-ca"
+
+openssl req -new -nodes -newkey rsa:2048 \
+    -keyout server.key -out server.csr -subj "/CN=localhost"
+printf "subjectAltName=DNS:localhost,IP:127.0.0.1\nbasicConstraints=CA:FALSE\nextendedKeyUsage=serverAuth\n" > server.ext
+openssl x509 -req -in server.csr -CA ca.crt -CAkey ca.key -CAcreateserial -days 30 \
+    -extfile server.ext -out server.crt
+
+openssl req -new -nodes -newkey rsa:2048 \
+    -keyout client.key -out client.csr -subj "/CN=kvredis-test-client"
+printf "basicConstraints=CA:FALSE\nextendedKeyUsage=clientAuth\n" > client.ext
+openssl x509 -req -in client.csr -CA ca.crt -CAkey ca.key -CAcreateserial -days 30 \
+    -extfile client.ext -out client.crt
+
+rm -f ./*.csr ./*.ext ./*.srl
@@ -0,0 +1,451 @@
+//! Consuming streams with consumer groups
+//!
+//! A link subscribes to streams with `SUBSCRIPTIONS`, a comma-separated list of stream
+//! names relative to the link's key prefix. Each stream is read by its own task with
+//! XREADGROUP, as a consumer in the link's consumer group, and each entry is sent to the
+//! actor with `MessageSubscriber.HandleMessage`. An entry is acknowledged with XACK only
+//! after the actor handles it without error. Entries that are not acknowledged stay
+//! pending, and are delivered again:
+//! - when the consumer restarts, since it first reads its own pending entries, and
+//! - every `CLAIM_IDLE_SECS`, when entries that have been pending for at least that long,
+//!   for any consumer in the group, are claimed with XAUTOCLAIM and handled again.
+//!
+//! Instances of the provider on different hosts are different consumers in the same
+//! group (the consumer name defaults to the host id), so each entry is handled by one
+//! of them. Consumer groups require Redis 5.0, and XAUTOCLAIM requires Redis 6.2.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use redis::{
+    from_redis_value,
+    streams::{StreamId, StreamRangeReply, StreamReadOptions, StreamReadReply},
+    RedisResult, Value,
+};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use wasmbus_rpc::{
+    core::LinkDefinition,
+    error::{RpcError, RpcResult},
+    provider::prelude::Context,
+};
+use wasmcloud_interface_messaging::{MessageSubscriber, MessageSubscriberSender, SubMessage};
+
+use wasmcloud_provider_kvredis::{
+    link::retry_delay,
+    options::ConnectionOptions,
+    pool::{Connection, ConnectionPool, Topology},
+};
+
+use crate::{BODY_FIELD, REPLY_TO_FIELD};
+
+const SUBSCRIPTIONS_KEY: &str = "SUBSCRIPTIONS";
+const CONSUMER_GROUP_KEY: &str = "CONSUMER_GROUP";
+const CONSUMER_NAME_KEY: &str = "CONSUMER_NAME";
+const START_ID_KEY: &str = "START_ID";
+const BATCH_SIZE_KEY: &str = "BATCH_SIZE";
+const CLAIM_IDLE_SECS_KEY: &str = "CLAIM_IDLE_SECS";
+
+/// Where a new consumer group starts reading: only entries added after it is created
+const DEFAULT_START_ID: &str = "$";
+/// Maximum number of entries returned by each read
+const DEFAULT_BATCH_SIZE: usize = 10;
+/// How long an entry may be pending before it is claimed and handled again
+const DEFAULT_CLAIM_IDLE_SECS: u64 = 60;
+/// How long each XREADGROUP waits for new entries
+const BLOCK_MILLIS: usize = 5000;
+
+/// Consumer settings from link values
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ConsumerConfig {
+    /// streams to read, without the key prefix
+    streams: Vec<String>,
+    group: String,
+    consumer: String,
+    /// ID used when the group is created
+    start_id: String,
+    batch_size: usize,
+    /// minimum idle time of claimed entries, or None to never claim entries
+    claim_idle: Option<Duration>,
+}
+
+impl ConsumerConfig {
+    /// Reads consumer settings from link values (case-insensitive). The group defaults
+    /// to the actor id, and the consumer name to the host id.
+    /// Returns None if the link doesn't subscribe to any streams
+    pub(crate) fn from_link(
+        values: &HashMap<String, String>,
+        actor_id: &str,
+        host_id: &str,
+    ) -> RpcResult<Option<Self>> {
+        let find = |name: &str| {
+            values
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        let number = |name: &str| -> RpcResult<Option<u64>> {
+            find(name)
+                .map(|v| {
+                    v.parse::<u64>().map_err(|_| {
+                        RpcError::InvalidParameter(format!("invalid {} '{}'", name, v))
+                    })
+                })
+                .transpose()
+        };
+        let streams: Vec<String> = find(SUBSCRIPTIONS_KEY)
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        if streams.is_empty() {
+            return Ok(None);
+        }
+        let batch_size = match number(BATCH_SIZE_KEY)? {
+            Some(0) => {
+                return Err(RpcError::InvalidParameter(format!(
+                    "{} must be at least 1",
+                    BATCH_SIZE_KEY
+                )))
+            }
+            Some(n) => n as usize,
+            None => DEFAULT_BATCH_SIZE,
+        };
+        let claim_idle = match number(CLAIM_IDLE_SECS_KEY)?.unwrap_or(DEFAULT_CLAIM_IDLE_SECS) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
+        Ok(Some(ConsumerConfig {
+            streams,
+            group: find(CONSUMER_GROUP_KEY).unwrap_or_else(|| actor_id.to_string()),
+            consumer: find(CONSUMER_NAME_KEY).unwrap_or_else(|| host_id.to_string()),
+            start_id: find(START_ID_KEY).unwrap_or_else(|| DEFAULT_START_ID.to_string()),
+            batch_size,
+            claim_idle,
+        }))
+    }
+}
+
+/// Starts a task for each subscribed stream that reads entries and sends them to the actor.
+/// Each task has its own connection, which is reopened, with backoff, if it fails
+pub(crate) fn spawn(
+    ld: &LinkDefinition,
+    topology: &Topology,
+    options: &ConnectionOptions,
+    key_prefix: &str,
+    config: ConsumerConfig,
+) -> Vec<JoinHandle<()>> {
+    config
+        .streams
+        .iter()
+        .map(|stream| {
+            let consumer = Consumer {
+                ld: ld.clone(),
+                topology: topology.clone(),
+                options: options.clone(),
+                key: format!("{}{}", key_prefix, stream),
+                stream: stream.clone(),
+                config: config.clone(),
+            };
+            tokio::spawn(consumer.run())
+        })
+        .collect()
+}
+
+struct Consumer {
+    ld: LinkDefinition,
+    topology: Topology,
+    options: ConnectionOptions,
+    /// the stream name sent to the actor as the subject
+    stream: String,
+    /// redis key of the stream
+    key: String,
+    config: ConsumerConfig,
+}
+
+impl Consumer {
+    async fn run(self) {
+        let mut failures = 0;
+        loop {
+            match self.connect().await {
+                Ok(pool) => {
+                    failures = 0;
+                    info!(actor_id = %self.ld.actor_id, stream = %self.stream, group = %self.config.group, "consuming redis stream");
+                    let generation = pool.generation();
+                    if let Err(e) = self.consume(&mut pool.get()).await {
+                        warn!(actor_id = %self.ld.actor_id, stream = %self.stream, error = %e, "redis stream consumer failed");
+                        pool.check_failover(&e, generation).await;
+                        failures += 1;
+                    }
+                }
+                Err(e) => {
+                    failures += 1;
+                    warn!(
+                        actor_id = %self.ld.actor_id,
+                        stream = %self.stream,
+                        error = %e,
+                        attempts = failures,
+                        "unable to connect redis stream consumer"
+                    );
+                }
+            }
+            tokio::time::sleep(retry_delay(failures.max(1))).await;
+        }
+    }
+
+    /// Opens the consumer's connection, and creates the stream and consumer group
+    /// if they don't exist
+    async fn connect(&self) -> RedisResult<ConnectionPool> {
+        let pool = ConnectionPool::connect(self.topology.clone(), self.options.clone(), 1).await?;
+        let created: RedisResult<()> = redis::Cmd::xgroup_create_mkstream(
+            &self.key,
+            &self.config.group,
+            &self.config.start_id,
+        )
+        .query_async(&mut pool.get())
+        .await;
+        match created {
+            Err(e) if e.code() != Some("BUSYGROUP") => Err(e),
+            _ => Ok(pool),
+        }
+    }
+
+    /// Reads and handles entries until the connection fails
+    async fn consume(&self, con: &mut Connection) -> RedisResult<()> {
+        // entries delivered to this consumer before a restart, that were never acknowledged
+        self.read_pending(con).await?;
+        let mut last_claim = Instant::now();
+        let options = self.read_options().block(BLOCK_MILLIS);
+        loop {
+            let reply: Option<StreamReadReply> =
+                redis::Cmd::xread_options(&[&self.key], &[">"], &options)
+                    .query_async(con)
+                    .await?;
+            for entry in reply.into_iter().flat_map(|r| r.keys).flat_map(|k| k.ids) {
+                self.handle(con, entry).await?;
+            }
+            if let Some(idle) = self.config.claim_idle {
+                if last_claim.elapsed() >= idle {
+                    self.claim(con, idle).await?;
+                    last_claim = Instant::now();
+                }
+            }
+        }
+    }
+
+    fn read_options(&self) -> StreamReadOptions {
+        StreamReadOptions::default()
+            .group(&self.config.group, &self.config.consumer)
+            .count(self.config.batch_size)
+    }
+
+    /// Handles the entries that are pending for this consumer
+    async fn read_pending(&self, con: &mut Connection) -> RedisResult<()> {
+        let options = self.read_options();
+        let mut last = "0-0".to_string();
+        loop {
+            let reply: Option<StreamReadReply> =
+                redis::Cmd::xread_options(&[&self.key], &[&last], &options)
+                    .query_async(con)
+                    .await?;
+            let entries: Vec<StreamId> = reply
+                .into_iter()
+                .flat_map(|r| r.keys)
+                .flat_map(|k| k.ids)
+                .collect();
+            match entries.last() {
+                Some(entry) => last = entry.id.clone(),
+                None => return Ok(()),
+            }
+            for entry in entries {
+                self.handle(con, entry).await?;
+            }
+        }
+    }
+
+    /// Claims and handles the entries that have been pending for at least `idle`
+    async fn claim(&self, con: &mut Connection, idle: Duration) -> RedisResult<()> {
+        let mut start = "0-0".to_string();
+        loop {
+            let reply: Value = redis::cmd("XAUTOCLAIM")
+                .arg(&self.key)
+                .arg(&self.config.group)
+                .arg(&self.config.consumer)
+                .arg(idle.as_millis() as u64)
+                .arg(&start)
+                .arg("COUNT")
+                .arg(self.config.batch_size)
+                .query_async(con)
+                .await?;
+            let (next, entries) = autoclaim_reply(&reply)?;
+            for entry in entries {
+                self.handle(con, entry).await?;
+            }
+            if next == "0-0" {
+                return Ok(());
+            }
+            start = next;
+        }
+    }
+
+    /// Sends an entry to the actor, and acknowledges it if the actor handled it.
+    /// Returns an error only if redis fails
+    async fn handle(&self, con: &mut Connection, entry: StreamId) -> RedisResult<()> {
+        match sub_message(&self.stream, &entry) {
+            Some(msg) => {
+                debug!(actor_id = %self.ld.actor_id, stream = %self.stream, id = %entry.id, "stream entry");
+                let actor = MessageSubscriberSender::for_actor(&self.ld);
+                if let Err(e) = actor.handle_message(&Context::default(), &msg).await {
+                    // left pending, so it is delivered again
+                    error!(actor_id = %self.ld.actor_id, stream = %self.stream, id = %entry.id, error = %e, "unable to send stream entry to actor");
+                    return Ok(());
+                }
+            }
+            // deleted while pending, or not added by a provider
+            None => {
+                warn!(actor_id = %self.ld.actor_id, stream = %self.stream, id = %entry.id, "skipping stream entry without a body")
+            }
+        }
+        redis::Cmd::xack(&self.key, &self.config.group, &[&entry.id])
+            .query_async(con)
+            .await
+    }
+}
+
+/// Converts a stream entry into the message sent to the actor.
+/// Returns None if the entry has no body
+fn sub_message(stream: &str, entry: &StreamId) -> Option<SubMessage> {
+    Some(SubMessage {
+        subject: stream.to_string(),
+        body: entry.get(BODY_FIELD)?,
+        reply_to: entry.get(REPLY_TO_FIELD),
+    })
+}
+
+/// Parses the reply to XAUTOCLAIM: the ID to start the next call from ("0-0" when done),
+/// and the claimed entries. Replies from Redis 7 also list deleted entries, which are ignored
+fn autoclaim_reply(reply: &Value) -> RedisResult<(String, Vec<StreamId>)> {
+    let items: Vec<Value> = from_redis_value(reply)?;
+    match items.as_slice() {
+        [next, entries, ..] => {
+            let entries: StreamRangeReply = from_redis_value(entries)?;
+            Ok((from_redis_value(next)?, entries.ids))
+        }
+        _ => Err((redis::ErrorKind::TypeError, "invalid reply to XAUTOCLAIM").into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn data(s: &str) -> Value {
+        Value::Data(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn config_from_link() {
+        assert_eq!(
+            ConsumerConfig::from_link(&values(&[]), "Mactor", "Nhost").unwrap(),
+            None
+        );
+
+        let config = ConsumerConfig::from_link(
+            &values(&[("subscriptions", "orders, payments,")]),
+            "Mactor",
+            "Nhost",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(config.streams, vec!["orders", "payments"]);
+        assert_eq!(config.group, "Mactor");
+        assert_eq!(config.consumer, "Nhost");
+        assert_eq!(config.start_id, "$");
+        assert_eq!(config.batch_size, DEFAULT_BATCH_SIZE);
+        assert_eq!(config.claim_idle, Some(Duration::from_secs(60)));
+
+        let config = ConsumerConfig::from_link(
+            &values(&[
+                ("SUBSCRIPTIONS", "orders"),
+                ("CONSUMER_GROUP", "billing"),
+                ("START_ID", "0"),
+                ("BATCH_SIZE", "100"),
+                ("CLAIM_IDLE_SECS", "0"),
+            ]),
+            "Mactor",
+            "Nhost",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(config.group, "billing");
+        assert_eq!(config.start_id, "0");
+        assert_eq!(config.batch_size, 100);
+        assert_eq!(config.claim_idle, None);
+
+        assert!(ConsumerConfig::from_link(
+            &values(&[("SUBSCRIPTIONS", "orders"), ("BATCH_SIZE", "0")]),
+            "Mactor",
+            "Nhost"
+        )
+        .is_err());
+        assert!(ConsumerConfig::from_link(
+            &values(&[("SUBSCRIPTIONS", "orders"), ("CLAIM_IDLE_SECS", "soon")]),
+            "Mactor",
+            "Nhost"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn convert_entries() {
+        let entry = StreamId {
+            id: "1700000000000-0".to_string(),
+            map: HashMap::from([
+                (BODY_FIELD.to_string(), data("hello")),
+                (REPLY_TO_FIELD.to_string(), data("replies")),
+            ]),
+        };
+        let msg = sub_message("orders", &entry).unwrap();
+        assert_eq!(msg.subject, "orders");
+        assert_eq!(msg.body, b"hello");
+        assert_eq!(msg.reply_to.as_deref(), Some("replies"));
+
+        // deleted entries have no fields
+        let deleted = StreamId {
+            id: "1700000000000-1".to_string(),
+            map: HashMap::new(),
+        };
+        assert!(sub_message("orders", &deleted).is_none());
+    }
+
+    #[test]
+    fn parse_autoclaim_reply() {
+        let reply = Value::Bulk(vec![
+            data("1700000000005-0"),
+            Value::Bulk(vec![Value::Bulk(vec![
+                data("1700000000001-0"),
+                Value::Bulk(vec![data(BODY_FIELD), data("hello")]),
+            ])]),
+            // deleted entries (Redis 7)
+            Value::Bulk(vec![data("1700000000002-0")]),
+        ]);
+        let (next, entries) = autoclaim_reply(&reply).unwrap();
+        assert_eq!(next, "1700000000005-0");
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].id, "1700000000001-0");
+        assert_eq!(entries[0].get::<Vec<u8>>(BODY_FIELD).unwrap(), b"hello");
+
+        assert!(autoclaim_reply(&Value::Int(0)).is_err());
+    }
+}
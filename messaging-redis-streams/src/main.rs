@@ -0,0 +1,284 @@
+//! Redis Streams implementation for wasmcloud:messaging.
+//!
+//! Actors publish messages with XADD, to the stream named by the message subject.
+//! Messages from the streams in a link's `SUBSCRIPTIONS` are read with XREADGROUP in a
+//! consumer group, and acknowledged after the actor handles them, so messages are not
+//! lost while an actor or the provider is down. See the [consumer] module.
+//!
+//! Connections, TLS, authentication, and key prefixes are configured with the same link
+//! values and provider config as the kvredis provider, and are managed by its
+//! [link](wasmcloud_provider_kvredis::link) module.
+//!
+use std::{collections::HashMap, convert::Infallible, sync::Arc};
+
+use redis::streams::StreamMaxlen;
+use tokio::{sync::RwLock, task::JoinHandle};
+use tracing::{info, instrument};
+use wasmbus_rpc::{
+    core::{HealthCheckRequest, HealthCheckResponse},
+    provider::prelude::*,
+};
+use wasmcloud_interface_messaging::{
+    Messaging, MessagingReceiver, PubMessage, ReplyMessage, RequestMessage,
+};
+
+use wasmcloud_provider_kvredis::{
+    get_pool_size, get_redis_url,
+    link::{get_key_prefix, ActorLink, LinkHealth},
+    options::ConnectionConfig,
+    pool::{Topology, DEFAULT_POOL_SIZE},
+    KvRedisConfig,
+};
+
+mod consumer;
+use consumer::ConsumerConfig;
+
+/// Link value with the approximate maximum length of streams published to.
+/// Older entries are trimmed when a message is added
+const MAX_LEN_KEY: &str = "MAX_LEN";
+
+/// Field of a stream entry with the message body
+pub(crate) const BODY_FIELD: &str = "body";
+/// Field of a stream entry with the message's reply-to subject, if it has one
+pub(crate) const REPLY_TO_FIELD: &str = "reply_to";
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let hd = load_host_data()?;
+
+    let config = if let Some(raw_config) = hd.config_json.as_ref() {
+        // fail rather than connect without the configured TLS and credentials
+        serde_json::from_str(raw_config)
+            .map_err(|e| format!("invalid provider configuration: {}", e))?
+    } else {
+        KvRedisConfig::default()
+    };
+    let provider = RedisStreamsProvider::new(config, &hd.host_id);
+
+    provider_start(
+        provider,
+        hd,
+        Some("Redis Streams Messaging Provider".to_string()),
+    )?;
+
+    eprintln!("Redis Streams provider exiting");
+    Ok(())
+}
+
+/// A link's connections, and the tasks consuming its subscriptions
+struct StreamsLink {
+    link: ActorLink,
+    max_len: Option<usize>,
+    consumers: Vec<JoinHandle<()>>,
+}
+
+impl Drop for StreamsLink {
+    fn drop(&mut self) {
+        for task in self.consumers.iter() {
+            task.abort();
+        }
+    }
+}
+
+/// Redis Streams messaging provider implementation.
+#[derive(Default, Clone, Provider)]
+#[services(Messaging)]
+struct RedisStreamsProvider {
+    // store redis connections and consumers per actor
+    actors: Arc<RwLock<HashMap<String, Arc<StreamsLink>>>>,
+    // Default connection URL for actors without a `URL` link value
+    default_connect_url: String,
+    // Default number of connections for actors without a `POOL_SIZE` link value
+    default_pool_size: usize,
+    // Default TLS and authentication settings
+    default_connection: ConnectionConfig,
+    // default consumer name
+    host_id: String,
+}
+
+impl RedisStreamsProvider {
+    fn new(config: KvRedisConfig, host_id: &str) -> Self {
+        RedisStreamsProvider {
+            default_connect_url: config.url,
+            default_pool_size: config.pool_size.unwrap_or(DEFAULT_POOL_SIZE),
+            default_connection: config.connection,
+            host_id: host_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the link of the actor making the request
+    async fn link(&self, ctx: &Context) -> RpcResult<Arc<StreamsLink>> {
+        let actor_id = ctx
+            .actor
+            .as_ref()
+            .ok_or_else(|| RpcError::InvalidParameter("no actor in request".to_string()))?;
+        let rd = self.actors.read().await;
+        rd.get(actor_id)
+            .cloned()
+            .ok_or_else(|| RpcError::InvalidParameter(format!("actor not linked:{}", actor_id)))
+    }
+}
+
+/// use default implementations of provider message handlers
+impl ProviderDispatch for RedisStreamsProvider {}
+
+/// Handle provider control commands
+/// put_link (new actor link command), del_link (remove link command), and shutdown
+#[async_trait]
+impl ProviderHandler for RedisStreamsProvider {
+    /// Checks the link values, and starts consuming the link's subscriptions
+    #[instrument(level = "debug", skip(self, ld), fields(actor_id = %ld.actor_id))]
+    async fn put_link(&self, ld: &LinkDefinition) -> RpcResult<bool> {
+        let redis_url = get_redis_url(&ld.values, &self.default_connect_url);
+        let topology = Topology::from_link(&ld.values, redis_url)?;
+        topology.validate()?;
+        let options = self
+            .default_connection
+            .with_link_values(&ld.values)?
+            .load(&topology)?;
+        let pool_size = get_pool_size(&ld.values, self.default_pool_size)?;
+        let key_prefix = get_key_prefix(&ld.values);
+        let max_len = get_max_len(&ld.values)?;
+        let subscriptions = ConsumerConfig::from_link(&ld.values, &ld.actor_id, &self.host_id)?;
+
+        let consumers = match subscriptions {
+            Some(config) => consumer::spawn(ld, &topology, &options, &key_prefix, config),
+            None => Vec::new(),
+        };
+        let link = Arc::new(StreamsLink {
+            link: ActorLink::new(&ld.actor_id, topology, options, pool_size, key_prefix),
+            max_len,
+            consumers,
+        });
+        let mut update_map = self.actors.write().await;
+        update_map.insert(ld.actor_id.to_string(), link.clone());
+
+        // start connecting now, so the connection is likely to be ready for the first publish.
+        // If it fails, the error is logged, reported by health checks, and returned to the actor
+        tokio::spawn(async move {
+            let _ = link.link.connect().await;
+        });
+
+        Ok(true)
+    }
+
+    /// Reports the connection state of all links. The provider is healthy if
+    /// no link has failed to connect
+    async fn health_request(&self, _arg: &HealthCheckRequest) -> RpcResult<HealthCheckResponse> {
+        let rd = self.actors.read().await;
+        let mut connecting = 0;
+        let mut failed = Vec::new();
+        for (actor_id, link) in rd.iter() {
+            match link.link.health() {
+                LinkHealth::Connected => {}
+                LinkHealth::Connecting => connecting += 1,
+                LinkHealth::Failed(error) => failed.push(format!("{}: {}", actor_id, error)),
+            }
+        }
+        let message = format!(
+            "links={} connecting={} failed={}",
+            rd.len(),
+            connecting,
+            failed.len()
+        );
+        Ok(HealthCheckResponse {
+            healthy: failed.is_empty(),
+            message: Some(if failed.is_empty() {
+                message
+            } else {
+                format!("{} ({})", message, failed.join("; "))
+            }),
+        })
+    }
+
+    /// Handle notification that a link is dropped - stop consuming and close the connections.
+    /// Unacknowledged messages stay pending in the consumer group
+    #[instrument(level = "info", skip(self))]
+    async fn delete_link(&self, actor_id: &str) {
+        let mut aw = self.actors.write().await;
+        if let Some(link) = aw.remove(actor_id) {
+            info!("redis closing connection for actor {}", actor_id);
+            drop(link)
+        }
+    }
+
+    /// Handle shutdown request by closing all connections
+    async fn shutdown(&self) -> Result<(), Infallible> {
+        let mut aw = self.actors.write().await;
+        // empty the actor link data and stop all consumers
+        for (_, link) in aw.drain() {
+            drop(link)
+        }
+        Ok(())
+    }
+}
+
+/// Handle Messaging methods that interact with redis
+#[async_trait]
+impl Messaging for RedisStreamsProvider {
+    /// Adds the message to the stream named by the subject
+    #[instrument(level = "debug", skip(self, ctx, msg), fields(actor_id = ?ctx.actor, subject = %msg.subject, reply_to = ?msg.reply_to, body_len = %msg.body.len()))]
+    async fn publish(&self, ctx: &Context, msg: &PubMessage) -> RpcResult<()> {
+        if msg.subject.is_empty() {
+            return Err(RpcError::InvalidParameter(
+                "subject must not be empty".to_string(),
+            ));
+        }
+        let link = self.link(ctx).await?;
+        let mut fields: Vec<(&str, &[u8])> = vec![(BODY_FIELD, &msg.body)];
+        if let Some(reply_to) = &msg.reply_to {
+            fields.push((REPLY_TO_FIELD, reply_to.as_bytes()));
+        }
+        let key = link.link.key(&msg.subject);
+        let cmd = match link.max_len {
+            Some(max_len) => {
+                redis::Cmd::xadd_maxlen(key, StreamMaxlen::Approx(max_len), "*", &fields)
+            }
+            None => redis::Cmd::xadd(key, "*", &fields),
+        };
+        let _id: String = link.link.exec(&cmd).await?;
+        Ok(())
+    }
+
+    /// Streams have no replies, so requests are not supported
+    #[instrument(level = "debug", skip(self, _ctx, msg), fields(subject = %msg.subject))]
+    async fn request(&self, _ctx: &Context, msg: &RequestMessage) -> RpcResult<ReplyMessage> {
+        Err(RpcError::NotImplemented)
+    }
+}
+
+/// Returns the `MAX_LEN` link value (case-insensitive), if the link has one
+fn get_max_len(link_values: &HashMap<String, String>) -> RpcResult<Option<usize>> {
+    match link_values
+        .iter()
+        .find(|(key, _value)| key.eq_ignore_ascii_case(MAX_LEN_KEY))
+    {
+        Some((_key, value)) => match value.trim().parse::<usize>() {
+            Ok(max_len) if max_len > 0 => Ok(Some(max_len)),
+            _ => Err(RpcError::InvalidParameter(format!(
+                "invalid {} '{}'",
+                MAX_LEN_KEY, value
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::get_max_len;
+
+    #[test]
+    fn can_read_max_len() {
+        let mut values = HashMap::new();
+        assert_eq!(get_max_len(&values).unwrap(), None);
+
+        values.insert("max_len".to_string(), "10000".to_string());
+        assert_eq!(get_max_len(&values).unwrap(), Some(10000));
+
+        values.insert("max_len".to_string(), "0".to_string());
+        assert!(get_max_len(&values).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+use std::{collections::HashMap, time::Duration};
+
+use futures::StreamExt;
+use redis::streams::{StreamPendingReply, StreamRangeReply};
+use wasmbus_rpc::{
+    async_nats::Subscriber,
+    common::{deserialize, serialize},
+    core::{Invocation, InvocationResponse},
+    error::{RpcError, RpcResult},
+    provider::prelude::Context,
+};
+use wasmcloud_interface_messaging::*;
+use wasmcloud_test_util::{
+    check, check_eq,
+    cli::print_test_results,
+    provider_test::{test_provider, Provider},
+    run_selected_spawn,
+    testing::TestOptions,
+};
+
+/// redis server used by the provider's default link
+const REDIS_URL: &str = "redis://127.0.0.1:6379/";
+
+/// how long to wait for the provider to send a stream entry to the test actor
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[tokio::test]
+async fn run_all() {
+    let opts = TestOptions::default();
+    let res = run_selected_spawn!(
+        opts,
+        health_check,
+        publish,
+        request_not_supported,
+        consumer_group
+    );
+    print_test_results(&res);
+
+    let passed = res.iter().filter(|tr| tr.passed).count();
+    let total = res.len();
+    assert_eq!(passed, total, "{} passed out of {}", passed, total);
+
+    // try to let the provider shut down gracefully
+    let provider = test_provider().await;
+    let _ = provider.shutdown().await;
+}
+
+/// returns a new stream name with the given prefix
+/// The purpose is to make sure different tests don't collide with each other
+fn new_stream(prefix: &str) -> String {
+    format!("{}_{:x}", prefix, rand::random::<u32>())
+}
+
+fn to_rpc_err(e: redis::RedisError) -> RpcError {
+    RpcError::Other(format!("redis error: {}", e))
+}
+
+/// opens a connection directly to redis
+async fn redis_connection() -> RpcResult<redis::aio::MultiplexedConnection> {
+    let client = redis::Client::open(REDIS_URL).map_err(to_rpc_err)?;
+    client
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(to_rpc_err)
+}
+
+/// reads all entries of a stream directly from redis, and deletes the stream
+async fn read_stream(stream: &str) -> RpcResult<StreamRangeReply> {
+    let mut con = redis_connection().await?;
+    let entries = redis::Cmd::xrange_all(stream)
+        .query_async(&mut con)
+        .await
+        .map_err(to_rpc_err)?;
+    let _: u32 = redis::Cmd::del(stream)
+        .query_async(&mut con)
+        .await
+        .map_err(to_rpc_err)?;
+    Ok(entries)
+}
+
+/// replaces the test link with one that has these values
+async fn relink(prov: &Provider, values: &[(&str, &str)]) -> RpcResult<()> {
+    let values: HashMap<String, String> = values
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    prov.link_to_test(values)
+        .await
+        .map_err(|e| RpcError::Other(format!("unable to link: {}", e)))?;
+    // the link is sent without waiting for the provider
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    Ok(())
+}
+
+/// A message sent by the provider to the test actor
+struct Delivery {
+    message: SubMessage,
+    invocation_id: String,
+    reply: String,
+}
+
+/// waits for the provider to send the next message to the test actor
+async fn receive(sub: &mut Subscriber) -> RpcResult<Delivery> {
+    let msg = tokio::time::timeout(DELIVERY_TIMEOUT, sub.next())
+        .await
+        .map_err(|_| RpcError::Timeout("no message sent to the actor".to_string()))?
+        .ok_or_else(|| RpcError::Other("rpc subscription closed".to_string()))?;
+    let inv: Invocation = deserialize(&msg.payload)?;
+    check_eq!(inv.operation.as_str(), "MessageSubscriber.HandleMessage")?;
+    Ok(Delivery {
+        message: deserialize(&inv.msg)?,
+        invocation_id: inv.id,
+        reply: msg
+            .reply
+            .ok_or_else(|| RpcError::Other("invocation has no reply subject".to_string()))?,
+    })
+}
+
+/// replies to a message as the test actor, with an error if `error` is set
+async fn respond(prov: &Provider, delivery: Delivery, error: Option<&str>) -> RpcResult<()> {
+    // InvocationResponse is non-exhaustive
+    let mut resp = InvocationResponse::default();
+    resp.invocation_id = delivery.invocation_id;
+    resp.error = error.map(|e| e.to_string());
+    prov.nats_client
+        .publish(delivery.reply, serialize(&resp)?.into())
+        .await
+        .map_err(|e| RpcError::Nats(e.to_string()))
+}
+
+/// test that health check returns healthy
+async fn health_check(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+
+    // health check
+    let hc = prov.health_check().await;
+    check!(hc.is_ok())?;
+    Ok(())
+}
+
+/// test that published messages are added to the stream
+async fn publish(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+    let client = MessagingSender::via(prov);
+    let ctx = Context::default();
+    let stream = new_stream("pub");
+
+    client
+        .publish(
+            &ctx,
+            &PubMessage {
+                subject: stream.clone(),
+                body: b"hello".to_vec(),
+                reply_to: None,
+            },
+        )
+        .await?;
+    client
+        .publish(
+            &ctx,
+            &PubMessage {
+                subject: stream.clone(),
+                body: b"world".to_vec(),
+                reply_to: Some("replies".to_string()),
+            },
+        )
+        .await?;
+
+    let entries = read_stream(&stream).await?.ids;
+    check_eq!(entries.len(), 2)?;
+    check_eq!(entries[0].get::<Vec<u8>>("body"), Some(b"hello".to_vec()))?;
+    check!(!entries[0].contains_key("reply_to"))?;
+    check_eq!(entries[1].get::<Vec<u8>>("body"), Some(b"world".to_vec()))?;
+    check_eq!(
+        entries[1].get::<String>("reply_to").as_deref(),
+        Some("replies")
+    )?;
+
+    // a subject is required
+    let empty = client
+        .publish(
+            &ctx,
+            &PubMessage {
+                subject: String::new(),
+                body: b"hello".to_vec(),
+                reply_to: None,
+            },
+        )
+        .await;
+    check!(empty.is_err())?;
+
+    Ok(())
+}
+
+/// test that requests are rejected, since streams have no replies
+async fn request_not_supported(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+    let client = MessagingSender::via(prov);
+    let ctx = Context::default();
+
+    let resp = client
+        .request(
+            &ctx,
+            &RequestMessage {
+                subject: new_stream("req"),
+                body: b"hello".to_vec(),
+                timeout_ms: 1000,
+            },
+        )
+        .await;
+    check!(resp.is_err())?;
+    Ok(())
+}
+
+/// test that a subscribed stream is read in a consumer group: entries are sent to the
+/// actor, acknowledged when the actor handles them, and claimed and sent again when
+/// the actor returns an error
+async fn consumer_group(_opt: &TestOptions) -> RpcResult<()> {
+    let prov = test_provider().await;
+    let client = MessagingSender::via(prov.clone());
+    let ctx = Context::default();
+    let stream = new_stream("sub");
+
+    let mut sub = prov.subscribe_rpc().await?;
+    relink(
+        &prov,
+        &[
+            ("SUBSCRIPTIONS", &stream),
+            ("START_ID", "0"),
+            ("CLAIM_IDLE_SECS", "1"),
+        ],
+    )
+    .await?;
+
+    for body in ["first", "second"] {
+        client
+            .publish(
+                &ctx,
+                &PubMessage {
+                    subject: stream.clone(),
+                    body: body.as_bytes().to_vec(),
+                    reply_to: None,
+                },
+            )
+            .await?;
+    }
+
+    // the actor fails to handle the first entry, so it stays pending
+    let first = receive(&mut sub).await?;
+    check_eq!(first.message.subject.as_str(), stream.as_str())?;
+    check_eq!(first.message.body.as_slice(), b"first")?;
+    respond(&prov, first, Some("not ready")).await?;
+
+    let second = receive(&mut sub).await?;
+    check_eq!(second.message.body.as_slice(), b"second")?;
+    respond(&prov, second, None).await?;
+
+    // the first entry is claimed after it has been idle, and sent again
+    let again = receive(&mut sub).await?;
+    check_eq!(again.message.body.as_slice(), b"first")?;
+    respond(&prov, again, None).await?;
+
+    // both entries are acknowledged
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let mut con = redis_connection().await?;
+    let pending: StreamPendingReply = redis::Cmd::xpending(&stream, &prov.actor_id)
+        .query_async(&mut con)
+        .await
+        .map_err(to_rpc_err)?;
+    check_eq!(pending.count(), 0)?;
+
+    // clean up
+    relink(&prov, &[]).await?;
+    let _ = sub.unsubscribe().await;
+    read_stream(&stream).await?;
+    Ok(())
+}